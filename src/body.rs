@@ -1,37 +1,231 @@
-use std::ffi::OsStr;
 use std::fs::Metadata;
-use std::path::PathBuf;
+use std::io::{Cursor, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 use bytes::{Bytes, BytesMut};
 use tokio::fs::File;
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncSeek, AsyncSeekExt as _, ReadBuf};
+use tokio::sync::mpsc;
 
 use crate::header::ContentLength;
 
+/// Number of chunks read ahead from a reader (see [`Body::reader`])
+const READER_CAPACITY: usize = 4;
+
+/// Maximum size of chunks read from a reader (see [`Body::reader`])
+const READER_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Contents of a file, which are either read from the file system or already held in memory
+#[derive(Debug)]
+enum Source {
+    File(File),
+    /// Whole contents of a file (e.g., from a cache) along with the current position
+    Memory(Cursor<Bytes>),
+}
+
+impl AsyncRead for Source {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::File(file) => Pin::new(file).poll_read(cx, buf),
+            Self::Memory(contents) => Pin::new(contents).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncSeek for Source {
+    #[inline]
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        match self.get_mut() {
+            Self::File(file) => Pin::new(file).start_seek(position),
+            Self::Memory(contents) => Pin::new(contents).start_seek(position),
+        }
+    }
+
+    #[inline]
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        match self.get_mut() {
+            Self::File(file) => Pin::new(file).poll_complete(cx),
+            Self::Memory(contents) => Pin::new(contents).poll_complete(cx),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct FileBody {
-    path: PathBuf,
-    file: File,
+    file: Source,
     meta: Metadata,
+    /// Number of bytes to serve from the current position in the file
+    len: u64,
+    /// The file is already encoded by the response's `Content-Encoding`
+    precompressed: bool,
 }
 
 impl FileBody {
+    /// Body of a file with given metadata whose whole `contents` are already in memory
+    #[inline]
+    pub(crate) fn in_memory(contents: Bytes, meta: Metadata) -> Self {
+        Self {
+            len: contents.len() as u64,
+            file: Source::Memory(Cursor::new(contents)),
+            meta,
+            precompressed: false,
+        }
+    }
+
+    /// Returns `true` iff the contents are held in memory (see [`Self::in_memory`])
+    #[inline]
+    pub(crate) fn is_in_memory(&self) -> bool {
+        matches!(self.file, Source::Memory(_))
+    }
+
     // NOTE: files are already buffered
     #[inline]
     pub fn into_reader(self) -> impl AsyncRead + Unpin {
-        self.file
+        self.file.take(self.len)
+    }
+
+    #[inline]
+    pub(crate) fn metadata(&self) -> &Metadata {
+        &self.meta
+    }
+
+    /// Total size of the file (regardless of the served range)
+    #[inline]
+    pub(crate) fn file_len(&self) -> u64 {
+        self.meta.len()
+    }
+
+    /// Returns `true` iff the body is just a part of the file (see [`Self::slice`])
+    #[inline]
+    pub(crate) fn is_partial(&self) -> bool {
+        self.len != self.meta.len()
+    }
+
+    /// Reader of the (remaining) served part of the file
+    #[inline]
+    pub(crate) fn reader(&mut self) -> impl AsyncRead + Unpin + '_ {
+        (&mut self.file).take(self.len)
+    }
+
+    /// Restrict the body to bytes `start..=end` of the file
+    pub(crate) async fn slice(&mut self, start: u64, end: u64) -> std::io::Result<()> {
+        debug_assert!(start <= end && end < self.meta.len(), "invalid file range");
+        self.file.seek(SeekFrom::Start(start)).await?;
+        self.len = end - start + 1;
+        Ok(())
+    }
+
+    /// Serve the whole file again from its start (i.e., undo reading and [`Self::slice`])
+    pub(crate) async fn rewind(&mut self) -> std::io::Result<()> {
+        self.file.rewind().await?;
+        self.len = self.meta.len();
+        Ok(())
+    }
+
+    /// Duplicate the file handle, note that the clone shares the file offset with the original
+    /// (unless the contents are in memory)
+    pub(crate) async fn try_clone(&self) -> std::io::Result<Self> {
+        let file = match &self.file {
+            Source::File(file) => Source::File(file.try_clone().await?),
+            Source::Memory(contents) => Source::Memory(contents.clone()),
+        };
+
+        Ok(Self {
+            file,
+            meta: self.meta.clone(),
+            len: self.len,
+            precompressed: self.precompressed,
+        })
+    }
+
+    /// Returns `true` iff the file is already compressed (see [`Self::set_precompressed`])
+    #[inline]
+    pub(crate) fn is_precompressed(&self) -> bool {
+        self.precompressed
     }
 
+    /// Mark the file as already encoded by the response's `Content-Encoding`, so that it's sent
+    /// as it is
     #[inline]
-    pub(crate) fn as_path(&self) -> &OsStr {
-        self.path.as_os_str()
+    pub(crate) fn set_precompressed(&mut self) {
+        self.precompressed = true;
+    }
+
+    /// Read up to `len` bytes from the start of the file without consuming them
+    pub(crate) async fn peek(&mut self, len: usize) -> std::io::Result<Bytes> {
+        let mut buf = BytesMut::zeroed(len.min(self.meta.len() as usize));
+
+        let mut n = 0;
+        while n < buf.len() {
+            match self.file.read(&mut buf[n..]).await? {
+                0 => break,
+                read => n += read,
+            }
+        }
+        buf.truncate(n);
+
+        self.file.seek(SeekFrom::Start(0)).await?;
+
+        Ok(buf.freeze())
+    }
+}
+
+/// Body which is produced incrementally (e.g., by a background task), usually of unknown length
+#[derive(Debug)]
+pub struct StreamBody {
+    rx: mpsc::Receiver<std::io::Result<Bytes>>,
+    /// Length of the whole body if it's known in advance
+    len: Option<u64>,
+}
+
+impl StreamBody {
+    /// Wait for next chunk of the body, returns `None` once the whole body has been produced.
+    ///
+    /// Fails if the producer could not finish the body (e.g., the client disconnected in the
+    /// middle of sending a request body).
+    #[inline]
+    pub async fn next(&mut self) -> Option<std::io::Result<Bytes>> {
+        self.rx.recv().await
     }
 }
 
+/// Producing half of a [`StreamBody`]
+#[derive(Clone, Debug)]
+#[repr(transparent)]
+pub struct BodySender(mpsc::Sender<std::io::Result<Bytes>>);
+
+impl BodySender {
+    /// Send next chunk of the body.
+    ///
+    /// Fails if the body has been dropped (e.g., because the client disconnected).
+    #[inline]
+    pub async fn send(&self, chunk: impl Into<Bytes>) -> Result<(), BodyClosed> {
+        self.0.send(Ok(chunk.into())).await.map_err(|_| BodyClosed)
+    }
+
+    /// Terminate the body with an error instead of a regular end
+    #[inline]
+    pub async fn abort(&self, error: std::io::Error) -> Result<(), BodyClosed> {
+        self.0.send(Err(error)).await.map_err(|_| BodyClosed)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("body receiver has been dropped")]
+pub struct BodyClosed;
+
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Body {
     Bytes(Bytes),
     File(FileBody),
+    Stream(StreamBody),
 }
 
 impl Body {
@@ -45,9 +239,49 @@ impl Body {
         Self::Bytes(bytes.into())
     }
 
-    pub async fn file(path: PathBuf, file: File) -> std::io::Result<Self> {
+    pub async fn file(file: File) -> std::io::Result<Self> {
         let meta = file.metadata().await?;
-        Ok(Self::from(FileBody { path, file, meta }))
+        let len = meta.len();
+        Ok(Self::from(FileBody {
+            file: Source::File(file),
+            meta,
+            len,
+            precompressed: false,
+        }))
+    }
+
+    /// Create a streamed body along with a sender of its chunks.
+    ///
+    /// The body ends once all the senders are dropped. At most `capacity` chunks are buffered.
+    pub fn channel(capacity: usize) -> (BodySender, Self) {
+        let (tx, rx) = mpsc::channel(capacity);
+        (BodySender(tx), Self::Stream(StreamBody { rx, len: None }))
+    }
+
+    /// Stream the body from given `reader` (e.g., a pipe or a socket) in a background task.
+    ///
+    /// If the length of the body is given, it's sent with that `Content-Length` (rather than
+    /// chunked) and reading stops after `len` bytes. The body then fails if the reader ends
+    /// before producing all of them.
+    pub fn reader<R>(reader: R, len: Option<u64>) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let (tx, rx) = mpsc::channel(READER_CAPACITY);
+        tokio::spawn(forward(reader, len, tx));
+        Self::Stream(StreamBody { rx, len })
+    }
+
+    #[inline]
+    pub fn is_stream(&self) -> bool {
+        matches!(self, Self::Stream(_))
+    }
+
+    /// Returns `true` iff the length of the body is known in advance (i.e., it's not a stream of
+    /// unknown length, which is sent chunked)
+    #[inline]
+    pub fn is_sized(&self) -> bool {
+        !matches!(self, Self::Stream(StreamBody { len: None, .. }))
     }
 
     #[inline]
@@ -55,11 +289,32 @@ impl Body {
         self.len() == 0
     }
 
+    /// Length of the body in bytes (note that it's `0` for streamed bodies of unknown length)
     #[inline]
     pub fn len(&self) -> u64 {
         match self {
             Body::Bytes(bytes) => bytes.len() as u64,
-            Body::File(file) => file.meta.len(),
+            Body::File(file) => file.len,
+            Body::Stream(stream) => stream.len.unwrap_or_default(),
+        }
+    }
+
+    /// Materialize the whole body in memory
+    pub async fn into_bytes(self) -> std::io::Result<Bytes> {
+        match self {
+            Body::Bytes(bytes) => Ok(bytes),
+            Body::File(file) => {
+                let mut buf = Vec::with_capacity(file.len as usize);
+                file.into_reader().read_to_end(&mut buf).await?;
+                Ok(buf.into())
+            }
+            Body::Stream(mut stream) => {
+                let mut buf = BytesMut::new();
+                while let Some(chunk) = stream.next().await {
+                    buf.extend_from_slice(&chunk?);
+                }
+                Ok(buf.freeze())
+            }
         }
     }
 
@@ -83,6 +338,13 @@ impl From<BytesMut> for Body {
     }
 }
 
+impl From<String> for Body {
+    #[inline]
+    fn from(string: String) -> Self {
+        Self::Bytes(string.into())
+    }
+}
+
 impl From<&[u8]> for Body {
     #[inline]
     fn from(bytes: &[u8]) -> Self {
@@ -100,3 +362,38 @@ impl From<FileBody> for Body {
         Self::File(file)
     }
 }
+
+/// Forward chunks read from `reader` (up to `len` bytes if given) to a streamed body
+async fn forward<R>(reader: R, len: Option<u64>, tx: mpsc::Sender<std::io::Result<Bytes>>)
+where
+    R: AsyncRead + Send + Unpin,
+{
+    let mut reader = reader.take(len.unwrap_or(u64::MAX));
+    let mut read = 0;
+
+    loop {
+        let mut buf = BytesMut::with_capacity(READER_CHUNK_SIZE);
+
+        let chunk = match reader.read_buf(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => {
+                read += n as u64;
+                Ok(buf.freeze())
+            }
+            Err(e) => Err(e),
+        };
+
+        let failed = chunk.is_err();
+        if tx.send(chunk).await.is_err() || failed {
+            return;
+        }
+    }
+
+    if len.is_some_and(|len| read < len) {
+        let error = std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("body ended after {read} bytes"),
+        );
+        let _ = tx.send(Err(error)).await;
+    }
+}
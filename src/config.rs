@@ -1,12 +1,18 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env::Args;
 use std::net::SocketAddr;
+use std::os::fd::RawFd;
 use std::path::{Path, PathBuf};
-use std::sync::OnceLock;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
 
 use anyhow::{bail, Context as _, Result};
 
 use crate::encoding::{self, Encoding};
+use crate::io::reader::{MAX_HEADERS, MAX_HEADER_SIZE, MAX_REQUEST_LINE};
+use crate::net::Cidr;
+use crate::router::TrailingSlash;
 
 fn listen_socket_addr(port: &impl std::fmt::Display) -> Result<SocketAddr> {
     format!("0.0.0.0:{port}")
@@ -14,21 +20,269 @@ fn listen_socket_addr(port: &impl std::fmt::Display) -> Result<SocketAddr> {
         .with_context(|| format!("failed to parse listen socket address: '0.0.0.0:{port}'"))
 }
 
+fn arg_value<T>(args: &mut impl Iterator<Item = String>, name: &str) -> Result<T>
+where
+    T: FromStr,
+{
+    let Some(value) = args.next() else {
+        bail!("missing argument value for {name}");
+    };
+
+    let Ok(value) = value.parse() else {
+        bail!("invalid argument value for {name}: '{value}'");
+    };
+
+    Ok(value)
+}
+
+fn compression_level(
+    args: &mut impl Iterator<Item = String>,
+    name: &str,
+    encoding: Encoding,
+) -> Result<u32> {
+    let level = arg_value(args, name)?;
+
+    match encoding.levels() {
+        Some(levels) if levels.contains(&level) => Ok(level),
+        Some(levels) => bail!(
+            "{name} must be between {} and {}: '{level}'",
+            levels.start(),
+            levels.end()
+        ),
+        None => bail!("{encoding} does not support compression levels"),
+    }
+}
+
+/// Address of the socket the server listens on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// Path to a Unix domain socket
+    Unix(PathBuf),
+    /// Already bound listening socket inherited from the parent process (e.g., systemd)
+    Inherited(RawFd),
+}
+
+/// File descriptor of the first socket passed by systemd (see `sd_listen_fds(3)`)
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Environment variables through which systemd passes sockets to the activated process
+pub(crate) const SD_LISTEN_VARS: [&str; 3] = ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"];
+
+/// Detect systemd socket activation based on `LISTEN_PID` and `LISTEN_FDS` environment variables.
+///
+/// Returns the file descriptor of the first passed socket if this process has been activated.
+fn listen_fds() -> Result<Option<RawFd>> {
+    let Ok(pid) = std::env::var("LISTEN_PID") else {
+        return Ok(None);
+    };
+
+    let pid = pid.parse::<u32>().context("invalid LISTEN_PID")?;
+
+    // sockets were meant for another process
+    if pid != std::process::id() {
+        return Ok(None);
+    }
+
+    let fds = std::env::var("LISTEN_FDS")
+        .context("missing LISTEN_FDS")?
+        .parse::<RawFd>()
+        .context("invalid LISTEN_FDS")?;
+
+    // NOTE: the variables are left set, since modifying the environment is unsound once other
+    //  threads might read it, and are instead removed for child processes (e.g., compression
+    //  programs), so that the sockets are not passed on to these
+    match fds {
+        0 => Ok(None),
+        1 => Ok(Some(SD_LISTEN_FDS_START)),
+        n => {
+            eprintln!("received {n} sockets via socket activation, using just the first one");
+            Ok(Some(SD_LISTEN_FDS_START))
+        }
+    }
+}
+
+impl From<SocketAddr> for ListenAddr {
+    #[inline]
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+impl FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    /// Parses either `unix:/path/to.sock` or a TCP socket address (e.g., `127.0.0.1:4221`)
+    fn from_str(addr: &str) -> Result<Self> {
+        match addr.strip_prefix("unix:") {
+            Some("") => bail!("missing Unix socket path"),
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None => addr
+                .parse()
+                .map(Self::Tcp)
+                .with_context(|| format!("failed to parse listen socket address: '{addr}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for ListenAddr {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::Inherited(fd) => write!(f, "fd:{fd}"),
+        }
+    }
+}
+
+/// Directory served (read-only) under a URL path prefix.
+///
+/// Parsed from `PREFIX=DIR[,OPTION...]` where the options are
+///  - `autoindex` to list directories (see [`Config::autoindex`])
+///  - `max-age=SECS` to let clients cache the files for given number of seconds
+///  - `auth=USER:PASSWORD` to require HTTP Basic authentication
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mount {
+    pub(crate) prefix: String,
+    pub(crate) dir: PathBuf,
+    pub(crate) autoindex: bool,
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) auth: Option<String>,
+}
+
+impl Mount {
+    #[inline]
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    #[inline]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    #[inline]
+    pub fn autoindex(&self) -> bool {
+        self.autoindex
+    }
+
+    /// How long clients may cache the files (sent as `Cache-Control: max-age`)
+    #[inline]
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Credentials (`user:password`) required to access the files
+    #[inline]
+    pub fn auth(&self) -> Option<&str> {
+        self.auth.as_deref()
+    }
+}
+
+impl FromStr for Mount {
+    type Err = anyhow::Error;
+
+    fn from_str(mount: &str) -> Result<Self> {
+        let mut options = mount.split(',');
+
+        let Some((prefix, dir)) = options.next().and_then(|mount| mount.split_once('=')) else {
+            bail!("mount must be given as PREFIX=DIR: '{mount}'");
+        };
+
+        if !prefix.starts_with('/') {
+            bail!("mount prefix must start with '/': '{prefix}'");
+        }
+
+        if dir.is_empty() {
+            bail!("missing mount directory for '{prefix}'");
+        }
+
+        let mut mount = Self {
+            prefix: prefix.to_string(),
+            dir: PathBuf::from(dir),
+            autoindex: false,
+            max_age: None,
+            auth: None,
+        };
+
+        for option in options {
+            match option.split_once('=') {
+                None if option == "autoindex" => mount.autoindex = true,
+                Some(("max-age", secs)) => {
+                    let secs = secs
+                        .parse()
+                        .with_context(|| format!("invalid mount max-age '{secs}'"))?;
+                    mount.max_age = Some(Duration::from_secs(secs));
+                }
+                Some(("auth", credentials)) if credentials.contains(':') => {
+                    mount.auth = Some(credentials.to_string());
+                }
+                _ => bail!("invalid mount option '{option}'"),
+            }
+        }
+
+        Ok(mount)
+    }
+}
+
 #[derive(Debug)]
 pub struct Config {
-    pub(crate) addr: SocketAddr,
+    pub(crate) addr: ListenAddr,
     pub(crate) dir: PathBuf,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) queue_timeout: Duration,
+    pub(crate) header_timeout: Duration,
+    pub(crate) body_timeout: Duration,
+    pub(crate) write_timeout: Duration,
+    pub(crate) idle_timeout: Duration,
+    pub(crate) max_requests_per_conn: Option<usize>,
+    pub(crate) trusted_proxies: Vec<Cidr>,
+    pub(crate) sniff_content_type: bool,
+    pub(crate) preserve_header_case: bool,
+    pub(crate) strict_parsing: bool,
+    pub(crate) trailing_slash: TrailingSlash,
+    pub(crate) autoindex: bool,
+    pub(crate) index_files: Vec<String>,
+    pub(crate) hidden_files: Vec<String>,
+    pub(crate) mounts: Vec<Mount>,
+    pub(crate) charset: Option<String>,
+    pub(crate) max_body_size: Option<u64>,
+    pub(crate) max_headers: usize,
+    pub(crate) max_header_size: usize,
+    pub(crate) max_request_line: usize,
+    pub(crate) upload_extensions: Vec<String>,
+    pub(crate) upload_types: Vec<String>,
+    pub(crate) compression_levels: HashMap<Encoding, u32>,
+    pub(crate) min_compress_size: u64,
+    pub(crate) compress_types: Vec<String>,
+    pub(crate) prespawned_encoders: usize,
+    pub(crate) compression_cache_size: usize,
+    pub(crate) file_cache_size: usize,
+    pub(crate) stat_cache_ttl: Duration,
+    pub(crate) compression_temp_file_size: Option<u64>,
+    pub(crate) encodings: Option<HashSet<Encoding>>,
+    pub(crate) disabled_encodings: HashSet<Encoding>,
 }
 
 impl Config {
-    #[inline]
+    /// Read the configuration from program arguments.
+    ///
+    /// If the process has been started via systemd socket activation, the inherited socket
+    /// takes precedence over any listen address given in the arguments.
     pub fn from_args() -> Result<Self> {
-        std::env::args().try_into()
+        let mut cfg = Self::try_from(std::env::args())?;
+
+        if let Some(fd) = listen_fds().context("socket activation")? {
+            cfg.addr = ListenAddr::Inherited(fd);
+        }
+
+        Ok(cfg)
     }
 
     #[inline]
-    pub fn listen_addr(&self) -> SocketAddr {
-        self.addr
+    pub fn listen_addr(&self) -> ListenAddr {
+        self.addr.clone()
     }
 
     #[inline]
@@ -36,8 +290,248 @@ impl Config {
         self.dir.as_path()
     }
 
+    /// Maximum number of concurrently served connections (unlimited if `None`)
+    #[inline]
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    /// How long an accepted connection may wait for a free slot when the connection limit is
+    /// reached before it's rejected with `503 Service Unavailable`
+    #[inline]
+    pub fn queue_timeout(&self) -> Duration {
+        self.queue_timeout
+    }
+
+    /// Deadline for receiving the request line and headers once a request starts arriving
+    #[inline]
+    pub fn header_timeout(&self) -> Duration {
+        self.header_timeout
+    }
+
+    /// Deadline for receiving the whole request body
+    #[inline]
+    pub fn body_timeout(&self) -> Duration {
+        self.body_timeout
+    }
+
+    /// Deadline for writing a response (including its compression)
+    #[inline]
+    pub fn write_timeout(&self) -> Duration {
+        self.write_timeout
+    }
+
+    /// How long a persistent connection may stay silent between requests before it's closed
+    #[inline]
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    /// Maximum size of a request body in bytes, larger requests are rejected with
+    /// `413 Content Too Large` (unlimited if `None`)
+    #[inline]
+    pub fn max_body_size(&self) -> Option<u64> {
+        self.max_body_size
+    }
+
+    /// Maximum number of header fields of a request, requests with more are rejected with
+    /// `431 Request Header Fields Too Large`
+    #[inline]
+    pub fn max_headers(&self) -> usize {
+        self.max_headers
+    }
+
+    /// Maximum total size in bytes of the header section of a request, larger ones are rejected
+    /// with `431 Request Header Fields Too Large`
+    #[inline]
+    pub fn max_header_size(&self) -> usize {
+        self.max_header_size
+    }
+
+    /// Maximum size in bytes of a request line, longer ones are rejected with `414 URI Too Long`
+    #[inline]
+    pub fn max_request_line(&self) -> usize {
+        self.max_request_line
+    }
+
+    /// Maximum number of requests served over a single connection (unlimited if `None`)
+    #[inline]
+    pub fn max_requests_per_conn(&self) -> Option<usize> {
+        self.max_requests_per_conn
+    }
+
+    /// Proxies whose `Forwarded` and `X-Forwarded-For` headers are used to resolve the client's
+    /// address (see [`crate::Request::client_ip`])
+    #[inline]
+    pub fn trusted_proxies(&self) -> &[Cidr] {
+        &self.trusted_proxies
+    }
+
+    /// Whether to detect the content type of served files from their contents
+    #[inline]
+    pub fn sniff_content_type(&self) -> bool {
+        self.sniff_content_type
+    }
+
+    /// Whether to reject requests with obsolete line folding or bare LF line endings, which are
+    /// otherwise tolerated
+    #[inline]
+    pub fn strict_parsing(&self) -> bool {
+        self.strict_parsing
+    }
+
+    /// Whether to send response header names exactly as handlers set them instead of in their
+    /// canonical `Title-Case`
+    #[inline]
+    pub fn preserve_header_case(&self) -> bool {
+        self.preserve_header_case
+    }
+
+    /// How request paths differing from routes just in a trailing `/` are handled (ignored by
+    /// default, so that e.g. `/user-agent/` is served as `/user-agent`)
+    #[inline]
+    pub fn trailing_slash(&self) -> TrailingSlash {
+        self.trailing_slash
+    }
+
+    /// Whether to list the contents of requested directories instead of responding with 404
+    #[inline]
+    pub fn autoindex(&self) -> bool {
+        self.autoindex
+    }
+
+    /// Names of files served in place of a requested directory (the first one which exists)
+    #[inline]
+    pub fn index_files(&self) -> &[String] {
+        &self.index_files
+    }
+
+    /// Glob patterns (e.g., `.*` or `*.bak`) of file names which are not served nor listed, as if
+    /// they did not exist. Patterns with a trailing slash (e.g., `.git/`) match just directories.
+    #[inline]
+    pub fn hidden_files(&self) -> &[String] {
+        &self.hidden_files
+    }
+
+    /// Additional directories served under URL path prefixes (besides [`Self::files_dir`])
+    #[inline]
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// File extensions (e.g., `txt` or `png`) of files which may be uploaded (any if empty)
+    #[inline]
+    pub fn upload_extensions(&self) -> &[String] {
+        &self.upload_extensions
+    }
+
+    /// Content types (e.g., `text/plain` or `image/*`) of request bodies which may be uploaded
+    /// (any if empty)
+    #[inline]
+    pub fn upload_types(&self) -> &[String] {
+        &self.upload_types
+    }
+
+    /// Charset parameter appended to `text/*` and JSON content types (`None` to omit it)
+    #[inline]
+    pub fn charset(&self) -> Option<&str> {
+        self.charset.as_deref()
+    }
+
+    /// Compression levels of encodings which should not use the program's default (e.g., `6`
+    /// for gzip)
+    #[inline]
+    pub fn compression_levels(&self) -> &HashMap<Encoding, u32> {
+        &self.compression_levels
+    }
+
+    /// Size in bytes of the smallest response body which is compressed, smaller bodies are sent
+    /// as they are
     #[inline]
-    pub fn encodings() -> &'static HashSet<Encoding> {
+    pub fn min_compress_size(&self) -> u64 {
+        self.min_compress_size
+    }
+
+    /// Media ranges (e.g., `text/*` or `application/json`) of response bodies which are worth
+    /// compressing, others (e.g., images or archives) are sent as they are
+    #[inline]
+    pub fn compress_types(&self) -> &[String] {
+        &self.compress_types
+    }
+
+    /// Number of idle compression program processes kept ready for each encoding, so that
+    /// responses don't wait for the programs to start (`0` to spawn them on demand)
+    #[inline]
+    pub fn prespawned_encoders(&self) -> usize {
+        self.prespawned_encoders
+    }
+
+    /// Maximum total size in bytes of compressed files kept in memory, so that files which are
+    /// served repeatedly are not compressed every time (`0` to disable the cache)
+    #[inline]
+    pub fn compression_cache_size(&self) -> usize {
+        self.compression_cache_size
+    }
+
+    /// Maximum total size in bytes of small files kept in memory, so that files which are served
+    /// repeatedly are not read every time (`0` to disable the cache)
+    #[inline]
+    pub fn file_cache_size(&self) -> usize {
+        self.file_cache_size
+    }
+
+    /// How long the metadata (and entity tags) of served files are reused before they're looked
+    /// up again (zero, the default, to look them up for every request).
+    ///
+    /// Files modified other than through the server may be served as of their previous version
+    /// for up to this long.
+    #[inline]
+    pub fn stat_cache_ttl(&self) -> Duration {
+        self.stat_cache_ttl
+    }
+
+    /// Size in bytes of the smallest file which is compressed into a temporary file instead of
+    /// memory (`None` to always compress into memory)
+    #[inline]
+    pub fn compression_temp_file_size(&self) -> Option<u64> {
+        self.compression_temp_file_size
+    }
+
+    /// Encodings to use: either the ones forced by `--encodings` or those available on the system
+    /// at startup (see [`Self::detected_encodings`]), in both cases except for the disabled ones
+    #[inline]
+    pub fn enabled_encodings(&self) -> HashSet<Encoding> {
+        self.select_encodings(Self::detected_encodings())
+    }
+
+    /// Encodings to use given the `detected` ones (see [`Self::enabled_encodings`])
+    pub(crate) fn select_encodings(&self, detected: &HashSet<Encoding>) -> HashSet<Encoding> {
+        self.encodings
+            .as_ref()
+            .unwrap_or(detected)
+            .difference(&self.disabled_encodings)
+            .copied()
+            .collect()
+    }
+
+    /// Encodings currently used by the server (see [`Self::enabled_encodings`]).
+    ///
+    /// Before the server starts, these are just the encodings available on the system. Note that
+    /// the server detects the encodings again on `SIGHUP`, so the set may change over time.
+    pub fn encodings() -> Arc<HashSet<Encoding>> {
+        if let Some(encodings) = &*ENCODINGS.read().expect("encodings") {
+            return Arc::clone(encodings);
+        }
+
+        let mut encodings = ENCODINGS.write().expect("encodings");
+        let encodings =
+            encodings.get_or_insert_with(|| Arc::new(Self::detected_encodings().clone()));
+        Arc::clone(encodings)
+    }
+
+    /// Encodings whose programs are installed on the system at startup (or which don't need any)
+    #[inline]
+    pub fn detected_encodings() -> &'static HashSet<Encoding> {
         // NOTE: Normally, this would not be necessary, but here we depend on external programs.
         static SUPPORTED: OnceLock<HashSet<Encoding>> = OnceLock::new();
         SUPPORTED.get_or_init(|| {
@@ -46,12 +540,64 @@ impl Config {
     }
 }
 
+/// Encodings used by the server, see [`Config::encodings`]
+static ENCODINGS: RwLock<Option<Arc<HashSet<Encoding>>>> = RwLock::new(None);
+
+/// Set the encodings used by the server (see [`Config::enabled_encodings`]), replacing the
+/// previous ones for any subsequent requests
+pub(crate) fn set_encodings(encodings: HashSet<Encoding>) {
+    *ENCODINGS.write().expect("encodings") = Some(Arc::new(encodings));
+}
+
 impl Default for Config {
     #[inline]
     fn default() -> Self {
         Self {
-            addr: listen_socket_addr(&4221).expect("default listen address"),
+            addr: listen_socket_addr(&4221)
+                .map(ListenAddr::Tcp)
+                .expect("default listen address"),
             dir: PathBuf::from("/tmp"),
+            max_connections: None,
+            queue_timeout: Duration::ZERO,
+            header_timeout: Duration::from_secs(30),
+            body_timeout: Duration::from_secs(60),
+            write_timeout: Duration::from_secs(60),
+            idle_timeout: Duration::from_secs(5),
+            max_requests_per_conn: None,
+            trusted_proxies: Vec::new(),
+            sniff_content_type: false,
+            preserve_header_case: false,
+            strict_parsing: false,
+            trailing_slash: TrailingSlash::Ignore,
+            autoindex: false,
+            index_files: vec!["index.html".to_string(), "index.htm".to_string()],
+            hidden_files: Vec::new(),
+            mounts: Vec::new(),
+            charset: Some("utf-8".to_string()),
+            max_body_size: None,
+            max_headers: MAX_HEADERS,
+            max_header_size: MAX_HEADER_SIZE,
+            max_request_line: MAX_REQUEST_LINE,
+            upload_extensions: Vec::new(),
+            upload_types: Vec::new(),
+            compression_levels: HashMap::new(),
+            min_compress_size: 0,
+            compress_types: [
+                "text/*",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            prespawned_encoders: 2,
+            compression_cache_size: 32 * 1024 * 1024,
+            file_cache_size: 16 * 1024 * 1024,
+            stat_cache_ttl: Duration::ZERO,
+            compression_temp_file_size: Some(16 * 1024 * 1024),
+            encodings: None,
+            disabled_encodings: HashSet::new(),
         }
     }
 }
@@ -75,7 +621,11 @@ impl TryFrom<Args> for Config {
                         bail!("invalid argument value for --port: '{port}'");
                     };
 
-                    cfg.addr = addr;
+                    cfg.addr = ListenAddr::Tcp(addr);
+                }
+
+                "--listen" => {
+                    cfg.addr = arg_value(&mut args, "--listen")?;
                 }
 
                 "--dir" | "--directory" => {
@@ -86,6 +636,236 @@ impl TryFrom<Args> for Config {
                     cfg.dir = dir;
                 }
 
+                "--mount" => {
+                    cfg.mounts.push(arg_value(&mut args, "--mount")?);
+                }
+
+                "--charset" => {
+                    let charset: String = arg_value(&mut args, "--charset")?;
+                    cfg.charset = match charset.trim() {
+                        "" | "none" => None,
+                        charset => Some(charset.to_string()),
+                    };
+                }
+
+                "--max-connections" => {
+                    cfg.max_connections = Some(arg_value(&mut args, "--max-connections")?);
+                }
+
+                "--queue-timeout-ms" => {
+                    let timeout = arg_value(&mut args, "--queue-timeout-ms")?;
+                    cfg.queue_timeout = Duration::from_millis(timeout);
+                }
+
+                "--header-timeout-ms" => {
+                    let timeout = arg_value(&mut args, "--header-timeout-ms")?;
+                    cfg.header_timeout = Duration::from_millis(timeout);
+                }
+
+                "--body-timeout-ms" => {
+                    let timeout = arg_value(&mut args, "--body-timeout-ms")?;
+                    cfg.body_timeout = Duration::from_millis(timeout);
+                }
+
+                "--write-timeout-ms" => {
+                    let timeout = arg_value(&mut args, "--write-timeout-ms")?;
+                    cfg.write_timeout = Duration::from_millis(timeout);
+                }
+
+                "--idle-timeout-ms" => {
+                    let timeout = arg_value(&mut args, "--idle-timeout-ms")?;
+                    cfg.idle_timeout = Duration::from_millis(timeout);
+                }
+
+                "--max-body-size" => {
+                    cfg.max_body_size = Some(arg_value(&mut args, "--max-body-size")?);
+                }
+
+                "--max-headers" => {
+                    cfg.max_headers = arg_value(&mut args, "--max-headers")?;
+                }
+
+                "--max-header-size" => {
+                    cfg.max_header_size = arg_value(&mut args, "--max-header-size")?;
+                }
+
+                "--max-request-line" => {
+                    cfg.max_request_line = arg_value(&mut args, "--max-request-line")?;
+                }
+
+                "--max-requests-per-conn" => {
+                    let max = arg_value(&mut args, "--max-requests-per-conn")?;
+                    cfg.max_requests_per_conn = Some(max);
+                }
+
+                "--trusted-proxy" => {
+                    let proxies: String = arg_value(&mut args, "--trusted-proxy")?;
+                    for proxy in proxies.split(',') {
+                        let cidr = proxy
+                            .trim()
+                            .parse()
+                            .with_context(|| format!("invalid --trusted-proxy '{proxy}'"))?;
+                        cfg.trusted_proxies.push(cidr);
+                    }
+                }
+
+                "--sniff-content-type" => {
+                    cfg.sniff_content_type = true;
+                }
+
+                "--strict-parsing" => {
+                    cfg.strict_parsing = true;
+                }
+
+                "--preserve-header-case" => {
+                    cfg.preserve_header_case = true;
+                }
+
+                "--trailing-slash" => {
+                    cfg.trailing_slash = arg_value(&mut args, "--trailing-slash")?;
+                }
+
+                "--autoindex" => {
+                    cfg.autoindex = true;
+                }
+
+                "--index" => {
+                    let files: String = arg_value(&mut args, "--index")?;
+                    cfg.index_files = files
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|file| !file.is_empty())
+                        .map(String::from)
+                        .collect();
+                }
+
+                "--hide" => {
+                    let patterns: String = arg_value(&mut args, "--hide")?;
+                    cfg.hidden_files.extend(
+                        patterns
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|pattern| !pattern.is_empty())
+                            .map(String::from),
+                    );
+                }
+
+                "--upload-ext" => {
+                    let extensions: String = arg_value(&mut args, "--upload-ext")?;
+                    cfg.upload_extensions.extend(
+                        extensions
+                            .split(',')
+                            .map(|ext| ext.trim().trim_start_matches('.'))
+                            .filter(|ext| !ext.is_empty())
+                            .map(str::to_ascii_lowercase),
+                    );
+                }
+
+                "--upload-type" => {
+                    let types: String = arg_value(&mut args, "--upload-type")?;
+                    cfg.upload_types.extend(
+                        types
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|mime| !mime.is_empty())
+                            .map(str::to_ascii_lowercase),
+                    );
+                }
+
+                "--min-compress-size" => {
+                    cfg.min_compress_size = arg_value(&mut args, "--min-compress-size")?;
+                }
+
+                "--compress-types" => {
+                    let types: String = arg_value(&mut args, "--compress-types")?;
+                    cfg.compress_types = types
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|mime| !mime.is_empty())
+                        .map(String::from)
+                        .collect();
+                }
+
+                "--prespawned-encoders" => {
+                    cfg.prespawned_encoders = arg_value(&mut args, "--prespawned-encoders")?;
+                }
+
+                "--compression-cache-size" => {
+                    let size = arg_value(&mut args, "--compression-cache-size")?;
+                    cfg.compression_cache_size = size;
+                }
+
+                "--file-cache-size" => {
+                    cfg.file_cache_size = arg_value(&mut args, "--file-cache-size")?;
+                }
+
+                "--stat-cache-ttl-ms" => {
+                    let ttl = arg_value(&mut args, "--stat-cache-ttl-ms")?;
+                    cfg.stat_cache_ttl = Duration::from_millis(ttl);
+                }
+
+                "--compression-temp-file-size" => {
+                    let size: String = arg_value(&mut args, "--compression-temp-file-size")?;
+                    cfg.compression_temp_file_size = match size.trim() {
+                        "none" => None,
+                        size => Some(arg_value(
+                            &mut std::iter::once(size.to_string()),
+                            "--compression-temp-file-size",
+                        )?),
+                    };
+                }
+
+                "--encodings" => {
+                    let encodings: String = arg_value(&mut args, "--encodings")?;
+                    let encodings = encodings
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|enc| !enc.is_empty() && !enc.eq_ignore_ascii_case("identity"))
+                        .map(|enc| {
+                            let Ok(encoding) = Encoding::try_from(enc.to_lowercase().as_bytes())
+                            else {
+                                bail!("invalid argument value for --encodings: '{enc}'");
+                            };
+                            if !encoding.is_implemented() {
+                                bail!("{encoding} encoding is not implemented");
+                            }
+                            Ok(encoding)
+                        })
+                        .collect::<Result<_>>()?;
+                    cfg.encodings = Some(encodings);
+                }
+
+                "--no-gzip" => {
+                    cfg.disabled_encodings.insert(Encoding::Gzip);
+                }
+
+                "--no-compress" => {
+                    cfg.disabled_encodings.insert(Encoding::Compress);
+                }
+
+                "--no-br" | "--no-brotli" => {
+                    cfg.disabled_encodings.insert(Encoding::Br);
+                }
+
+                "--no-zstd" => {
+                    cfg.disabled_encodings.insert(Encoding::Zstd);
+                }
+
+                "--gzip-level" => {
+                    let level = compression_level(&mut args, "--gzip-level", Encoding::Gzip)?;
+                    cfg.compression_levels.insert(Encoding::Gzip, level);
+                }
+
+                "--br-level" => {
+                    let level = compression_level(&mut args, "--br-level", Encoding::Br)?;
+                    cfg.compression_levels.insert(Encoding::Br, level);
+                }
+
+                "--zstd-level" => {
+                    let level = compression_level(&mut args, "--zstd-level", Encoding::Zstd)?;
+                    cfg.compression_levels.insert(Encoding::Zstd, level);
+                }
+
                 _ => continue,
             }
         }
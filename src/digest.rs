@@ -0,0 +1,352 @@
+//! Digests of uploaded content declared by `Digest` (RFC 3230), `Content-MD5` (RFC 1864) or
+//! `X-Checksum-SHA256` headers
+use bytes::Bytes;
+
+use crate::header::{HeaderMap, CONTENT_MD5, DIGEST, X_CHECKSUM_SHA256};
+use crate::websocket::base64;
+
+/// Supported digest algorithms
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    /// Name of the algorithm in the `Digest` header
+    #[inline]
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha256 => "sha-256",
+        }
+    }
+
+    /// Length of the digest in bytes
+    #[inline]
+    fn len(&self) -> usize {
+        match self {
+            Self::Md5 => 16,
+            Self::Sha256 => 32,
+        }
+    }
+
+    #[inline]
+    fn parse(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("md5") {
+            Some(Self::Md5)
+        } else if name.eq_ignore_ascii_case("sha-256") {
+            Some(Self::Sha256)
+        } else {
+            None
+        }
+    }
+}
+
+/// Digest of the content expected by the client
+#[derive(Debug)]
+pub(crate) struct Expected {
+    pub(crate) algorithm: Algorithm,
+    pub(crate) digest: Vec<u8>,
+}
+
+/// Collect all the digests of the request body declared by the client.
+///
+/// Digests with unknown algorithms are ignored, but malformed values are an error.
+pub(crate) fn expected(headers: &HeaderMap) -> Result<Vec<Expected>, String> {
+    let mut expected = Vec::new();
+
+    let mut push = |algorithm: Algorithm, digest: Option<Vec<u8>>, header: &str| match digest
+        .filter(|digest| digest.len() == algorithm.len())
+    {
+        Some(digest) => {
+            expected.push(Expected { algorithm, digest });
+            Ok(())
+        }
+        None => Err(format!("malformed {header} header")),
+    };
+
+    if let Some(value) = headers.get(CONTENT_MD5) {
+        push(
+            Algorithm::Md5,
+            text(&value).and_then(base64_decode),
+            "Content-MD5",
+        )?;
+    }
+
+    if let Some(value) = headers.get(DIGEST) {
+        let value = text(&value).ok_or("malformed Digest header")?;
+
+        for instance in value.split(',') {
+            let Some((name, digest)) = instance.split_once('=') else {
+                return Err("malformed Digest header".to_string());
+            };
+
+            if let Some(algorithm) = Algorithm::parse(name.trim()) {
+                push(algorithm, base64_decode(digest.trim()), "Digest")?;
+            }
+        }
+    }
+
+    if let Some(value) = headers.get(X_CHECKSUM_SHA256) {
+        let digest = text(&value).and_then(hex_decode);
+        push(Algorithm::Sha256, digest, "X-Checksum-SHA256")?;
+    }
+
+    Ok(expected)
+}
+
+/// Format given digests as a value of the `Digest` header (e.g., `sha-256=<base64>`)
+pub(crate) fn header_value(digests: &[(Algorithm, Vec<u8>)]) -> Bytes {
+    let instances = digests
+        .iter()
+        .map(|(algorithm, digest)| format!("{}={}", algorithm.name(), base64(digest)));
+
+    itertools::join(instances, ", ").into()
+}
+
+#[inline]
+fn text(value: &Bytes) -> Option<&str> {
+    std::str::from_utf8(value).ok().map(str::trim)
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => Some((hex_digit(*hi)? << 4) | hex_digit(*lo)?),
+            _ => None,
+        })
+        .collect()
+}
+
+#[inline]
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+fn base64_decode(data: &str) -> Option<Vec<u8>> {
+    let data = data.trim_end_matches('=').as_bytes();
+
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+
+    for &c in data {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+
+        acc = acc << 6 | value as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+/// Incremental computation of a digest over a body which is processed in chunks
+#[derive(Debug)]
+pub(crate) struct Hasher {
+    algorithm: Algorithm,
+    state: [u32; 8],
+    block: [u8; 64],
+    /// Number of bytes in the current (incomplete) block
+    pending: usize,
+    /// Total number of bytes processed so far
+    len: u64,
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: Algorithm) -> Self {
+        let state = match algorithm {
+            Algorithm::Md5 => [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0, 0, 0, 0],
+            Algorithm::Sha256 => [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+        };
+
+        Self {
+            algorithm,
+            state,
+            block: [0; 64],
+            pending: 0,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+
+        while !data.is_empty() {
+            let n = data.len().min(64 - self.pending);
+            self.block[self.pending..self.pending + n].copy_from_slice(&data[..n]);
+            self.pending += n;
+            data = &data[n..];
+
+            if self.pending == 64 {
+                self.compress();
+                self.pending = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let bits = self.len.wrapping_mul(8);
+
+        // NOTE: the length is appended in little endian by MD5 and in big endian by SHA-2
+        let len = match self.algorithm {
+            Algorithm::Md5 => bits.to_le_bytes(),
+            Algorithm::Sha256 => bits.to_be_bytes(),
+        };
+
+        let padding = if self.pending < 56 {
+            56 - self.pending
+        } else {
+            120 - self.pending
+        };
+
+        let mut tail = vec![0; padding];
+        tail[0] = 0x80;
+        tail.extend_from_slice(&len);
+
+        // the length was already accounted for
+        let len = self.len;
+        self.update(&tail);
+        self.len = len;
+
+        debug_assert_eq!(self.pending, 0);
+
+        match self.algorithm {
+            Algorithm::Md5 => self.state[..4]
+                .iter()
+                .flat_map(|h| h.to_le_bytes())
+                .collect(),
+            Algorithm::Sha256 => self.state.iter().flat_map(|h| h.to_be_bytes()).collect(),
+        }
+    }
+
+    #[inline]
+    fn compress(&mut self) {
+        match self.algorithm {
+            Algorithm::Md5 => md5_compress(&mut self.state, &self.block),
+            Algorithm::Sha256 => sha256_compress(&mut self.state, &self.block),
+        }
+    }
+}
+
+fn md5_compress(state: &mut [u32; 8], block: &[u8; 64]) {
+    const S: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+    const K: [u32; 64] = [
+        0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613,
+        0xfd469501, 0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193,
+        0xa679438e, 0x49b40821, 0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d,
+        0x02441453, 0xd8a1e681, 0xe7d3fbc8, 0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed,
+        0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a, 0xfffa3942, 0x8771f681, 0x6d9d6122,
+        0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70, 0x289b7ec6, 0xeaa127fa,
+        0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665, 0xf4292244,
+        0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+        0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb,
+        0xeb86d391,
+    ];
+
+    let mut m = [0u32; 16];
+    for (m, word) in m.iter_mut().zip(block.chunks_exact(4)) {
+        *m = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+    }
+
+    let [mut a, mut b, mut c, mut d, ..] = *state;
+
+    for i in 0..64 {
+        let (f, g) = match i / 16 {
+            0 => ((b & c) | (!b & d), i),
+            1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+            2 => (b ^ c ^ d, (3 * i + 5) % 16),
+            _ => (c ^ (b | !d), (7 * i) % 16),
+        };
+
+        let f = f.wrapping_add(a).wrapping_add(K[i]).wrapping_add(m[g]);
+
+        a = d;
+        d = c;
+        c = b;
+        b = b.wrapping_add(f.rotate_left(S[(i / 16) * 4 + i % 4]));
+    }
+
+    for (h, x) in state.iter_mut().zip([a, b, c, d]) {
+        *h = h.wrapping_add(x);
+    }
+}
+
+fn sha256_compress(state: &mut [u32; 8], block: &[u8; 64]) {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+
+    let mut w = [0u32; 64];
+    for (w, word) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *w = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+
+    for (k, w) in K.iter().zip(w) {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(*k)
+            .wrapping_add(w);
+
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+
+    for (h, x) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *h = h.wrapping_add(x);
+    }
+}
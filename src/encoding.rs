@@ -1,70 +1,689 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs::Metadata;
+use std::ops::RangeInclusive;
+use std::os::unix::fs::MetadataExt as _;
 use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
 
-use anyhow::{bail, Context, Error, Result};
+use anyhow::{bail, ensure, Context, Error, Result};
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use itertools::Itertools as _;
-use tokio::io::{AsyncWriteExt, BufWriter};
-use tokio::process::Command;
+use tokio::io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt, BufWriter};
+use tokio::process::{Child, ChildStdin, Command};
+
+use crate::body::{Body, BodySender, FileBody};
+use crate::header::{trim_whitespace, HeaderMap, CONTENT_ENCODING, TRANSFER_ENCODING};
+use crate::StatusCode;
+use crate::{config, lzw};
+
+/// Compression levels of encodings which should not use the program's default
+static LEVELS: OnceLock<HashMap<Encoding, u32>> = OnceLock::new();
+
+/// Set the compression levels of given encodings.
+///
+/// Only the first call takes effect, so the levels are the same for all the responses.
+pub(crate) fn init_levels(levels: &HashMap<Encoding, u32>) {
+    let _ = LEVELS.set(levels.clone());
+}
+
+/// Number of output chunks buffered before the program has to wait for the consumer
+const DECOMPRESSED_CAPACITY: usize = 4;
 
-use crate::body::Body;
+/// Size of chunks in which the output of a program is read
+const DECOMPRESSED_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of chunks of input processed by the in-process LZW coding at once
+const LZW_CHUNK_SIZE: usize = 4 * 1024;
 
 pub trait SystemEncoder {
     fn program(&self) -> Option<&str>;
 
     fn command(&self) -> Option<Command>;
 
+    /// Start the program which compresses its standard input into its standard output
+    #[inline]
+    async fn spawn(&self) -> Result<Child> {
+        spawn_program(self.command())
+    }
+
+    /// Compress given (whole) body with the program
+    #[inline]
     async fn compress(&self, body: Body) -> Result<Body> {
-        let mut cmd = self.command().context("program is not configured")?;
+        compress_with_program(self, body).await
+    }
+
+    /// Compress given body into a temporary file, so that the output does not have to be kept in
+    /// memory (see [`init_temp_file_size`]).
+    ///
+    /// The file is removed right away, so it ceases to exist once the body is dropped.
+    async fn compress_to_file(&self, body: Body) -> Result<Body> {
+        let mut cmd = self.command().context(ProgramUnavailable)?;
+
+        let output = tokio::task::spawn_blocking(temp_file)
+            .await?
+            .context("create temporary file")?;
+
+        cmd.arg("-")
+            .stdin(Stdio::piped())
+            .stdout(output.try_clone().context("setup program output")?)
+            .kill_on_drop(true);
+
+        let mut cmd = spawn(&mut cmd)?;
+
+        let input = cmd.stdin.take().context("setup program input")?;
+
+        let (fed, status) = tokio::join!(feed(input, body), cmd.wait());
+
+        let status = status.context("wait for program")?;
+        fed.context("write program input")?;
+
+        ensure!(status.success(), "program exited with {status}");
+
+        // NOTE: the program shares the file offset, so it's at the end of the output now
+        let mut output = tokio::fs::File::from_std(output);
+        output.rewind().await.context("read program output")?;
+
+        Body::file(output).await.context("read program output")
+    }
+
+    /// Decompress given body as it's being produced, the decompressed body is streamed.
+    ///
+    /// If the body is not validly encoded, the stream ends with an
+    /// [`std::io::ErrorKind::InvalidData`] error.
+    #[inline]
+    async fn decompress(&self, body: Body) -> Result<Body> {
+        decompress_with_program(self, body).await
+    }
+}
+
+/// Identity of a file compressed with an encoding (i.e., regardless of its path)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    dev: u64,
+    ino: u64,
+    encoding: Encoding,
+}
+
+/// Compressed contents of a file as of given modification
+#[derive(Debug)]
+struct CacheEntry {
+    len: u64,
+    modified: Option<SystemTime>,
+    compressed: Bytes,
+    /// Logical time of the last use of the entry
+    used: u64,
+}
+
+impl CacheEntry {
+    /// Returns `true` iff the file has not changed since it was compressed
+    #[inline]
+    fn is_fresh(&self, meta: &Metadata) -> bool {
+        self.len == meta.len() && self.modified == meta.modified().ok()
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Total size of the compressed contents in bytes
+    size: usize,
+    clock: u64,
+}
+
+impl CacheState {
+    #[inline]
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.size -= entry.compressed.len();
+        }
+    }
+}
+
+/// Compressed contents of files which are served repeatedly, the least recently used ones are
+/// evicted once the total size would exceed the budget
+#[derive(Debug)]
+struct Cache {
+    budget: usize,
+    state: Mutex<CacheState>,
+}
+
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+/// Keep up to `budget` bytes of compressed files in memory (`0` to compress them every time).
+///
+/// Only the first call takes effect.
+pub(crate) fn init_cache(budget: usize) {
+    if budget > 0 {
+        let _ = CACHE.set(Cache {
+            budget,
+            state: Mutex::default(),
+        });
+    }
+}
+
+#[inline]
+fn cache_key(meta: &Metadata, encoding: Encoding) -> CacheKey {
+    CacheKey {
+        dev: meta.dev(),
+        ino: meta.ino(),
+        encoding,
+    }
+}
 
-        let cmd = match body {
-            Body::Bytes(bytes) => {
-                cmd.arg("-")
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .kill_on_drop(true);
+/// Compressed contents of the file with given metadata, unless it's not cached or has been
+/// modified since
+pub(crate) fn cached(meta: &Metadata, encoding: Encoding) -> Option<Bytes> {
+    let cache = CACHE.get()?;
+    let key = cache_key(meta, encoding);
 
-                let mut cmd =
-                    tokio::task::spawn_blocking(move || cmd.spawn().context("spawn program"))
-                        .await??;
+    let mut state = cache.state.lock().expect("compression cache");
+    state.clock += 1;
+    let now = state.clock;
 
-                let input = cmd.stdin.take().context("setup program input")?;
-                let mut input = BufWriter::new(input);
+    match state.entries.get_mut(&key) {
+        Some(entry) if entry.is_fresh(meta) => {
+            entry.used = now;
+            Some(entry.compressed.clone())
+        }
+        Some(_) => {
+            state.remove(&key);
+            None
+        }
+        None => None,
+    }
+}
 
-                input
-                    .write_all(&bytes)
-                    .await
-                    .context("write program input")?;
+/// Remember the compressed contents of the file with given metadata
+pub(crate) fn cache(meta: &Metadata, encoding: Encoding, compressed: Bytes) {
+    let Some(cache) = CACHE.get() else {
+        return;
+    };
 
-                input.flush().await.context("flush program input")?;
+    if compressed.len() > cache.budget {
+        return;
+    }
+
+    let key = cache_key(meta, encoding);
+
+    let mut state = cache.state.lock().expect("compression cache");
+
+    // replace any outdated entry of the file
+    state.remove(&key);
+
+    while state.size + compressed.len() > cache.budget {
+        let lru = state
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(key, _)| *key);
+
+        match lru {
+            Some(lru) => state.remove(&lru),
+            None => break,
+        }
+    }
 
-                cmd
+    state.clock += 1;
+    state.size += compressed.len();
+
+    let entry = CacheEntry {
+        len: meta.len(),
+        modified: meta.modified().ok(),
+        compressed,
+        used: state.clock,
+    };
+
+    state.entries.insert(key, entry);
+}
+
+/// Encodings whose programs turned out to be unavailable (see [`is_program_unavailable`])
+static UNAVAILABLE: Mutex<Vec<Encoding>> = Mutex::new(Vec::new());
+
+/// Stop using given encoding for responses (e.g., because its program is missing or broken)
+pub(crate) fn set_unavailable(encoding: Encoding) {
+    let mut unavailable = UNAVAILABLE.lock().expect("unavailable encodings");
+    if !unavailable.contains(&encoding) {
+        eprintln!("disabling {encoding} encoding of responses");
+        unavailable.push(encoding);
+    }
+}
+
+/// Use all the encodings again (e.g., after their programs have been reinstalled)
+pub(crate) fn reset_unavailable() {
+    UNAVAILABLE.lock().expect("unavailable encodings").clear();
+}
+
+/// Returns `false` iff given encoding was marked as unavailable (see [`set_unavailable`])
+pub(crate) fn is_available(encoding: Encoding) -> bool {
+    !UNAVAILABLE
+        .lock()
+        .expect("unavailable encodings")
+        .contains(&encoding)
+}
+
+/// Files of at least this size are compressed into temporary files instead of memory
+static TEMP_FILE_SIZE: OnceLock<Option<u64>> = OnceLock::new();
+
+/// Compress files of at least given size into temporary files instead of memory (`None` to
+/// always compress into memory).
+///
+/// Only the first call takes effect.
+pub(crate) fn init_temp_file_size(size: Option<u64>) {
+    let _ = TEMP_FILE_SIZE.set(size);
+}
+
+#[inline]
+fn is_large_file(file: &FileBody) -> bool {
+    TEMP_FILE_SIZE
+        .get()
+        .copied()
+        .flatten()
+        .is_some_and(|size| file.file_len() >= size)
+}
+
+/// Create a new file in the temporary directory, which is unlinked right away so that it's
+/// removed once closed (even if the server does not exit gracefully)
+fn temp_file() -> std::io::Result<std::fs::File> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let name = format!(
+        ".http-server-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let path = std::env::temp_dir().join(name);
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+
+    std::fs::remove_file(&path)?;
+
+    Ok(file)
+}
+
+/// Spawn given compression program reading from its standard input
+fn spawn_program(cmd: Option<Command>) -> Result<Child> {
+    let mut cmd = cmd.context(ProgramUnavailable)?;
+
+    cmd.arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true);
+
+    spawn(&mut cmd)
+}
+
+/// Spawn the program, failing with [`ProgramUnavailable`] if it cannot be executed at all
+fn spawn(cmd: &mut Command) -> Result<Child> {
+    cmd.spawn().map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied => {
+            Error::new(e).context(ProgramUnavailable)
+        }
+        _ => Error::new(e).context("spawn program"),
+    })
+}
+
+/// The program of an encoding is not configured or cannot be executed (e.g., it's not
+/// installed), as opposed to failing to compress a particular body
+#[derive(Debug, thiserror::Error)]
+#[error("program is unavailable")]
+struct ProgramUnavailable;
+
+/// Returns `true` iff the encoding failed because its program is unavailable, in which case it
+/// should not be used for other responses either (see [`set_unavailable`])
+#[inline]
+pub(crate) fn is_program_unavailable(error: &Error) -> bool {
+    error.is::<ProgramUnavailable>()
+}
+
+/// Processes of compression programs which have been spawned ahead of time, so that responses
+/// don't have to wait for the programs to start.
+///
+/// Each process compresses just a single body, so a new one is spawned in the background
+/// whenever an idle process is taken.
+#[derive(Debug)]
+struct Prespawned {
+    /// Number of idle processes kept for each encoding
+    size: usize,
+    idle: Mutex<HashMap<Encoding, Vec<Child>>>,
+}
+
+impl Prespawned {
+    /// Take an idle process of given encoding or spawn a new one if there's none
+    async fn take(&'static self, encoding: Encoding) -> Result<Child> {
+        self.refill(encoding);
+
+        loop {
+            let idle = self
+                .idle
+                .lock()
+                .expect("prespawned processes")
+                .get_mut(&encoding)
+                .and_then(Vec::pop);
+
+            let Some(mut child) = idle else {
+                return spawn_program(encoding.command());
+            };
+
+            // skip processes which exited in the meantime (e.g., have been killed)
+            if matches!(child.try_wait(), Ok(None)) {
+                return Ok(child);
             }
+        }
+    }
 
-            Body::File(file) => {
-                cmd.arg(file.as_path())
-                    .stdout(Stdio::piped())
-                    .kill_on_drop(true);
+    /// Spawn a full pool of idle processes of given encodings in the background
+    fn fill(&'static self, encodings: &HashSet<Encoding>) {
+        // NOTE: there's nothing to prespawn for encodings implemented in-process
+        for &encoding in encodings.iter().filter(|enc| enc.program().is_some()) {
+            for _ in 0..self.size {
+                self.refill(encoding);
+            }
+        }
+    }
 
-                tokio::task::spawn_blocking(move || cmd.spawn().context("spawn program")).await??
+    /// Spawn an idle process of given encoding in the background
+    fn refill(&'static self, encoding: Encoding) {
+        tokio::spawn(async move {
+            let child = match spawn_program(encoding.command()) {
+                Ok(child) => child,
+                Err(e) => {
+                    eprintln!("failed to prespawn {encoding} process: {e:#}");
+                    return;
+                }
+            };
+
+            let mut idle = self.idle.lock().expect("prespawned processes");
+            let idle = idle.entry(encoding).or_default();
+
+            // NOTE: the process is killed when dropped if there's enough of them already
+            if idle.len() < self.size {
+                idle.push(child);
             }
+        });
+    }
+}
+
+static PRESPAWNED: OnceLock<Prespawned> = OnceLock::new();
+
+/// Keep `size` idle processes of each of given encodings ready to compress responses (`0` to
+/// always spawn the programs on demand).
+///
+/// Only the first call takes effect and it must be made after [`init_levels`] (the processes are
+/// spawned with the compression levels).
+pub(crate) fn init_prespawned(size: usize, encodings: &HashSet<Encoding>) {
+    if size == 0 || PRESPAWNED.get().is_some() {
+        return;
+    }
+
+    let pool = PRESPAWNED.get_or_init(|| Prespawned {
+        size,
+        idle: Mutex::default(),
+    });
+
+    pool.fill(encodings);
+}
+
+/// Replace all the idle prespawned processes by new ones for given encodings (e.g., after the
+/// available programs have changed)
+pub(crate) fn respawn_prespawned(encodings: &HashSet<Encoding>) {
+    let Some(pool) = PRESPAWNED.get() else {
+        return;
+    };
+
+    // NOTE: the processes are killed once dropped
+    pool.idle.lock().expect("prespawned processes").clear();
+
+    pool.fill(encodings);
+}
+
+/// Compress given body with the program of the encoder (see [`SystemEncoder::compress`])
+async fn compress_with_program<E: SystemEncoder + ?Sized>(encoder: &E, body: Body) -> Result<Body> {
+    match &body {
+        Body::File(file) if file.is_partial() => bail!("partial file cannot be compressed"),
+        Body::Stream(_) => bail!("streamed body cannot be compressed"),
+        _ => {}
+    }
+
+    if matches!(&body, Body::File(file) if is_large_file(file)) {
+        return encoder.compress_to_file(body).await;
+    }
+
+    let mut cmd = encoder.spawn().await?;
+
+    let input = cmd.stdin.take().context("setup program input")?;
+
+    // NOTE: the output is read while the input is still being written, so that the program
+    //  cannot get stuck on a full pipe
+    let (fed, output) = tokio::join!(feed(input, body), cmd.wait_with_output());
+
+    let output = output.context("wait for program output")?;
+    fed.context("write program input")?;
+
+    ensure!(
+        output.status.success(),
+        "program exited with {}",
+        output.status
+    );
+
+    Ok(Body::bytes(output.stdout))
+}
+
+/// Decompress given body with the program of the encoder (see [`SystemEncoder::decompress`])
+async fn decompress_with_program<E: SystemEncoder + ?Sized>(
+    encoder: &E,
+    body: Body,
+) -> Result<Body> {
+    let mut cmd = encoder.command().context("program is not configured")?;
+
+    cmd.arg("-d")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+
+    let cmd = cmd.spawn().context("spawn program")?;
+
+    Ok(stream_through(cmd, body, || {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid encoded body")
+    }))
+}
+
+/// Feed the whole body into the input of a running program and stream its output.
+///
+/// If the program fails, the stream ends with the error returned by `failure`.
+pub(crate) fn stream_through(mut cmd: Child, body: Body, failure: fn() -> std::io::Error) -> Body {
+    let (tx, output_body) = Body::channel(DECOMPRESSED_CAPACITY);
+
+    tokio::spawn(async move {
+        let (Some(input), Some(mut output)) = (cmd.stdin.take(), cmd.stdout.take()) else {
+            let _ = tx
+                .abort(std::io::Error::other(
+                    "program input or output is not piped",
+                ))
+                .await;
+            return;
+        };
+
+        let drain = async {
+            let mut open = true;
+            loop {
+                let mut chunk = BytesMut::with_capacity(DECOMPRESSED_CHUNK_SIZE);
+                if output.read_buf(&mut chunk).await? == 0 {
+                    break Ok::<_, std::io::Error>(());
+                }
+                // NOTE: the output is discarded if the body has been dropped, so that the
+                // program does not get stuck on a full pipe
+                open = open && tx.send(chunk).await.is_ok();
+            }
+        };
+
+        let (fed, drained) = tokio::join!(feed(input, body), drain);
+
+        let status = cmd.wait().await;
+
+        let error = match (fed, status, drained) {
+            // the program stops reading its input once it finds out it's not valid
+            (Err(e), _, _) if e.kind() != std::io::ErrorKind::BrokenPipe => Some(e),
+            (_, Ok(status), _) if !status.success() => Some(failure()),
+            (_, Err(e), _) | (_, _, Err(e)) => Some(e),
+            _ => None,
         };
 
-        // XXX: for files it might be better to let the program write the output into a temp file
-        //  - pros: don't have to load the whole (compressed) file contents into memory for output
-        //  - cons: takes more storage space, have to deal with temp file cleanup and/or caching
-        let output = cmd
-            .wait_with_output()
-            .await
-            .context("wait for program output")?;
+        if let Some(error) = error {
+            let _ = tx.abort(error).await;
+        }
+    });
+
+    output_body
+}
 
-        if !output.status.success() {
-            eprintln!("program exited with code {}", output.status);
+/// Compress given body in-process with the LZW coding of the `compress` program.
+///
+/// Like with programs, large files are compressed into a temporary file (see
+/// [`init_temp_file_size`]).
+async fn lzw_compress(body: Body) -> Result<Body> {
+    let mut encoder = lzw::Encoder::new();
+    let mut out = Vec::new();
+
+    let file = match body {
+        Body::Bytes(bytes) => {
+            encoder.update(&bytes, &mut out);
+            encoder.finish(&mut out);
+            return Ok(Body::bytes(out));
         }
+        Body::File(file) if file.is_partial() => bail!("partial file cannot be compressed"),
+        Body::File(file) => file,
+        Body::Stream(_) => bail!("streamed body cannot be compressed"),
+    };
+
+    let mut output = if is_large_file(&file) {
+        let output = tokio::task::spawn_blocking(temp_file)
+            .await?
+            .context("create temporary file")?;
+        Some(BufWriter::new(tokio::fs::File::from_std(output)))
+    } else {
+        None
+    };
+
+    let mut input = file.into_reader();
+    let mut chunk = BytesMut::with_capacity(LZW_CHUNK_SIZE);
+
+    loop {
+        chunk.clear();
+        if input.read_buf(&mut chunk).await.context("read input")? == 0 {
+            break;
+        }
+
+        encoder.update(&chunk, &mut out);
 
-        Ok(Body::bytes(output.stdout))
+        if let Some(output) = output.as_mut() {
+            output.write_all(&out).await.context("write output")?;
+            out.clear();
+        }
     }
+
+    encoder.finish(&mut out);
+
+    let Some(mut output) = output else {
+        return Ok(Body::bytes(out));
+    };
+
+    output.write_all(&out).await.context("write output")?;
+    output.flush().await.context("write output")?;
+
+    let mut output = output.into_inner();
+    output.rewind().await.context("read output")?;
+
+    Body::file(output).await.context("read output")
+}
+
+/// Decompress given body in-process with the LZW coding of the `compress` program, the
+/// decompressed body is streamed.
+///
+/// If the body is not validly encoded, the stream ends with an
+/// [`std::io::ErrorKind::InvalidData`] error.
+fn lzw_decompress(body: Body) -> Result<Body> {
+    let (tx, decompressed) = Body::channel(DECOMPRESSED_CAPACITY);
+
+    tokio::spawn(async move {
+        let mut decoder = lzw::Decoder::new();
+        let mut open = true;
+
+        let result = async {
+            match body {
+                Body::Bytes(bytes) => lzw_decode(&mut decoder, &bytes, &tx, &mut open).await?,
+                Body::File(file) => {
+                    let mut reader = file.into_reader();
+                    let mut chunk = BytesMut::with_capacity(LZW_CHUNK_SIZE);
+                    while reader.read_buf(&mut chunk).await? > 0 {
+                        lzw_decode(&mut decoder, &chunk, &tx, &mut open).await?;
+                        chunk.clear();
+                    }
+                }
+                Body::Stream(mut stream) => {
+                    while let Some(chunk) = stream.next().await {
+                        lzw_decode(&mut decoder, &chunk?, &tx, &mut open).await?;
+                    }
+                }
+            }
+            decoder.finish()
+        };
+
+        if let Err(error) = result.await {
+            let _ = tx.abort(error).await;
+        }
+    });
+
+    Ok(decompressed)
+}
+
+/// Decode next part of the input and send the output to the decompressed body
+async fn lzw_decode(
+    decoder: &mut lzw::Decoder,
+    input: &[u8],
+    tx: &BodySender,
+    open: &mut bool,
+) -> std::io::Result<()> {
+    // NOTE: input is decoded in small parts, so that the decompressed chunks stay bounded
+    for part in input.chunks(LZW_CHUNK_SIZE) {
+        let mut out = Vec::new();
+        decoder.update(part, &mut out)?;
+
+        // NOTE: the output is discarded if the body has been dropped, but the input is still
+        //  checked to be valid
+        if *open && !out.is_empty() {
+            *open = tx.send(out).await.is_ok();
+        }
+    }
+    Ok(())
+}
+
+/// Write the whole body into the program's input, which is closed afterwards
+async fn feed(input: ChildStdin, body: Body) -> std::io::Result<()> {
+    let mut input = BufWriter::new(input);
+
+    match body {
+        Body::Bytes(bytes) => input.write_all(&bytes).await?,
+        Body::File(file) => {
+            tokio::io::copy(&mut file.into_reader(), &mut input).await?;
+        }
+        Body::Stream(mut stream) => {
+            while let Some(chunk) = stream.next().await {
+                input.write_all(&chunk?).await?;
+            }
+        }
+    }
+
+    input.shutdown().await
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -134,7 +753,78 @@ impl TryFrom<Bytes> for Encoding {
     }
 }
 
+impl Encoding {
+    /// Compression levels supported by the program of this encoding (from the fastest)
+    #[inline]
+    pub(crate) fn levels(&self) -> Option<RangeInclusive<u32>> {
+        match self {
+            Self::Gzip => Some(1..=9),
+            Self::Br => Some(0..=11),
+            // NOTE: higher levels require `--ultra` and a lot of memory
+            Self::Zstd => Some(1..=19),
+            Self::Compress | Self::Deflate => None,
+        }
+    }
+
+    /// Configured compression level (see [`init_levels`]), `None` for the program's default
+    #[inline]
+    fn level(&self) -> Option<u32> {
+        LEVELS.get()?.get(self).copied()
+    }
+
+    /// Returns `false` iff there's no implementation of the encoding (neither a program nor an
+    /// in-process one)
+    #[inline]
+    pub(crate) fn is_implemented(&self) -> bool {
+        self.program().is_some() || matches!(self, Self::Compress)
+    }
+
+    /// Returns `true` iff the encoding can be applied as a transfer coding (see RFC 9112, section
+    /// 7), i.e., while the body is being sent
+    #[inline]
+    pub(crate) fn is_transfer_coding(&self) -> bool {
+        // NOTE: other registered transfer codings are either not implemented or in-process only
+        matches!(self, Self::Gzip)
+    }
+
+    /// Extension of files precompressed with this encoding (e.g., `gz` for `foo.txt.gz`)
+    #[inline]
+    pub(crate) fn file_extension(&self) -> Option<&'static str> {
+        match self {
+            Self::Gzip => Some("gz"),
+            Self::Compress => Some("Z"),
+            Self::Deflate => None,
+            Self::Br => Some("br"),
+            Self::Zstd => Some("zst"),
+        }
+    }
+}
+
 impl SystemEncoder for Encoding {
+    #[inline]
+    async fn compress(&self, body: Body) -> Result<Body> {
+        match self {
+            Self::Compress => lzw_compress(body).await,
+            _ => compress_with_program(self, body).await,
+        }
+    }
+
+    #[inline]
+    async fn decompress(&self, body: Body) -> Result<Body> {
+        match self {
+            Self::Compress => lzw_decompress(body),
+            _ => decompress_with_program(self, body).await,
+        }
+    }
+
+    #[inline]
+    async fn spawn(&self) -> Result<Child> {
+        match PRESPAWNED.get() {
+            Some(pool) => pool.take(*self).await,
+            None => spawn_program(self.command()),
+        }
+    }
+
     #[inline]
     fn program(&self) -> Option<&str> {
         match self {
@@ -147,27 +837,40 @@ impl SystemEncoder for Encoding {
     }
 
     fn command(&self) -> Option<Command> {
-        match self {
+        let mut cmd = match self {
             Self::Gzip => self.program().map(Command::new).map(|mut gzip| {
                 gzip.arg("-q").arg("-c");
+                if let Some(level) = self.level() {
+                    gzip.arg(format!("-{level}"));
+                }
                 gzip
             }),
             Self::Compress => None,
             Self::Deflate => None,
             Self::Br => self.program().map(Command::new).map(|mut br| {
                 br.arg("-c");
+                if let Some(level) = self.level() {
+                    br.arg("-q").arg(level.to_string());
+                }
                 br
             }),
             Self::Zstd => self.program().map(Command::new).map(|mut zstd| {
                 zstd.arg("-q").arg("-c");
+                if let Some(level) = self.level() {
+                    zstd.arg(format!("-{level}"));
+                }
                 zstd
             }),
+        }?;
+
+        for var in config::SD_LISTEN_VARS {
+            cmd.env_remove(var);
         }
+
+        Some(cmd)
     }
 }
 
-// TODO: support * and quality values (e.g., `*;q=0.1`)
-// https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Accept-Encoding
 encoding! {
     Gzip(GZIP, b"gzip", Some("gzip")),
     Compress(COMPRESS, b"compress", None),
@@ -176,6 +879,74 @@ encoding! {
     Zstd(ZSTD, b"zstd", Some("zstd"))
 }
 
+/// Content codings of a request body in the order in which they have been applied.
+///
+/// Fails if any of the codings is unknown or not supported (see [`crate::Config::encodings`]).
+pub(crate) fn request_codings(
+    headers: &HeaderMap,
+    supported: &HashSet<Encoding>,
+) -> Result<Vec<Encoding>> {
+    let Some(value) = headers.get(CONTENT_ENCODING) else {
+        return Ok(Vec::new());
+    };
+
+    value
+        .split(|&b| b == b',')
+        .map(trim_whitespace)
+        .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case(b"identity"))
+        .map(|coding| {
+            let encoding = Encoding::try_from(coding.to_ascii_lowercase().as_slice())?;
+            if !supported.contains(&encoding) {
+                bail!("unsupported encoding '{encoding}'");
+            }
+            Ok(encoding)
+        })
+        .collect()
+}
+
+/// Transfer codings of a request body in the order in which they have been applied, except for
+/// the final `chunked` (see RFC 9112, section 6.1).
+///
+/// Returns `None` if the body is not transfer-coded (i.e., its length is given by
+/// `Content-Length`). Otherwise fails with
+///  - [`StatusCode::BAD_REQUEST`] if `chunked` is not the final coding, since the end of the body
+///    could not be determined
+///  - [`StatusCode::NOT_IMPLEMENTED`] if any other coding is unknown or not supported
+pub(crate) fn request_transfer_codings(
+    headers: &HeaderMap,
+    supported: &HashSet<Encoding>,
+) -> Result<Option<Vec<Encoding>>, StatusCode> {
+    let Some(value) = headers.get(TRANSFER_ENCODING) else {
+        return Ok(None);
+    };
+
+    let mut codings = value
+        .split(|&b| b == b',')
+        .map(trim_whitespace)
+        .filter(|coding| !coding.is_empty())
+        .map(|coding| coding.to_ascii_lowercase())
+        .collect_vec();
+
+    if codings.pop().as_deref() != Some(b"chunked".as_slice()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    codings
+        .into_iter()
+        .map(|coding| {
+            // NOTE: `x-gzip` is an alias of `gzip` (see RFC 9112, section 7.2)
+            let coding = coding.strip_prefix(b"x-").unwrap_or(&coding);
+            match Encoding::try_from(coding) {
+                Ok(encoding) if encoding.is_transfer_coding() && supported.contains(&encoding) => {
+                    Ok(encoding)
+                }
+                _ => Err(StatusCode::NOT_IMPLEMENTED),
+            }
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
 pub(crate) fn get_supported() -> Result<HashSet<Encoding>> {
     use std::process::Command;
 
@@ -202,6 +973,8 @@ pub(crate) fn get_supported() -> Result<HashSet<Encoding>> {
                 Some(enc)
             }
         })
+        // NOTE: LZW is implemented in-process, so it does not depend on any program
+        .chain(std::iter::once(Encoding::Compress))
         .collect();
 
     Ok(supported)
@@ -0,0 +1,98 @@
+use std::io::ErrorKind;
+
+use crate::extract::Rejection;
+use crate::header::WWW_AUTHENTICATE;
+use crate::{IntoResponse, Response, ResponseBuilder, StatusCode};
+
+/// Error of a request handler which is turned into a `4xx` or `5xx` response
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    #[error("{0}")]
+    BadRequest(String),
+
+    /// Missing or invalid credentials for given authentication realm
+    #[error("unauthorized")]
+    Unauthorized(String),
+
+    #[error("forbidden")]
+    Forbidden,
+
+    #[error("not found")]
+    NotFound,
+
+    /// The request conflicts with the current state of the target (e.g., it is a directory)
+    #[error("{0}")]
+    Conflict(String),
+
+    /// Conditional request whose precondition does not hold for the current state of the target
+    #[error("precondition failed")]
+    PreconditionFailed,
+
+    /// The request body is of a type which is not accepted
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+
+    #[error("{0}")]
+    Rejected(#[from] Rejection),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0:#}")]
+    Internal(#[from] anyhow::Error),
+}
+
+impl ServerError {
+    #[inline]
+    pub fn bad_request(reason: impl Into<String>) -> Self {
+        Self::BadRequest(reason.into())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::Rejected(rejection) => rejection.status(),
+            Self::Io(e) => match e.kind() {
+                ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+                ErrorKind::InvalidInput | ErrorKind::InvalidData => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Plain text response with the error message, except for `404 Not Found` which is empty.
+///
+/// Server errors only carry the reason phrase of their status, so that internal details (e.g.,
+/// I/O error messages) are only logged and not leaked to clients.
+impl IntoResponse for ServerError {
+    fn into_response(self, resp: ResponseBuilder) -> Response {
+        let status = self.status();
+
+        eprintln!("request failed with {}: {self}", status.as_u16());
+
+        match self {
+            _ if status == StatusCode::NOT_FOUND => resp.status(status).build(),
+            _ if status.as_u16() >= 500 => {
+                let reason = status.as_str().to_string();
+                resp.status(status).plain(reason)
+            }
+            Self::Rejected(rejection) => rejection.into_response(resp),
+            Self::Unauthorized(ref realm) => {
+                let challenge = format!("Basic realm=\"{realm}\", charset=\"UTF-8\"");
+                resp.status(status)
+                    .header(WWW_AUTHENTICATE, challenge.into())
+                    .plain(self.to_string())
+            }
+            error => resp.status(status).plain(error.to_string()),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Typed extractors that let handlers declare which parts of a [`Request`] they need.
+//!
+//! Each handler argument implements [`FromRequest`]. The router runs the extractors in order
+//! before calling the handler and short-circuits with the [`Rejection`] of the first one that
+//! fails (by default, a `400 Bad Request` with a plain text reason).
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use bytes::Bytes;
+
+use crate::body::Body as RawBody;
+use crate::header::{percent_decode, HeaderMap, ToHeaderName};
+use crate::{IntoResponse, Method, Request, Response, ResponseBuilder, StatusCode};
+
+/// Failed extraction which is turned into an error response
+#[derive(Debug)]
+pub struct Rejection {
+    status: StatusCode,
+    reason: String,
+}
+
+impl Rejection {
+    #[inline]
+    pub fn new(status: StatusCode, reason: impl Into<String>) -> Self {
+        Self {
+            status,
+            reason: reason.into(),
+        }
+    }
+
+    #[inline]
+    pub fn bad_request(reason: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, reason)
+    }
+
+    #[inline]
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+impl IntoResponse for Rejection {
+    #[inline]
+    fn into_response(self, resp: ResponseBuilder) -> Response {
+        resp.status(self.status).plain(self.reason)
+    }
+}
+
+impl std::fmt::Display for Rejection {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.status.as_u16(),
+            self.status.as_str(),
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+pub trait FromRequest: Sized {
+    fn from_request(req: &mut Request) -> impl Future<Output = Result<Self, Rejection>> + Send;
+}
+
+/// Path parameter captured by the route pattern (e.g., `{name}` in `/files/{name}`).
+///
+/// If the pattern captures more than one parameter, the last one is extracted. The parameter is
+/// percent-decoded completely, including the escapes kept by routing (e.g., `%2F`), so it may
+/// contain slashes or dot segments of its own.
+#[derive(Debug)]
+pub struct Path<T>(pub T);
+
+impl<T> FromRequest for Path<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    async fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        let Some((name, value)) = req.params.last() else {
+            return Err(Rejection::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "route has no path parameters",
+            ));
+        };
+
+        // NOTE: the path has been normalized with encoded slashes and `%` intact, so that these
+        //  did not affect routing, and just now are they decoded
+        let value = percent_decode(value);
+
+        let Ok(value) = std::str::from_utf8(&value) else {
+            return Err(Rejection::bad_request(format!(
+                "path parameter '{name}' is not valid UTF-8"
+            )));
+        };
+
+        value
+            .parse()
+            .map(Self)
+            .map_err(|e| Rejection::bad_request(format!("invalid path parameter '{name}': {e}")))
+    }
+}
+
+pub trait FromQuery: Sized {
+    fn from_query(query: &str) -> Result<Self, Rejection>;
+}
+
+fn query_pairs(query: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    query.split('&').filter(|p| !p.is_empty()).map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (key.to_string(), value.to_string())
+    })
+}
+
+impl FromQuery for HashMap<String, String> {
+    #[inline]
+    fn from_query(query: &str) -> Result<Self, Rejection> {
+        Ok(query_pairs(query).collect())
+    }
+}
+
+impl FromQuery for Vec<(String, String)> {
+    #[inline]
+    fn from_query(query: &str) -> Result<Self, Rejection> {
+        Ok(query_pairs(query).collect())
+    }
+}
+
+/// Query string of the request target (the part after `?`)
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+impl<T: FromQuery> FromRequest for Query<T> {
+    async fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        let query = req.query().unwrap_or_default();
+
+        let Ok(query) = std::str::from_utf8(query) else {
+            return Err(Rejection::bad_request("query is not valid UTF-8"));
+        };
+
+        T::from_query(query).map(Self)
+    }
+}
+
+/// Typed header value, rejects requests in which the header is missing or malformed
+#[derive(Debug)]
+pub struct Header<T>(pub T);
+
+impl<T> FromRequest for Header<T>
+where
+    T: ToHeaderName,
+    Bytes: TryInto<T>,
+{
+    async fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        let name = T::header_name();
+
+        let Some(value) = req.headers.get(&name) else {
+            return Err(Rejection::bad_request(format!(
+                "missing header '{}'",
+                String::from_utf8_lossy(&name)
+            )));
+        };
+
+        value.try_into().map(Self).map_err(|_| {
+            Rejection::bad_request(format!(
+                "invalid header '{}'",
+                String::from_utf8_lossy(&name)
+            ))
+        })
+    }
+}
+
+pub trait FromBody: Sized {
+    fn from_body(body: RawBody) -> impl Future<Output = Result<Self, Rejection>> + Send;
+}
+
+impl FromBody for RawBody {
+    #[inline]
+    async fn from_body(body: RawBody) -> Result<Self, Rejection> {
+        Ok(body)
+    }
+}
+
+impl FromBody for Bytes {
+    async fn from_body(body: RawBody) -> Result<Self, Rejection> {
+        body.into_bytes().await.map_err(|e| {
+            Rejection::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to read body: {e}"),
+            )
+        })
+    }
+}
+
+impl FromBody for String {
+    async fn from_body(body: RawBody) -> Result<Self, Rejection> {
+        let body = Bytes::from_body(body).await?;
+        String::from_utf8(body.into())
+            .map_err(|_| Rejection::bad_request("body is not valid UTF-8"))
+    }
+}
+
+/// Request body, consumes the body so it can be extracted just once
+#[derive(Debug)]
+pub struct Body<T>(pub T);
+
+impl<T: FromBody> FromRequest for Body<T> {
+    async fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        let body = std::mem::replace(&mut req.body, RawBody::empty());
+        T::from_body(body).await.map(Self)
+    }
+}
+
+impl<T: FromRequest + Send> FromRequest for Option<T> {
+    #[inline]
+    async fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        Ok(T::from_request(req).await.ok())
+    }
+}
+
+impl FromRequest for Method {
+    #[inline]
+    async fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        Ok(req.method.clone())
+    }
+}
+
+impl FromRequest for HeaderMap {
+    #[inline]
+    async fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        Ok(req.headers.clone())
+    }
+}
+
+/// IP address of the client which originated the request (see [`Request::client_ip`])
+#[derive(Debug)]
+pub struct ClientIp(pub IpAddr);
+
+impl FromRequest for ClientIp {
+    async fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        req.client_ip().map(Self).ok_or_else(|| {
+            Rejection::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "client address is not known",
+            )
+        })
+    }
+}
+
+/// Response builder with content negotiation based on the request
+impl FromRequest for ResponseBuilder {
+    #[inline]
+    async fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        Ok(Response::from_request(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::{get, Router};
+
+    async fn echo(target: &'static str) -> Bytes {
+        let router = Router::new().route("/echo/{*msg}", get(crate::handlers::echo));
+
+        let req = Request::builder().target(target).build();
+        let resp = router.handle(req).await;
+
+        assert_eq!(resp.status, StatusCode::OK);
+        resp.body.into_bytes().await.expect("echoed message")
+    }
+
+    #[tokio::test]
+    async fn path_decoded_after_routing() {
+        assert_eq!(echo("/echo/100%25").await, "100%");
+        assert_eq!(echo("/echo/a%2Fb/c").await, "a/b/c");
+        assert_eq!(echo("/echo/hello%20world").await, "hello world");
+        assert_eq!(echo("/echo/%252F").await, "%2F");
+    }
+}
@@ -0,0 +1,1882 @@
+//! Handlers of a file resource backed by a directory on the local file system
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::{Bytes, BytesMut};
+use tokio::fs;
+use tokio::io::{AsyncReadExt as _, AsyncSeekExt as _};
+
+use crate::body::{Body, BodySender, FileBody};
+use crate::digest;
+use crate::extract;
+use crate::header::{
+    Accept, AcceptEncoding, ContentEncoding, ContentLength, ContentRange, ContentType, ETag,
+    HeaderMap, HttpDate, IfMatch, IfModifiedSince, IfNoneMatch, IfRange, IfUnmodifiedSince,
+    IntoHeaderValue, LastModified, MimeType, Range, ACCEPT, ACCEPT_RANGES, AUTHORIZATION, BYTES,
+    CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, DIGEST, ETAG,
+    IF_MATCH, IF_NONE_MATCH, IF_RANGE, IF_UNMODIFIED_SINCE, VARY,
+};
+use crate::io::FileWriter;
+use crate::router::{self, MethodRouter};
+use crate::websocket::base64;
+use crate::{Config, Method, Mount, Response, ResponseBuilder, ServerError, StatusCode};
+
+type FilePath = extract::Path<PathBuf>;
+
+/// Number of leading bytes of a file inspected when sniffing its content type
+const SNIFF_LEN: usize = 512;
+
+/// Maximum number of ranges served in a single multipart response
+const MAX_RANGES: usize = 16;
+
+/// Size of chunks in which parts of a multipart response are read
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Files larger than this are always read from the file system (see [`init_cache`])
+const MAX_CACHED_FILE: u64 = 256 * 1024;
+
+/// Maximum number of paths whose metadata is kept (see [`init_stat_cache`])
+const MAX_STATS: usize = 4096;
+
+/// Options of serving files from a directory
+#[derive(Clone, Debug)]
+pub(crate) struct ServeOptions {
+    /// Detect the content type of files from their leading bytes
+    pub(crate) sniff: bool,
+    /// List the contents of requested directories
+    pub(crate) autoindex: bool,
+    /// Names of files served in place of a requested directory
+    pub(crate) index: Vec<String>,
+    /// Files and directories which are served as if they did not exist
+    pub(crate) hidden: Vec<Pattern>,
+    /// How long clients may cache the files
+    pub(crate) max_age: Option<Duration>,
+    /// Realm and expected (Base64-encoded) credentials of HTTP Basic authentication
+    pub(crate) auth: Option<(String, String)>,
+    /// List the files in the root of the directory as JSON (unless there's an index file)
+    pub(crate) list_files: bool,
+    /// Extensions of files which may be uploaded (any if empty)
+    pub(crate) upload_extensions: Vec<String>,
+    /// Content types (possibly `type/*`) of bodies which may be uploaded (any if empty)
+    pub(crate) upload_types: Vec<String>,
+}
+
+impl ServeOptions {
+    /// Options of a directory mounted under a URL path prefix
+    pub(crate) fn mount(cfg: &Config, mount: &Mount) -> Self {
+        Self {
+            autoindex: cfg.autoindex() || mount.autoindex(),
+            max_age: mount.max_age(),
+            auth: mount
+                .auth()
+                .map(|credentials| (mount.prefix().to_string(), base64(credentials.as_bytes()))),
+            ..Self::from(cfg)
+        }
+    }
+
+    /// Returns `true` iff the request carries the required credentials (if there are any)
+    fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        let Some((_, expected)) = &self.auth else {
+            return true;
+        };
+
+        let Some(authorization) = headers.get(AUTHORIZATION) else {
+            return false;
+        };
+
+        let Ok(authorization) = std::str::from_utf8(&authorization) else {
+            return false;
+        };
+
+        authorization
+            .trim()
+            .split_once(' ')
+            .is_some_and(|(scheme, credentials)| {
+                scheme.eq_ignore_ascii_case("basic") && credentials.trim() == expected
+            })
+    }
+
+    /// Fail with `415 Unsupported Media Type` unless the request body may be uploaded as given
+    /// file, i.e., both its extension and the declared `Content-Type` are allowed
+    fn check_upload(&self, file: &Path, headers: &HeaderMap) -> Result<(), ServerError> {
+        if !self.upload_extensions.is_empty() {
+            let allowed = file.extension().is_some_and(|ext| {
+                let ext = ext.to_string_lossy();
+                self.upload_extensions
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(&ext))
+            });
+
+            if !allowed {
+                return Err(ServerError::UnsupportedMediaType(
+                    "file extension is not allowed".to_string(),
+                ));
+            }
+        }
+
+        if !self.upload_types.is_empty() {
+            let allowed = headers
+                .extract::<ContentType>()
+                .is_some_and(|content_type| content_type.is_in(&self.upload_types));
+
+            if !allowed {
+                return Err(ServerError::UnsupportedMediaType(
+                    "content type is not allowed".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` iff an entry called `name` is hidden
+    fn is_hidden(&self, name: &str, is_dir: bool) -> bool {
+        self.hidden
+            .iter()
+            .any(|pattern| pattern.matches(name, is_dir))
+    }
+
+    /// Returns `true` iff any component of given relative path is hidden, all but the last one
+    /// being directories
+    fn is_hidden_path(&self, path: &Path, is_dir: bool) -> bool {
+        let mut components = path.components().peekable();
+
+        while let Some(component) = components.next() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+
+            let is_dir = is_dir || components.peek().is_some();
+
+            if self.is_hidden(&name.to_string_lossy(), is_dir) {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+impl Default for ServeOptions {
+    #[inline]
+    fn default() -> Self {
+        Self::from(&Config::default())
+    }
+}
+
+impl From<&Config> for ServeOptions {
+    #[inline]
+    fn from(cfg: &Config) -> Self {
+        Self {
+            sniff: cfg.sniff_content_type(),
+            autoindex: cfg.autoindex(),
+            index: cfg.index_files().to_vec(),
+            hidden: cfg.hidden_files().iter().map(|p| Pattern::new(p)).collect(),
+            max_age: None,
+            auth: None,
+            list_files: false,
+            upload_extensions: cfg.upload_extensions().to_vec(),
+            upload_types: cfg.upload_types().to_vec(),
+        }
+    }
+}
+
+/// Glob pattern matching file names, where `*` matches any sequence of characters and `?` any
+/// single character.
+///
+/// A pattern with a trailing slash (e.g., `.git/`) matches just directories.
+#[derive(Clone, Debug)]
+pub(crate) struct Pattern {
+    glob: Vec<char>,
+    dir_only: bool,
+}
+
+impl Pattern {
+    pub(crate) fn new(pattern: &str) -> Self {
+        let (pattern, dir_only) = match pattern.strip_suffix('/') {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+
+        Self {
+            glob: pattern.chars().collect(),
+            dir_only,
+        }
+    }
+
+    pub(crate) fn matches(&self, name: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let name = name.chars().collect::<Vec<_>>();
+
+        // NOTE: on mismatch, backtrack to the last `*` and let it consume one more character
+        let (mut p, mut n) = (0, 0);
+        let mut star = None;
+
+        while n < name.len() {
+            match self.glob.get(p) {
+                Some('*') => {
+                    star = Some((p, n));
+                    p += 1;
+                }
+                Some(&c) if c == '?' || c == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match star {
+                    Some((sp, sn)) => {
+                        star = Some((sp, sn + 1));
+                        p = sp + 1;
+                        n = sn + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        self.glob[p..].iter().all(|&c| c == '*')
+    }
+}
+
+/// Serve (read-only) files in given directory.
+///
+/// The file path relative to `dir` is taken from the (last) path parameter of the route.
+pub(crate) fn serve_dir(dir: impl Into<PathBuf>, opts: ServeOptions) -> MethodRouter {
+    let dir: Arc<Path> = Arc::from(dir.into());
+    serve(dir, Arc::new(opts))
+}
+
+fn serve(dir: Arc<Path>, opts: Arc<ServeOptions>) -> MethodRouter {
+    router::get(
+        move |extract::Path(file): FilePath,
+              method: Method,
+              headers: HeaderMap,
+              resp: ResponseBuilder| {
+            get_file(
+                Arc::clone(&dir),
+                file,
+                method,
+                headers,
+                Arc::clone(&opts),
+                resp,
+            )
+        },
+    )
+}
+
+/// Serve, upload and delete files in given directory.
+///
+/// The file path relative to `dir` is taken from the (last) path parameter of the route. If it's
+/// empty, the files stored in `dir` are listed (see [`list_files`]). Uploads can be sent in parts
+/// by `PUT` or `PATCH` with `Content-Range` (see [`write_range`]).
+pub(crate) fn files(dir: impl Into<PathBuf>, opts: ServeOptions) -> MethodRouter {
+    let dir: Arc<Path> = Arc::from(dir.into());
+
+    let opts = Arc::new(ServeOptions {
+        list_files: true,
+        ..opts
+    });
+
+    let get = serve(Arc::clone(&dir), Arc::clone(&opts));
+
+    let post = {
+        let (dir, opts) = (Arc::clone(&dir), Arc::clone(&opts));
+        move |extract::Path(file): FilePath, headers: HeaderMap, resp, extract::Body(body)| {
+            let (dir, opts) = (Arc::clone(&dir), Arc::clone(&opts));
+            async move {
+                let file = resolve(&dir, file, &opts).await?;
+                upload_file(file, opts, headers, resp, body).await
+            }
+        }
+    };
+
+    let put = {
+        let (dir, opts) = (Arc::clone(&dir), Arc::clone(&opts));
+        move |extract::Path(file): FilePath, headers: HeaderMap, resp, extract::Body(body)| {
+            let (dir, opts) = (Arc::clone(&dir), Arc::clone(&opts));
+            async move {
+                let file = resolve(&dir, file, &opts).await?;
+                put_file(file, opts, headers, resp, body).await
+            }
+        }
+    };
+
+    let patch = {
+        let (dir, opts) = (Arc::clone(&dir), Arc::clone(&opts));
+        move |extract::Path(file): FilePath, headers: HeaderMap, resp, extract::Body(body)| {
+            let (dir, opts) = (Arc::clone(&dir), Arc::clone(&opts));
+            async move {
+                let file = resolve(&dir, file, &opts).await?;
+                patch_file(file, opts, headers, resp, body).await
+            }
+        }
+    };
+
+    let delete = move |extract::Path(file): FilePath, headers: HeaderMap, resp| {
+        let (dir, opts) = (Arc::clone(&dir), Arc::clone(&opts));
+        async move {
+            let file = resolve(&dir, file, &opts).await?;
+            delete_file(file, headers, resp).await
+        }
+    };
+
+    get.post(post).put(put).patch(patch).delete(delete)
+}
+
+/// Returns `true` iff given relative path can't escape the directory it's joined to (e.g., by
+/// `..` or a root decoded from `%2F` in the path parameter)
+fn is_contained(file: &Path) -> bool {
+    file.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Resolve the requested file within `dir`, returns `None` if no file name was given.
+///
+/// Hidden files can't be modified any more than read, so these fail with `404 Not Found` as if
+/// they did not exist (see [`ServeOptions::is_hidden_path`]).
+async fn resolve(
+    dir: &Path,
+    file: PathBuf,
+    opts: &ServeOptions,
+) -> Result<Option<PathBuf>, ServerError> {
+    if file.as_os_str().is_empty() {
+        return Ok(None);
+    }
+
+    if !is_contained(&file) {
+        return Err(ServerError::NotFound);
+    }
+
+    let path = dir.join(&file);
+    let is_dir = stat(&path).await.is_ok_and(|stat| stat.meta.is_dir());
+
+    if opts.is_hidden_path(&file, is_dir) {
+        return Err(ServerError::NotFound);
+    }
+
+    Ok(Some(path))
+}
+
+/// Serve given file within `dir`, honoring conditional and range requests.
+///
+/// Directories are served by their index file if there's any and listed otherwise, if enabled
+/// by the options (see [`list_dir`]).
+async fn get_file(
+    dir: Arc<Path>,
+    file: PathBuf,
+    method: Method,
+    headers: HeaderMap,
+    opts: Arc<ServeOptions>,
+    resp: ResponseBuilder,
+) -> Result<Response, ServerError> {
+    if !opts.is_authorized(&headers) {
+        let realm = opts.auth.as_ref().map(|(realm, _)| realm.clone());
+        return Err(ServerError::Unauthorized(realm.unwrap_or_default()));
+    }
+
+    if !is_contained(&file) {
+        return Err(ServerError::NotFound);
+    }
+
+    let mut path = dir.join(&file);
+    let mut stat = stat(&path).await.ok();
+
+    let is_dir = stat.as_ref().is_some_and(|stat| stat.meta.is_dir());
+
+    if opts.is_hidden_path(&file, is_dir) {
+        return Err(ServerError::NotFound);
+    }
+
+    if is_dir {
+        let mut index = None;
+
+        for name in opts
+            .index
+            .iter()
+            .filter(|name| !opts.is_hidden(name, false))
+        {
+            let candidate = path.join(name);
+            match self::stat(&candidate).await {
+                Ok(found) if found.meta.is_file() => {
+                    index = Some((candidate, found));
+                    break;
+                }
+                _ => continue,
+            }
+        }
+
+        match index {
+            Some((index, found)) => {
+                path = index;
+                stat = Some(found);
+            }
+            None if opts.autoindex => return list_dir(&path, &file, &headers, &opts, resp).await,
+            None if opts.list_files && file.as_os_str().is_empty() => {
+                return list_files(&path, &opts, resp).await;
+            }
+            None => return Err(ServerError::NotFound),
+        }
+    }
+
+    // NOTE: browsers would download HTML pages served as binary data instead of displaying them
+    let html = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm"));
+
+    let stat = match stat {
+        Some(stat) if stat.meta.is_file() => stat,
+        _ => return Err(ServerError::NotFound),
+    };
+
+    // NOTE: small files are read just once and then served from memory until they're modified
+    let cached = cached(&path, &stat.meta);
+
+    let mut resp = match &cached {
+        Some(file) => {
+            let body = FileBody::in_memory(file.contents.clone(), stat.meta.clone());
+            resp.status(StatusCode::OK)
+                .with_content_type(MimeType::OctetStream, body)
+        }
+        None => resp.status(StatusCode::OK).file(path.clone()).await?,
+    };
+
+    let Body::File(body) = &mut resp.body else {
+        return Ok(resp);
+    };
+
+    let cached = match cached {
+        Some(file) => Some(file),
+        None => cache(&path, body).await?,
+    };
+
+    if html {
+        resp.headers = resp.headers.assoc(CONTENT_TYPE, MimeType::TextHtml);
+    } else if opts.sniff {
+        let sniffed = match &cached {
+            Some(file) => file.sniffed,
+            None => sniff(&body.peek(SNIFF_LEN).await?),
+        };
+
+        if let Some(content_type) = sniffed {
+            resp.headers = resp.headers.assoc(CONTENT_TYPE, content_type);
+        }
+    }
+
+    let etag = match &cached {
+        Some(file) => file.etag.clone(),
+        None => stat.etag(body.metadata()),
+    };
+    let last_modified = body.metadata().modified().ok().map(HttpDate::from);
+
+    resp.headers = resp.headers.assoc(ACCEPT_RANGES, BYTES);
+
+    if let Some(max_age) = opts.max_age {
+        let cache_control = format!("max-age={}", max_age.as_secs());
+        resp.headers = resp.headers.assoc(CACHE_CONTROL, cache_control);
+    }
+
+    if let Some(etag) = &etag {
+        resp.headers = resp.headers.insert(etag.clone());
+    }
+
+    if let Some(last_modified) = last_modified {
+        resp.headers = resp.headers.insert(LastModified(last_modified));
+    }
+
+    // NOTE: If-Modified-Since is evaluated only without If-None-Match (RFC 9110, section 13.2.2)
+    if headers.get(IF_NONE_MATCH).is_some() {
+        let matched = etag
+            .as_ref()
+            .zip(headers.extract::<IfNoneMatch>())
+            .and_then(|(etag, tags)| tags.find(etag));
+
+        if let Some(tag) = matched {
+            return Ok(not_modified(resp, Some(tag)));
+        }
+    } else if let Some(IfModifiedSince(since)) = headers.extract() {
+        // dates in the future are invalid and thus ignored
+        if last_modified.is_some_and(|modified| modified <= since) && since <= HttpDate::now() {
+            return Ok(not_modified(resp, etag));
+        }
+    }
+
+    // NOTE: range requests are defined just for GET (see RFC 9110, section 14.2)
+    let range = headers
+        .extract::<Range>()
+        .filter(|_| method == Method::Get)
+        .filter(|_| {
+            // the client's partial copy is outdated unless If-Range matches, so the whole file
+            // is sent instead
+            headers.get(IF_RANGE).is_none()
+                || headers
+                    .extract::<IfRange>()
+                    .is_some_and(|if_range| if_range.matches(etag.as_ref(), last_modified))
+        });
+
+    match range {
+        Some(range) => partial(resp, range).await,
+        None => Ok(precompressed(resp, &path, &headers).await),
+    }
+}
+
+/// Replace the file response body with a precompressed variant of the file (e.g., `foo.txt.gz`
+/// for `foo.txt`) in the most preferred encoding accepted by the client, if there's any.
+///
+/// Variants which are older than the file itself are considered stale and ignored. Otherwise,
+/// the response is left to be compressed on the fly (if at all).
+async fn precompressed(mut resp: Response, path: &Path, headers: &HeaderMap) -> Response {
+    let Some(accepted) = headers.extract::<AcceptEncoding>() else {
+        return resp;
+    };
+
+    let Body::File(file) = &resp.body else {
+        return resp;
+    };
+
+    let modified = file.metadata().modified().ok();
+
+    for encoding in accepted.iter() {
+        let Some(ext) = encoding.file_extension() else {
+            continue;
+        };
+
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(".");
+        variant.push(ext);
+        let variant = PathBuf::from(variant);
+
+        let Ok(file) = fs::File::open(&variant).await else {
+            continue;
+        };
+
+        let Ok(Body::File(mut body)) = Body::file(file).await else {
+            continue;
+        };
+
+        let fresh = body.metadata().is_file()
+            && match (body.metadata().modified().ok(), modified) {
+                (Some(variant), Some(modified)) => variant >= modified,
+                _ => false,
+            };
+
+        if !fresh {
+            continue;
+        }
+
+        body.set_precompressed();
+
+        let encoding = ContentEncoding::from(encoding);
+
+        if let Some(etag) = resp.headers.extract::<ETag>() {
+            resp.headers = resp.headers.insert(etag.encoded(&encoding));
+        }
+
+        resp.body = Body::from(body);
+        resp.headers = resp
+            .headers
+            .insert(encoding)
+            .insert(resp.body.content_length());
+
+        break;
+    }
+
+    resp
+}
+
+/// Entry of a directory listing
+#[derive(Debug)]
+struct DirEntry {
+    name: String,
+    is_dir: bool,
+    len: u64,
+    modified: Option<HttpDate>,
+}
+
+/// Read entries of given directory, sub-directories first and then ordered by name
+async fn read_dir(path: &Path) -> std::io::Result<Vec<DirEntry>> {
+    let mut dir = fs::read_dir(path).await?;
+    let mut entries = Vec::new();
+
+    while let Some(entry) = dir.next_entry().await? {
+        // NOTE: follows symlinks, so these are listed as what they point to (broken are skipped)
+        let Ok(meta) = fs::metadata(entry.path()).await else {
+            continue;
+        };
+
+        entries.push(DirEntry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: meta.is_dir(),
+            len: meta.len(),
+            modified: meta.modified().ok().map(HttpDate::from),
+        });
+    }
+
+    entries.sort_unstable_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    Ok(entries)
+}
+
+/// Respond with a listing of directory `path` which was requested as `file` (relative to the
+/// served directory).
+///
+/// The listing is an HTML page unless the client prefers JSON (based on the `Accept` header).
+async fn list_dir(
+    path: &Path,
+    file: &Path,
+    headers: &HeaderMap,
+    opts: &ServeOptions,
+    resp: ResponseBuilder,
+) -> Result<Response, ServerError> {
+    let mut entries = match read_dir(path).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => return Err(ServerError::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+
+    entries.retain(|entry| !opts.is_hidden(&entry.name, entry.is_dir));
+
+    let json = headers
+        .extract::<Accept>()
+        .is_some_and(|accept| accept.quality("application/json") > accept.quality("text/html"));
+
+    let (content_type, listing) = if json {
+        (MimeType::Json, list_json(file, entries))
+    } else {
+        (MimeType::TextHtml, list_html(file, entries))
+    };
+
+    Ok(resp
+        .status(StatusCode::OK)
+        .header(VARY, ACCEPT)
+        .with_content_type(content_type, listing))
+}
+
+/// Respond with a JSON listing of (just) the files stored in directory `path` (see [`list_json`])
+async fn list_files(
+    path: &Path,
+    opts: &ServeOptions,
+    resp: ResponseBuilder,
+) -> Result<Response, ServerError> {
+    let mut entries = read_dir(path).await?;
+
+    entries.retain(|entry| !entry.is_dir && !opts.is_hidden(&entry.name, false));
+
+    Ok(resp
+        .status(StatusCode::OK)
+        .json(list_json(Path::new(""), entries)))
+}
+
+/// Render the directory listing as an HTML page
+fn list_html(file: &Path, entries: Vec<DirEntry>) -> String {
+    let title = escape_html(&format!("Index of /{}", file.display()));
+
+    // NOTE: links are relative, so these must include the directory name if the request path
+    //       does not end with a slash (e.g., `/files/dir` instead of `/files/dir/`)
+    let base = match file.file_name() {
+        Some(name) if !file.as_os_str().as_encoded_bytes().ends_with(b"/") => {
+            format!("{}/", name.to_string_lossy())
+        }
+        _ => String::new(),
+    };
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+         <body>\n<h1>{title}</h1>\n<table>\n\
+         <tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n"
+    );
+
+    if !file.as_os_str().is_empty() {
+        let parent = if base.is_empty() { "../" } else { "./" };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{parent}\">../</a></td><td></td><td></td></tr>\n"
+        ));
+    }
+
+    for entry in entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        let name = escape_html(&entry.name);
+        let len = if entry.is_dir {
+            "-".to_string()
+        } else {
+            entry.len.to_string()
+        };
+        let modified = entry.modified.map(|m| m.to_string()).unwrap_or_default();
+
+        html.push_str(&format!(
+            "<tr><td><a href=\"{base}{name}{slash}\">{name}{slash}</a></td>\
+             <td>{len}</td><td>{modified}</td></tr>\n"
+        ));
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+
+    html
+}
+
+/// Render the directory listing as a JSON object.
+///
+/// Each entry has a `name`, `type` (either `file` or `directory`), `size` in bytes (`null` for
+/// directories) and the time of last modification `mtime` in seconds since the Unix epoch.
+fn list_json(file: &Path, entries: Vec<DirEntry>) -> String {
+    let path = escape_json(&format!("/{}", file.display()));
+
+    let entries = entries.into_iter().map(|entry| {
+        let name = escape_json(&entry.name);
+
+        let (ty, size) = if entry.is_dir {
+            ("directory", "null".to_string())
+        } else {
+            ("file", entry.len.to_string())
+        };
+
+        let mtime = entry
+            .modified
+            .and_then(|m| SystemTime::from(m).duration_since(UNIX_EPOCH).ok())
+            .map_or_else(|| "null".to_string(), |m| m.as_secs().to_string());
+
+        format!(r#"{{"name":"{name}","type":"{ty}","size":{size},"mtime":{mtime}}}"#)
+    });
+
+    let entries = itertools::join(entries, ",");
+
+    format!("{{\"path\":\"{path}\",\"entries\":[{entries}]}}\n")
+}
+
+/// Escape characters with a special meaning in HTML text and attribute values
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape characters which are not allowed in JSON strings
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whole contents of a small file along with what's derived from them
+#[derive(Clone, Debug)]
+struct CachedFile {
+    contents: Bytes,
+    /// Content type detected from the contents (see [`sniff`])
+    sniffed: Option<MimeType>,
+    etag: Option<ETag>,
+}
+
+/// Cached file as of given modification
+#[derive(Debug)]
+struct CacheEntry {
+    len: u64,
+    modified: Option<SystemTime>,
+    file: CachedFile,
+    /// Logical time of the last use of the entry
+    used: u64,
+}
+
+impl CacheEntry {
+    /// Returns `true` iff the file has not changed since it was cached
+    #[inline]
+    fn is_fresh(&self, meta: &Metadata) -> bool {
+        self.len == meta.len() && self.modified == meta.modified().ok()
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<PathBuf, CacheEntry>,
+    /// Total size of the cached contents in bytes
+    size: usize,
+    clock: u64,
+}
+
+impl CacheState {
+    #[inline]
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.size -= entry.file.contents.len();
+        }
+    }
+}
+
+/// Contents of small files which are served repeatedly, the least recently used ones are evicted
+/// once the total size would exceed the budget
+#[derive(Debug)]
+struct Cache {
+    budget: usize,
+    state: Mutex<CacheState>,
+}
+
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+/// Keep up to `budget` bytes of small served files in memory (`0` to read them every time).
+///
+/// Only the first call takes effect.
+pub(crate) fn init_cache(budget: usize) {
+    if budget > 0 {
+        let _ = CACHE.set(Cache {
+            budget,
+            state: Mutex::default(),
+        });
+    }
+}
+
+/// Cached file at given path, unless it's not cached or has been modified since
+fn cached(path: &Path, meta: &Metadata) -> Option<CachedFile> {
+    let cache = CACHE.get()?;
+
+    let mut state = cache.state.lock().expect("file cache");
+    state.clock += 1;
+    let now = state.clock;
+
+    match state.entries.get_mut(path) {
+        Some(entry) if entry.is_fresh(meta) => {
+            entry.used = now;
+            Some(entry.file.clone())
+        }
+        Some(_) => {
+            state.remove(path);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Read the whole file served by `body` into the cache if it's small enough and switch the body
+/// over to the contents in memory
+async fn cache(path: &Path, body: &mut FileBody) -> std::io::Result<Option<CachedFile>> {
+    let Some(cache) = CACHE.get() else {
+        return Ok(None);
+    };
+
+    let len = body.file_len();
+    if body.is_in_memory() || len > MAX_CACHED_FILE || len as usize > cache.budget {
+        return Ok(None);
+    }
+
+    let contents = body.peek(len as usize).await?;
+    if contents.len() as u64 != len {
+        // NOTE: the file has changed in the meantime, so it's not worth caching
+        return Ok(None);
+    }
+
+    let meta = body.metadata().clone();
+
+    let file = CachedFile {
+        sniffed: sniff(&contents[..contents.len().min(SNIFF_LEN)]),
+        etag: file_etag(&meta),
+        contents,
+    };
+
+    {
+        let mut state = cache.state.lock().expect("file cache");
+
+        // replace any outdated entry of the file
+        state.remove(path);
+
+        while state.size + file.contents.len() > cache.budget {
+            let lru = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(path, _)| path.clone());
+
+            match lru {
+                Some(lru) => state.remove(&lru),
+                None => break,
+            }
+        }
+
+        state.clock += 1;
+        state.size += file.contents.len();
+
+        let entry = CacheEntry {
+            len,
+            modified: meta.modified().ok(),
+            file: file.clone(),
+            used: state.clock,
+        };
+
+        state.entries.insert(path.to_path_buf(), entry);
+    }
+
+    *body = FileBody::in_memory(file.contents.clone(), meta);
+
+    Ok(Some(file))
+}
+
+/// Metadata of a file along with its entity tag
+#[derive(Clone, Debug)]
+struct Stat {
+    meta: Metadata,
+    etag: Option<ETag>,
+}
+
+impl Stat {
+    /// Entity tag of the file with given metadata, which may be newer than this one
+    #[inline]
+    fn etag(&self, meta: &Metadata) -> Option<ETag> {
+        if self.meta.len() == meta.len() && self.meta.modified().ok() == meta.modified().ok() {
+            self.etag.clone()
+        } else {
+            file_etag(meta)
+        }
+    }
+}
+
+/// Recently looked up metadata of files, which is considered valid for a fixed time
+#[derive(Debug)]
+struct StatCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<PathBuf, (Stat, Instant)>>,
+}
+
+static STATS: OnceLock<StatCache> = OnceLock::new();
+
+/// Reuse the metadata of served files for up to `ttl` (zero to look it up for every request).
+///
+/// Files modified by other means than through the server may thus be served as of their previous
+/// version for up to `ttl`. Only the first call takes effect.
+pub(crate) fn init_stat_cache(ttl: Duration) {
+    if !ttl.is_zero() {
+        let _ = STATS.set(StatCache {
+            ttl,
+            entries: Mutex::default(),
+        });
+    }
+}
+
+/// Metadata of given file, which may be up to the TTL of the cache old (see [`init_stat_cache`])
+async fn stat(path: &Path) -> std::io::Result<Stat> {
+    if let Some(cache) = STATS.get() {
+        let entries = cache.entries.lock().expect("stat cache");
+        if let Some((stat, at)) = entries.get(path) {
+            if at.elapsed() < cache.ttl {
+                return Ok(stat.clone());
+            }
+        }
+    }
+
+    let meta = fs::metadata(path).await?;
+    let stat = Stat {
+        etag: file_etag(&meta),
+        meta,
+    };
+
+    if let Some(cache) = STATS.get() {
+        let mut entries = cache.entries.lock().expect("stat cache");
+
+        if entries.len() >= MAX_STATS {
+            entries.retain(|_, (_, at)| at.elapsed() < cache.ttl);
+        }
+
+        if entries.len() < MAX_STATS {
+            entries.insert(path.to_path_buf(), (stat.clone(), Instant::now()));
+        }
+    }
+
+    Ok(stat)
+}
+
+/// Forget the metadata of given file, so that its modification is visible immediately
+fn forget_stat(path: &Path) {
+    if let Some(cache) = STATS.get() {
+        cache.entries.lock().expect("stat cache").remove(path);
+    }
+}
+
+/// Strong entity tag of a file derived from its size and modification time
+fn file_etag(meta: &Metadata) -> Option<ETag> {
+    let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+    Some(ETag::strong(format!(
+        "{:x}-{:x}.{:x}",
+        meta.len(),
+        mtime.as_secs(),
+        mtime.subsec_nanos()
+    )))
+}
+
+/// Turn the file response into `304 Not Modified` without the body.
+///
+/// The response carries given tag (if any) which matches the client's cached representation.
+fn not_modified(mut resp: Response, etag: Option<ETag>) -> Response {
+    resp.status = StatusCode::NOT_MODIFIED;
+    resp.body = Body::empty();
+    resp.headers = resp
+        .headers
+        .remove(CONTENT_TYPE)
+        .remove(CONTENT_LENGTH)
+        .remove(CONTENT_ENCODING);
+
+    if let Some(etag) = etag {
+        resp.headers = resp.headers.insert(etag);
+    }
+
+    resp
+}
+
+/// Restrict the file response to the requested ranges.
+///
+/// Responds with `206 Partial Content` if any range is satisfiable and with
+/// `416 Range Not Satisfiable` if none is. Multiple ranges are sent as a `multipart/byteranges`
+/// body, unless there are too many of them in which case the whole file is sent.
+async fn partial(mut resp: Response, range: Range) -> Result<Response, ServerError> {
+    let Body::File(body) = &mut resp.body else {
+        return Ok(resp);
+    };
+
+    let len = body.file_len();
+
+    let (first, last) = match range.resolve(len).as_slice() {
+        [] => {
+            resp.status = StatusCode::RANGE_NOT_SATISFIABLE;
+            resp.body = Body::empty();
+            resp.headers = resp
+                .headers
+                .remove(CONTENT_TYPE)
+                .remove(CONTENT_ENCODING)
+                .assoc(CONTENT_RANGE, format!("bytes */{len}"))
+                .insert(ContentLength::from(0));
+            return Ok(resp);
+        }
+        [range] => *range,
+        ranges if ranges.len() > MAX_RANGES => return Ok(resp),
+        ranges => return Ok(multipart(resp, ranges.to_vec())),
+    };
+
+    body.slice(first, last).await?;
+
+    resp.status = StatusCode::PARTIAL_CONTENT;
+
+    // NOTE: the range refers to the file as it is, so the part must be sent without compression
+    resp.headers = resp
+        .headers
+        .remove(CONTENT_ENCODING)
+        .assoc(CONTENT_RANGE, format!("bytes {first}-{last}/{len}"))
+        .insert(resp.body.content_length());
+
+    Ok(resp)
+}
+
+/// Respond with a `multipart/byteranges` body consisting of given ranges of the file.
+///
+/// The parts are read from the file as the body is being sent (see RFC 9110, section 14.6).
+fn multipart(mut resp: Response, ranges: Vec<(u64, u64)>) -> Response {
+    let Body::File(mut file) = std::mem::replace(&mut resp.body, Body::empty()) else {
+        unreachable!("multipart body of a non-file response");
+    };
+
+    let len = file.file_len();
+    let boundary = boundary();
+
+    let content_type = resp
+        .headers
+        .get(CONTENT_TYPE)
+        .unwrap_or_else(|| MimeType::OctetStream.into());
+
+    let (tx, body) = Body::channel(4);
+
+    let delimiter = format!("--{boundary}");
+    tokio::spawn(async move {
+        for (first, last) in ranges {
+            let mut head = BytesMut::with_capacity(128);
+            head.extend_from_slice(delimiter.as_bytes());
+            head.extend_from_slice(b"\r\nContent-Type: ");
+            head.extend_from_slice(&content_type);
+            head.extend_from_slice(
+                format!("\r\nContent-Range: bytes {first}-{last}/{len}").as_bytes(),
+            );
+            head.extend_from_slice(b"\r\n\r\n");
+
+            if tx.send(head).await.is_err() {
+                return;
+            }
+
+            if let Err(error) = send_part(&mut file, first, last, &tx).await {
+                eprintln!("failed to send file range {first}-{last}: {error}");
+                return;
+            }
+
+            if tx.send(Bytes::from_static(b"\r\n")).await.is_err() {
+                return;
+            }
+        }
+
+        let _ = tx.send(format!("{delimiter}--\r\n")).await;
+    });
+
+    resp.status = StatusCode::PARTIAL_CONTENT;
+    resp.body = body;
+    resp.headers = resp
+        .headers
+        .remove(CONTENT_ENCODING)
+        .remove(CONTENT_LENGTH)
+        .assoc(
+            CONTENT_TYPE,
+            format!("multipart/byteranges; boundary={boundary}"),
+        );
+
+    resp
+}
+
+/// Send bytes `first..=last` of the file in chunks
+async fn send_part(
+    file: &mut FileBody,
+    first: u64,
+    last: u64,
+    tx: &BodySender,
+) -> anyhow::Result<()> {
+    file.slice(first, last).await?;
+
+    let mut reader = file.reader();
+
+    loop {
+        let mut chunk = BytesMut::with_capacity(CHUNK_SIZE);
+        if reader.read_buf(&mut chunk).await? == 0 {
+            return Ok(());
+        }
+        tx.send(chunk).await?;
+    }
+}
+
+/// Unique boundary delimiting parts of a multipart body
+fn boundary() -> String {
+    use std::hash::{BuildHasher as _, Hasher as _};
+
+    // NOTE: each random state is seeded differently, which is enough to make the boundary unique
+    let hash = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+
+    format!("{hash:016x}")
+}
+
+/// Guess the content type from leading bytes of a file (`None` if it's not recognized)
+fn sniff(data: &[u8]) -> Option<MimeType> {
+    const SIGNATURES: [(&[u8], MimeType); 6] = [
+        (b"\x89PNG\r\n\x1a\n", MimeType::Png),
+        (b"\xFF\xD8\xFF", MimeType::Jpeg),
+        (b"GIF87a", MimeType::Gif),
+        (b"GIF89a", MimeType::Gif),
+        (b"%PDF-", MimeType::Pdf),
+        (b"\x1F\x8B", MimeType::Gzip),
+    ];
+
+    if data.is_empty() {
+        return None;
+    }
+
+    if let Some((_, content_type)) = SIGNATURES.iter().find(|(sig, _)| data.starts_with(sig)) {
+        return Some(*content_type);
+    }
+
+    // NOTE: the data may end in the middle of a multi-byte character
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&data[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+
+    let binary = text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0C' | '\x1B'));
+
+    (!binary).then_some(MimeType::TextPlain)
+}
+
+async fn upload_file(
+    file: Option<PathBuf>,
+    opts: Arc<ServeOptions>,
+    headers: HeaderMap,
+    resp: ResponseBuilder,
+    body: Body,
+) -> Result<Response, ServerError> {
+    let file = file.ok_or_else(|| ServerError::bad_request("missing file name"))?;
+
+    opts.check_upload(&file, &headers)?;
+    precondition(&file, &headers).await?;
+
+    let expected = digest::expected(&headers).map_err(ServerError::BadRequest)?;
+
+    replace_file(&file, body, &expected, resp, StatusCode::CREATED).await
+}
+
+/// Create or replace given file with the request body, or just a part of it if the request has
+/// `Content-Range` (see [`write_range`]).
+///
+/// Responds with `201 Created` if the file did not exist before, and with `204 No Content` if
+/// an existing file has been overwritten.
+async fn put_file(
+    file: Option<PathBuf>,
+    opts: Arc<ServeOptions>,
+    headers: HeaderMap,
+    resp: ResponseBuilder,
+    body: Body,
+) -> Result<Response, ServerError> {
+    let file = file.ok_or_else(|| ServerError::bad_request("missing file name"))?;
+
+    opts.check_upload(&file, &headers)?;
+    precondition(&file, &headers).await?;
+
+    let expected = digest::expected(&headers).map_err(ServerError::BadRequest)?;
+
+    if headers.get(CONTENT_RANGE).is_some() {
+        return write_range(file, headers, &expected, resp, body).await;
+    }
+
+    let status = match fs::metadata(&file).await {
+        Ok(_) => StatusCode::NO_CONTENT,
+        Err(e) if e.kind() == ErrorKind::NotFound => StatusCode::CREATED,
+        Err(e) => return Err(e.into()),
+    };
+
+    replace_file(&file, body, &expected, resp, status).await
+}
+
+/// Replace given file with the request body, which is written into a temporary file next to it
+/// first (see [`staging_file`]).
+///
+/// The file is replaced only once the whole body has been received and its digests match those
+/// `expected` by the client, so that an interrupted or corrupted upload leaves any previous
+/// contents intact and readers never see a partially written file.
+async fn replace_file(
+    file: &Path,
+    body: Body,
+    expected: &[digest::Expected],
+    resp: ResponseBuilder,
+    status: StatusCode,
+) -> Result<Response, ServerError> {
+    let current = fs::metadata(file).await.ok();
+
+    if current.as_ref().is_some_and(Metadata::is_dir) {
+        return Err(ServerError::Conflict(
+            "cannot replace a directory".to_string(),
+        ));
+    }
+
+    let (staged, opened) = staging_file(file).await?;
+
+    let computed = match write_file(&staged, opened, None, body, expected).await {
+        Ok(computed) => computed,
+        Err(e) => {
+            let _ = fs::remove_file(&staged).await;
+            return Err(e.into());
+        }
+    };
+
+    let digest = match verify(expected, &computed) {
+        Ok(digest) => digest,
+        Err(digest) => {
+            let _ = fs::remove_file(&staged).await;
+            return Ok(digest_mismatch(resp, digest));
+        }
+    };
+
+    // NOTE: the replaced file keeps its permissions
+    if let Some(meta) = current {
+        let _ = fs::set_permissions(&staged, meta.permissions()).await;
+    }
+
+    if let Err(e) = fs::rename(&staged, file).await {
+        let _ = fs::remove_file(&staged).await;
+        return Err(e.into());
+    }
+
+    forget_stat(file);
+
+    uploaded(file, digest, resp, status).await
+}
+
+/// Create a new hidden file in the directory of given `path`, into which an upload is written
+/// before it replaces the file (see [`replace_file`]).
+///
+/// Being on the same file system, the file can then be atomically renamed to the `path`.
+async fn staging_file(path: &Path) -> std::io::Result<(PathBuf, fs::File)> {
+    static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "missing file name"))?;
+
+    let mut staged = OsString::from(".");
+    staged.push(name);
+    staged.push(format!(
+        ".{}-{}.upload",
+        std::process::id(),
+        UPLOADS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let staged = path.with_file_name(staged);
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&staged)
+        .await?;
+
+    Ok((staged, file))
+}
+
+/// Write a part of given file, which must be specified by `Content-Range` (see [`write_range`])
+async fn patch_file(
+    file: Option<PathBuf>,
+    opts: Arc<ServeOptions>,
+    headers: HeaderMap,
+    resp: ResponseBuilder,
+    body: Body,
+) -> Result<Response, ServerError> {
+    if headers.get(CONTENT_RANGE).is_none() {
+        return Err(ServerError::bad_request("missing Content-Range"));
+    }
+    put_file(file, opts, headers, resp, body).await
+}
+
+/// Write the request body into given file at the position specified by `Content-Range`.
+///
+/// This allows clients to resume interrupted uploads: the length of the part stored so far is
+/// reported by `HEAD` as `Content-Length`, so the rest can be sent starting from there. A part
+/// which would leave a gap in the file is rejected with `416 Range Not Satisfiable` (with the
+/// current length as `Content-Range: bytes */len`). Once the last byte of the complete length
+/// is written, anything beyond it is truncated.
+///
+/// Unlike whole file uploads, the stored part is kept if the transfer is interrupted, unless the
+/// request has digests which can only be verified once the whole part has been received.
+async fn write_range(
+    file: PathBuf,
+    headers: HeaderMap,
+    expected: &[digest::Expected],
+    resp: ResponseBuilder,
+    body: Body,
+) -> Result<Response, ServerError> {
+    let range = headers
+        .extract::<ContentRange>()
+        .ok_or_else(|| ServerError::bad_request("invalid Content-Range"))?;
+
+    let len: u64 = headers.read(CONTENT_LENGTH).unwrap_or_default();
+
+    if len != range.last - range.first + 1 {
+        return Err(ServerError::bad_request(
+            "Content-Range does not match the body length",
+        ));
+    }
+
+    let current = match fs::metadata(&file).await {
+        Ok(meta) if meta.is_dir() => {
+            return Err(ServerError::Conflict(
+                "cannot write a directory".to_string(),
+            ));
+        }
+        Ok(meta) => Some(meta.len()),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let stored = current.unwrap_or_default();
+
+    if range.first > stored {
+        return Ok(resp
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(CONTENT_RANGE, format!("bytes */{stored}").into())
+            .build());
+    }
+
+    let digest = if expected.is_empty() {
+        let opened = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&file)
+            .await?;
+
+        write_file(&file, opened, Some(range.first), body, expected).await?;
+
+        None
+    } else {
+        // NOTE: the part is received into a temporary file first and copied into the file only
+        //  if its digests match, so that a corrupted part does not overwrite any stored data
+        let (staged, opened) = staging_file(&file).await?;
+
+        let computed = match write_file(&staged, opened, None, body, expected).await {
+            Ok(computed) => computed,
+            Err(e) => {
+                let _ = fs::remove_file(&staged).await;
+                return Err(e.into());
+            }
+        };
+
+        let digest = match verify(expected, &computed) {
+            Ok(digest) => digest,
+            Err(digest) => {
+                let _ = fs::remove_file(&staged).await;
+                return Ok(digest_mismatch(resp, digest));
+            }
+        };
+
+        let copied = copy_part(&staged, &file, range.first).await;
+        let _ = fs::remove_file(&staged).await;
+        copied?;
+
+        digest
+    };
+
+    if range.complete == Some(range.last + 1) {
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&file)
+            .await?
+            .set_len(range.last + 1)
+            .await?;
+        forget_stat(&file);
+    }
+
+    let status = if current.is_some() {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    };
+
+    uploaded(&file, digest, resp, status).await
+}
+
+/// Write the contents of the `part` file into given file at the `offset`
+async fn copy_part(part: &Path, file: &Path, offset: u64) -> std::io::Result<()> {
+    let part = fs::File::open(part).await?;
+    let len = part.metadata().await?.len();
+
+    let opened = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(file)
+        .await?;
+
+    write_file(
+        file,
+        opened,
+        Some(offset),
+        Body::reader(part, Some(len)),
+        &[],
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn delete_file(
+    file: Option<PathBuf>,
+    headers: HeaderMap,
+    resp: ResponseBuilder,
+) -> Result<Response, ServerError> {
+    let file = file.ok_or(ServerError::NotFound)?;
+
+    precondition(&file, &headers).await?;
+
+    if stat(&file).await?.meta.is_dir() {
+        return Err(ServerError::Conflict(
+            "cannot delete a directory".to_string(),
+        ));
+    }
+
+    fs::remove_file(&file).await?;
+    forget_stat(&file);
+
+    Ok(resp.status(StatusCode::NO_CONTENT).build())
+}
+
+/// Write the request body into given file (from the `offset` if given) and compute those of its
+/// digests which are `expected`.
+///
+/// Large bodies are streamed from the client, so the upload can fail in the middle, leaving
+/// the file incomplete.
+async fn write_file(
+    path: &Path,
+    mut file: fs::File,
+    offset: Option<u64>,
+    body: Body,
+    expected: &[digest::Expected],
+) -> std::io::Result<Vec<(digest::Algorithm, Vec<u8>)>> {
+    let mut hashers: Vec<digest::Hasher> = Vec::new();
+    for expected in expected {
+        if hashers.iter().all(|h| h.algorithm() != expected.algorithm) {
+            hashers.push(digest::Hasher::new(expected.algorithm));
+        }
+    }
+
+    if let Some(offset) = offset {
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+    }
+
+    // NOTE: the length of streamed bodies is enforced while reading them from the client
+    let bytes_read = (!body.is_stream()).then(|| body.len());
+
+    let written = FileWriter::new(file)
+        .write_inspect(body, |chunk| {
+            hashers.iter_mut().for_each(|h| h.update(chunk))
+        })
+        .await;
+
+    // NOTE: the file has changed even if the upload failed
+    forget_stat(path);
+
+    let bytes_written = written?;
+
+    if let Some(bytes_read) = bytes_read {
+        debug_assert_eq!(bytes_read, bytes_written, "corrupted file upload");
+    }
+
+    Ok(hashers
+        .into_iter()
+        .map(|h| (h.algorithm(), h.finish()))
+        .collect())
+}
+
+/// Fail with `412 Precondition Failed` unless the current state of given file satisfies the
+/// `If-Match` (or `If-Unmodified-Since` if there's none) precondition of a write request.
+///
+/// This lets clients modify files optimistically, without overwriting concurrent changes made
+/// since they've learned the `ETag` or `Last-Modified` of the file.
+async fn precondition(file: &Path, headers: &HeaderMap) -> Result<(), ServerError> {
+    let has_if_match = headers.get(IF_MATCH).is_some();
+
+    if !has_if_match && headers.get(IF_UNMODIFIED_SINCE).is_none() {
+        return Ok(());
+    }
+
+    let meta = match fs::metadata(file).await {
+        Ok(meta) => Some(meta),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    // NOTE: If-Unmodified-Since is evaluated only without If-Match (RFC 9110, section 13.2.2)
+    let satisfied = if has_if_match {
+        let etag = meta.as_ref().and_then(file_etag);
+        headers
+            .extract::<IfMatch>()
+            .is_some_and(|if_match| if_match.matches(etag.as_ref()))
+    } else {
+        let last_modified = meta
+            .and_then(|meta| meta.modified().ok())
+            .map(HttpDate::from);
+
+        // invalid dates are ignored, as well as files which don't exist
+        match (headers.extract::<IfUnmodifiedSince>(), last_modified) {
+            (Some(IfUnmodifiedSince(since)), Some(modified)) => modified <= since,
+            _ => true,
+        }
+    };
+
+    if satisfied {
+        Ok(())
+    } else {
+        Err(ServerError::PreconditionFailed)
+    }
+}
+
+/// Check the `computed` digests of an upload against those `expected` by the client.
+///
+/// Returns the value of the `Digest` header describing the upload (if any digest has been
+/// computed), or an error with it if any digest does not match the expected one.
+fn verify(
+    expected: &[digest::Expected],
+    computed: &[(digest::Algorithm, Vec<u8>)],
+) -> Result<Option<Bytes>, Bytes> {
+    if computed.is_empty() {
+        return Ok(None);
+    }
+
+    let digest = digest::header_value(computed);
+
+    let valid = expected.iter().all(|expected| {
+        computed.iter().any(|(algorithm, digest)| {
+            *algorithm == expected.algorithm && *digest == expected.digest
+        })
+    });
+
+    if valid {
+        Ok(Some(digest))
+    } else {
+        Err(digest)
+    }
+}
+
+/// Respond with `422 Unprocessable Content` to an upload which has been discarded because its
+/// `digest` does not match the one expected by the client
+fn digest_mismatch(resp: ResponseBuilder, digest: Bytes) -> Response {
+    resp.status(StatusCode::UNPROCESSABLE_CONTENT)
+        .header(DIGEST, digest)
+        .plain(Bytes::from_static(b"content digest mismatch"))
+}
+
+/// Respond to an upload with given `status` and the `digest` of the stored data, along with the
+/// new `ETag` of the file
+async fn uploaded(
+    path: &Path,
+    digest: Option<Bytes>,
+    resp: ResponseBuilder,
+    status: StatusCode,
+) -> Result<Response, ServerError> {
+    let mut resp = resp.status(status);
+
+    if let Some(digest) = digest {
+        resp = resp.header(DIGEST, digest);
+    }
+
+    // NOTE: the stored file is exactly the uploaded representation, so its tag can be sent
+    if let Some(etag) = file_etag(&fs::metadata(path).await?) {
+        resp = resp.header(ETAG, etag.into_header_value());
+    }
+
+    Ok(resp.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    use super::*;
+    use crate::Request;
+
+    /// Directory removed (with its contents) when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("files-{}-{name}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).expect("temporary directory");
+            Self(dir)
+        }
+
+        /// Names of the files in the directory, including hidden ones
+        fn entries(&self) -> Vec<String> {
+            let mut entries = std::fs::read_dir(&self.0)
+                .expect("directory entries")
+                .map(|entry| entry.expect("entry").file_name().to_string_lossy().into())
+                .collect::<Vec<String>>();
+            entries.sort();
+            entries
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn sha256(data: &[u8]) -> Vec<u8> {
+        let mut hasher = digest::Hasher::new(digest::Algorithm::Sha256);
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    fn expected(data: &[u8]) -> Vec<digest::Expected> {
+        vec![digest::Expected {
+            algorithm: digest::Algorithm::Sha256,
+            digest: sha256(data),
+        }]
+    }
+
+    fn builder() -> ResponseBuilder {
+        Response::builder(Request::HTTP_1_1)
+    }
+
+    #[test]
+    fn verify_digests() {
+        let computed = [(digest::Algorithm::Sha256, sha256(b"hello"))];
+        let header = digest::header_value(&computed);
+
+        assert_eq!(verify(&[], &[]), Ok(None));
+        assert_eq!(verify(&[], &computed), Ok(Some(header.clone())));
+        assert_eq!(
+            verify(&expected(b"hello"), &computed),
+            Ok(Some(header.clone()))
+        );
+        assert_eq!(verify(&expected(b"world"), &computed), Err(header));
+    }
+
+    #[tokio::test]
+    async fn staging_files() {
+        let dir = TempDir::new("staging");
+        let path = dir.0.join("a.txt");
+
+        let (first, _) = staging_file(&path).await.expect("staging file");
+        let (second, _) = staging_file(&path).await.expect("staging file");
+
+        assert_ne!(first, second);
+
+        for staged in [first, second] {
+            assert_eq!(staged.parent(), Some(dir.0.as_path()));
+
+            let name = staged.file_name().expect("file name").to_string_lossy();
+            assert!(name.starts_with(".a.txt.") && name.ends_with(".upload"));
+        }
+
+        assert!(staging_file(Path::new("/")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn replace_file_verified() {
+        let dir = TempDir::new("replace");
+        let path = dir.0.join("a.txt");
+
+        std::fs::write(&path, "old").expect("original file");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640))
+            .expect("permissions");
+
+        let body = Body::bytes("new contents");
+        let resp = replace_file(
+            &path,
+            body,
+            &expected(b"new contents"),
+            builder(),
+            StatusCode::NO_CONTENT,
+        )
+        .await
+        .expect("replaced file");
+
+        assert_eq!(resp.status, StatusCode::NO_CONTENT);
+        assert!(resp.headers.get(DIGEST).is_some());
+        assert!(resp.headers.get(ETAG).is_some());
+
+        assert_eq!(std::fs::read(&path).expect("contents"), b"new contents");
+
+        let mode = std::fs::metadata(&path)
+            .expect("metadata")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o640);
+
+        assert_eq!(dir.entries(), ["a.txt"]);
+    }
+
+    #[tokio::test]
+    async fn replace_file_digest_mismatch() {
+        let dir = TempDir::new("mismatch");
+        let path = dir.0.join("a.txt");
+
+        std::fs::write(&path, "old").expect("original file");
+
+        let body = Body::bytes("corrupted");
+        let resp = replace_file(
+            &path,
+            body,
+            &expected(b"new contents"),
+            builder(),
+            StatusCode::NO_CONTENT,
+        )
+        .await
+        .expect("digest mismatch");
+
+        assert_eq!(resp.status, StatusCode::UNPROCESSABLE_CONTENT);
+        assert_eq!(std::fs::read(&path).expect("contents"), b"old");
+
+        // the staged upload is discarded
+        assert_eq!(dir.entries(), ["a.txt"]);
+    }
+
+    #[tokio::test]
+    async fn replace_file_interrupted() {
+        let dir = TempDir::new("interrupted");
+        let path = dir.0.join("a.txt");
+
+        std::fs::write(&path, "old").expect("original file");
+
+        // NOTE: nothing receives the body yet, so both the chunk and the error must fit in
+        //  the channel
+        let (tx, body) = Body::channel(2);
+        tx.send("partial").await.expect("chunk");
+        tx.abort(ErrorKind::UnexpectedEof.into())
+            .await
+            .expect("abort");
+
+        let result = replace_file(&path, body, &[], builder(), StatusCode::NO_CONTENT).await;
+
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(&path).expect("contents"), b"old");
+        assert_eq!(dir.entries(), ["a.txt"]);
+    }
+
+    #[tokio::test]
+    async fn replace_directory() {
+        let dir = TempDir::new("directory");
+
+        let result = replace_file(&dir.0, Body::empty(), &[], builder(), StatusCode::CREATED).await;
+
+        assert!(matches!(result, Err(ServerError::Conflict(_))));
+        assert!(dir.entries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn write_range_verified() {
+        let dir = TempDir::new("range");
+        let path = dir.0.join("a.txt");
+
+        std::fs::write(&path, "hello world").expect("original file");
+
+        let headers = HeaderMap::from_iter([
+            (CONTENT_RANGE, Bytes::from_static(b"bytes 0-4/11")),
+            (CONTENT_LENGTH, Bytes::from_static(b"5")),
+        ]);
+
+        // a corrupted part leaves the stored data intact
+        let resp = write_range(
+            path.clone(),
+            headers.clone(),
+            &expected(b"HELLO"),
+            builder(),
+            Body::bytes("HELL0"),
+        )
+        .await
+        .expect("digest mismatch");
+
+        assert_eq!(resp.status, StatusCode::UNPROCESSABLE_CONTENT);
+        assert_eq!(std::fs::read(&path).expect("contents"), b"hello world");
+        assert_eq!(dir.entries(), ["a.txt"]);
+
+        let resp = write_range(
+            path.clone(),
+            headers,
+            &expected(b"HELLO"),
+            builder(),
+            Body::bytes("HELLO"),
+        )
+        .await
+        .expect("written part");
+
+        assert_eq!(resp.status, StatusCode::NO_CONTENT);
+        assert!(resp.headers.get(DIGEST).is_some());
+        assert_eq!(std::fs::read(&path).expect("contents"), b"HELLO world");
+        assert_eq!(dir.entries(), ["a.txt"]);
+    }
+}
@@ -0,0 +1,43 @@
+//! Handlers of the built-in endpoints
+use bytes::Bytes;
+
+use crate::extract::{Header, Path};
+use crate::header::UserAgent;
+use crate::websocket::WebSocketUpgrade;
+use crate::{Response, StatusCode};
+
+pub(crate) async fn index() -> StatusCode {
+    StatusCode::OK
+}
+
+pub(crate) async fn user_agent(user_agent: Option<Header<UserAgent>>) -> Result<Bytes, StatusCode> {
+    match user_agent {
+        Some(Header(UserAgent(user_agent))) => Ok(user_agent),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+pub(crate) async fn echo(Path(msg): Path<String>) -> String {
+    msg
+}
+
+/// WebSocket endpoint which sends every received message back to the client
+pub(crate) async fn ws_echo(ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(|mut socket| async move {
+        loop {
+            match socket.recv().await {
+                Ok(Some(msg)) => {
+                    if let Err(error) = socket.send(msg).await {
+                        eprintln!("WebSocket send failed: {error:#}");
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(error) => {
+                    eprintln!("{error:#}");
+                    break;
+                }
+            }
+        }
+    })
+}
@@ -1,211 +1,1879 @@
-use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher as _;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use anyhow::Context as _;
 use bytes::{Bytes, BytesMut};
 
+use crate::body::Body;
 use crate::encoding::{Encoding, SystemEncoder};
 
+pub const ACCEPT: Bytes = Bytes::from_static(b"Accept");
 pub const ACCEPT_ENCODING: Bytes = Bytes::from_static(b"Accept-Encoding");
+pub const ACCEPT_RANGES: Bytes = Bytes::from_static(b"Accept-Ranges");
+pub const AUTHORIZATION: Bytes = Bytes::from_static(b"Authorization");
+pub const ALLOW: Bytes = Bytes::from_static(b"Allow");
+pub const CACHE_CONTROL: Bytes = Bytes::from_static(b"Cache-Control");
+pub const CONTENT_MD5: Bytes = Bytes::from_static(b"Content-MD5");
+pub const CONNECTION: Bytes = Bytes::from_static(b"Connection");
+pub const COOKIE: Bytes = Bytes::from_static(b"Cookie");
+pub const DATE: Bytes = Bytes::from_static(b"Date");
+pub const DIGEST: Bytes = Bytes::from_static(b"Digest");
+pub const ETAG: Bytes = Bytes::from_static(b"ETag");
+pub const EXPECT: Bytes = Bytes::from_static(b"Expect");
+pub const FORWARDED: Bytes = Bytes::from_static(b"Forwarded");
+pub const HOST: Bytes = Bytes::from_static(b"Host");
+pub const IF_MATCH: Bytes = Bytes::from_static(b"If-Match");
+pub const IF_MODIFIED_SINCE: Bytes = Bytes::from_static(b"If-Modified-Since");
+pub const IF_NONE_MATCH: Bytes = Bytes::from_static(b"If-None-Match");
+pub const IF_RANGE: Bytes = Bytes::from_static(b"If-Range");
+pub const IF_UNMODIFIED_SINCE: Bytes = Bytes::from_static(b"If-Unmodified-Since");
+pub const LAST_MODIFIED: Bytes = Bytes::from_static(b"Last-Modified");
+pub const LOCATION: Bytes = Bytes::from_static(b"Location");
+pub const RANGE: Bytes = Bytes::from_static(b"Range");
+pub const RETRY_AFTER: Bytes = Bytes::from_static(b"Retry-After");
+pub const TE: Bytes = Bytes::from_static(b"TE");
+pub const UPGRADE: Bytes = Bytes::from_static(b"Upgrade");
+pub const USER_AGENT: Bytes = Bytes::from_static(b"User-Agent");
+pub const VARY: Bytes = Bytes::from_static(b"Vary");
+pub const WWW_AUTHENTICATE: Bytes = Bytes::from_static(b"WWW-Authenticate");
+pub const X_CHECKSUM_SHA256: Bytes = Bytes::from_static(b"X-Checksum-SHA256");
+pub const X_FORWARDED_FOR: Bytes = Bytes::from_static(b"X-Forwarded-For");
 
 pub const CONTENT_TYPE: Bytes = Bytes::from_static(b"Content-Type");
 pub const CONTENT_LENGTH: Bytes = Bytes::from_static(b"Content-Length");
 pub const CONTENT_ENCODING: Bytes = Bytes::from_static(b"Content-Encoding");
+pub const CONTENT_RANGE: Bytes = Bytes::from_static(b"Content-Range");
+pub const TRANSFER_ENCODING: Bytes = Bytes::from_static(b"Transfer-Encoding");
 
-// TODO: enum MimeType: Into<Bytes> + FromStr
-pub const TEXT_PLAIN: Bytes = Bytes::from_static(b"text/plain");
-pub const OCTET_STREAM: Bytes = Bytes::from_static(b"application/octet-stream");
+pub const CLOSE: Bytes = Bytes::from_static(b"close");
+pub const KEEP_ALIVE: Bytes = Bytes::from_static(b"keep-alive");
+pub const CONTINUE: Bytes = Bytes::from_static(b"100-continue");
+pub const WEBSOCKET: Bytes = Bytes::from_static(b"websocket");
+pub const CHUNKED: Bytes = Bytes::from_static(b"chunked");
+pub const BYTES: Bytes = Bytes::from_static(b"bytes");
+pub const NO_CACHE: Bytes = Bytes::from_static(b"no-cache");
+
+/// Charset parameter of textual content types, `None` if it should be omitted
+static CHARSET: OnceLock<Option<String>> = OnceLock::new();
+
+/// Set the charset appended to textual content types (`utf-8` by default).
+///
+/// Only the first call takes effect, so the charset is the same for all the responses.
+pub(crate) fn init_charset(charset: Option<&str>) {
+    let _ = CHARSET.set(charset.map(String::from));
+}
+
+macro_rules! mime_type {
+    ($($var:ident($mime:literal)),+) => {
+        /// Media types of bodies produced by the server
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum MimeType {
+            $($var),+
+        }
+
+        impl MimeType {
+            /// The media type without any parameters (e.g., `text/plain`)
+            #[inline]
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Self::$var => $mime,)+
+                }
+            }
+        }
+
+        impl FromStr for MimeType {
+            type Err = anyhow::Error;
+
+            /// Parses the media type, ignoring any parameters (e.g., `text/plain; charset=utf-8`)
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let essence = s.split(';').next().unwrap_or_default().trim();
+                match essence.to_ascii_lowercase().as_str() {
+                    $($mime => Ok(Self::$var),)+
+                    _ => anyhow::bail!("unsupported media type '{essence}'"),
+                }
+            }
+        }
+    };
+}
+
+mime_type! {
+    TextPlain("text/plain"),
+    TextHtml("text/html"),
+    EventStream("text/event-stream"),
+    Json("application/json"),
+    OctetStream("application/octet-stream"),
+    Pdf("application/pdf"),
+    Gzip("application/gzip"),
+    Png("image/png"),
+    Jpeg("image/jpeg"),
+    Gif("image/gif")
+}
+
+impl MimeType {
+    /// Returns `true` for `text/*` and JSON types which are sent with a charset parameter
+    #[inline]
+    pub fn is_text(&self) -> bool {
+        self.as_str().starts_with("text/") || *self == Self::Json
+    }
+}
+
+impl std::fmt::Display for MimeType {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// `Content-Type` value of the media type, including the charset for textual types
+impl From<MimeType> for Bytes {
+    fn from(mime: MimeType) -> Self {
+        let charset = CHARSET.get_or_init(|| Some("utf-8".to_string()));
+        match charset {
+            Some(charset) if mime.is_text() => format!("{mime}; charset={charset}").into(),
+            _ => Bytes::from_static(mime.as_str().as_bytes()),
+        }
+    }
+}
+
+impl TryFrom<Bytes> for MimeType {
+    type Error = anyhow::Error;
+
+    #[inline]
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        std::str::from_utf8(&value)
+            .context("media type is not valid UTF-8")?
+            .parse()
+    }
+}
 
 pub trait ToHeaderName {
     fn header_name() -> Bytes;
 }
 
-pub trait IntoHeaderValue {
-    fn into_header_value(self) -> Bytes;
+pub trait IntoHeaderValue {
+    fn into_header_value(self) -> Bytes;
+}
+
+/// Media type of a body along with its parameters (see RFC 9110, section 8.3)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentType {
+    /// Lowercase `type/subtype` (e.g., `text/plain`)
+    essence: String,
+    /// Parameters in the order of their appearance, with lowercase names and unquoted values
+    params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    /// The media type without any parameters (e.g., `text/plain`)
+    #[inline]
+    pub fn essence(&self) -> &str {
+        &self.essence
+    }
+
+    /// Value of parameter `name` (compared case-insensitively)
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[inline]
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    /// The media type if it's one of those produced by the server
+    #[inline]
+    pub fn mime_type(&self) -> Option<MimeType> {
+        self.essence.parse().ok()
+    }
+
+    /// Returns `true` iff the media type (regardless of its parameters) matches any of the
+    /// `ranges` (e.g., `text/plain`, `image/*` or `*/*`)
+    pub(crate) fn is_in(&self, ranges: &[String]) -> bool {
+        let main_type = self
+            .essence
+            .split_once('/')
+            .map_or(self.essence.as_str(), |(ty, _)| ty);
+
+        ranges.iter().any(|range| match range.strip_suffix("/*") {
+            Some("*") => true,
+            Some(ty) => ty.eq_ignore_ascii_case(main_type),
+            None => range.eq_ignore_ascii_case(&self.essence),
+        })
+    }
+}
+
+impl std::fmt::Display for ContentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.essence)?;
+        for (name, value) in self.params.iter() {
+            if is_token(value.as_bytes()) {
+                write!(f, "; {name}={value}")?;
+            } else {
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                write!(f, "; {name}=\"{value}\"")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Content type of the media type, including the charset for textual types
+impl From<MimeType> for ContentType {
+    fn from(mime: MimeType) -> Self {
+        let charset = CHARSET.get_or_init(|| Some("utf-8".to_string()));
+        let params = match charset {
+            Some(charset) if mime.is_text() => vec![("charset".to_string(), charset.clone())],
+            _ => Vec::new(),
+        };
+        Self {
+            essence: mime.as_str().to_string(),
+            params,
+        }
+    }
+}
+
+impl ToHeaderName for ContentType {
+    #[inline]
+    fn header_name() -> Bytes {
+        CONTENT_TYPE
+    }
+}
+
+impl IntoHeaderValue for ContentType {
+    #[inline]
+    fn into_header_value(self) -> Bytes {
+        self.to_string().into()
+    }
+}
+
+impl TryFrom<Bytes> for ContentType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        let value = std::str::from_utf8(&value).context("content type is not valid UTF-8")?;
+
+        let (essence, mut rest) = value.split_once(';').unwrap_or((value, ""));
+        let essence = essence.trim();
+
+        let valid = essence
+            .split_once('/')
+            .is_some_and(|(ty, subtype)| is_token(ty.as_bytes()) && is_token(subtype.as_bytes()));
+        anyhow::ensure!(valid, "invalid media type '{essence}'");
+
+        let mut params = Vec::new();
+
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ';']);
+            if rest.is_empty() {
+                break;
+            }
+
+            let Some((name, tail)) = rest.split_once('=') else {
+                anyhow::bail!("invalid media type parameter '{rest}'");
+            };
+
+            let name = name.trim_end();
+            anyhow::ensure!(is_token(name.as_bytes()), "invalid parameter name '{name}'");
+
+            // NOTE: quoted values may contain semicolons and escaped characters
+            let value = if let Some(tail) = tail.strip_prefix('"') {
+                let mut value = String::new();
+                let mut chars = tail.char_indices();
+                let end = loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => value.push(c),
+                            None => anyhow::bail!("unterminated quoted string"),
+                        },
+                        Some((at, '"')) => break at,
+                        Some((_, c)) => value.push(c),
+                        None => anyhow::bail!("unterminated quoted string"),
+                    }
+                };
+                rest = &tail[end + 1..];
+                value
+            } else {
+                let (value, tail) = tail.split_once(';').unwrap_or((tail, ""));
+                rest = tail;
+                value.trim().to_string()
+            };
+
+            params.push((name.to_ascii_lowercase(), value));
+        }
+
+        Ok(Self {
+            essence: essence.to_ascii_lowercase(),
+            params,
+        })
+    }
+}
+
+/// Media ranges acceptable by the client along with their weights (see RFC 9110, section 12.5.1)
+#[derive(Debug, Default)]
+#[repr(transparent)]
+pub struct Accept(Vec<(String, f32)>);
+
+impl Accept {
+    /// Weight of given media type (e.g., `application/json`) assigned by the most specific
+    /// matching media range, `0` if the type is not acceptable
+    pub(crate) fn quality(&self, media_type: &str) -> f32 {
+        let main_type = media_type.split_once('/').map_or(media_type, |(ty, _)| ty);
+
+        self.0
+            .iter()
+            .filter_map(|(range, q)| {
+                let specificity = if range.eq_ignore_ascii_case(media_type) {
+                    2
+                } else if range
+                    .strip_suffix("/*")
+                    .is_some_and(|ty| ty.eq_ignore_ascii_case(main_type))
+                {
+                    1
+                } else if range == "*/*" {
+                    0
+                } else {
+                    return None;
+                };
+                Some((specificity, *q))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, q)| q)
+    }
+}
+
+impl ToHeaderName for Accept {
+    #[inline]
+    fn header_name() -> Bytes {
+        ACCEPT
+    }
+}
+
+impl TryFrom<Bytes> for Accept {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        let value = std::str::from_utf8(&value).context("Accept is not valid UTF-8")?;
+
+        let ranges = value
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';').map(str::trim);
+
+                let range = params.next().filter(|range| !range.is_empty())?;
+
+                let q = params
+                    .filter_map(|param| param.split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("q"))
+                    .map_or(Some(1.0), |(_, q)| q.parse::<f32>().ok())?;
+
+                Some((range.to_string(), q.clamp(0.0, 1.0)))
+            })
+            .collect();
+
+        Ok(Self(ranges))
+    }
+}
+
+/// Content codings acceptable by the client along with their weights (see RFC 9110, section
+/// 12.5.3)
+#[derive(Debug, Default)]
+pub struct AcceptEncoding {
+    /// Explicitly listed codings in the order in which they were listed
+    encodings: Vec<(Encoding, f32)>,
+    /// Weight of any other coding (`*`) if given
+    any: Option<f32>,
+    /// Weight of no coding at all (`identity`) if given explicitly
+    identity: Option<f32>,
+}
+
+impl AcceptEncoding {
+    /// Returns `false` iff the client refuses unencoded content, i.e., either `identity` or `*`
+    /// (without `identity`) has zero weight
+    #[inline]
+    pub(crate) fn accepts_identity(&self) -> bool {
+        match self.identity.or(self.any) {
+            Some(q) => q > 0.0,
+            None => true,
+        }
+    }
+
+    /// Select the most preferred coding which is supported.
+    ///
+    /// Codings of equal weights are preferred in the order in which the client listed them.
+    #[inline]
+    pub(crate) fn select(&self, supported: impl Fn(&Encoding) -> bool) -> Option<Encoding> {
+        self.iter().find(supported)
+    }
+
+    /// Encodings accepted by the client in the order of preference.
+    ///
+    /// Codings with zero weight are not acceptable and thus left out. Codings matched just by `*`
+    /// come after the listed ones of the same weight.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Encoding> {
+        let others = Encoding::iter()
+            .filter(|enc| self.encodings.iter().all(|(listed, _)| listed != enc))
+            .filter_map(|enc| Some((enc, self.any?)));
+
+        let mut ranked: Vec<_> = self.encodings.iter().copied().chain(others).collect();
+
+        // NOTE: the sort is stable, so the order of codings of equal weights is preserved
+        ranked.sort_by(|(_, q1), (_, q2)| q2.total_cmp(q1));
+
+        ranked
+            .into_iter()
+            .filter(|(_, q)| *q > 0.0)
+            .map(|(enc, _)| enc)
+    }
+}
+
+impl From<Bytes> for AcceptEncoding {
+    fn from(value: Bytes) -> Self {
+        let mut accepted = Self::default();
+
+        for coding in value.split(|&b| b == b',') {
+            let mut params = coding.split(|&b| b == b';').map(trim_whitespace);
+
+            let Some(coding) = params.next().filter(|coding| !coding.is_empty()) else {
+                continue;
+            };
+
+            let q = params
+                .filter_map(|param| {
+                    let at = param.iter().position(|&b| b == b'=')?;
+                    Some((
+                        trim_whitespace(&param[..at]),
+                        trim_whitespace(&param[at + 1..]),
+                    ))
+                })
+                .find(|(name, _)| name.eq_ignore_ascii_case(b"q"))
+                .map_or(Some(1.0), |(_, q)| {
+                    std::str::from_utf8(q).ok()?.parse::<f32>().ok()
+                });
+
+            // NOTE: codings with invalid weights are ignored
+            let Some(q) = q.map(|q| q.clamp(0.0, 1.0)) else {
+                continue;
+            };
+
+            if coding == b"*" {
+                accepted.any = Some(q);
+            } else if coding.eq_ignore_ascii_case(b"identity") {
+                accepted.identity = Some(q);
+            } else if let Ok(enc) = Encoding::try_from(coding.to_ascii_lowercase().as_slice()) {
+                if accepted.encodings.iter().all(|(listed, _)| *listed != enc) {
+                    accepted.encodings.push((enc, q));
+                }
+            }
+        }
+
+        accepted
+    }
+}
+
+impl ToHeaderName for AcceptEncoding {
+    #[inline]
+    fn header_name() -> Bytes {
+        ACCEPT_ENCODING
+    }
+}
+
+/// Transfer codings acceptable by the client in responses along with their weights (see RFC
+/// 9110, section 10.1.4)
+#[derive(Debug, Default)]
+#[repr(transparent)]
+pub struct Te(AcceptEncoding);
+
+impl Te {
+    /// Select the most preferred transfer coding which is supported (see
+    /// [`AcceptEncoding::select`])
+    #[inline]
+    pub(crate) fn select(&self, supported: impl Fn(&Encoding) -> bool) -> Option<Encoding> {
+        self.0.select(supported)
+    }
+}
+
+impl From<Bytes> for Te {
+    #[inline]
+    fn from(value: Bytes) -> Self {
+        // NOTE: the syntax is the same, except that there's no `*` and `trailers` is not a coding
+        let mut accepted = AcceptEncoding::from(value);
+        accepted.any = None;
+        accepted.identity = None;
+        Self(accepted)
+    }
+}
+
+impl ToHeaderName for Te {
+    #[inline]
+    fn header_name() -> Bytes {
+        TE
+    }
+}
+
+impl From<AcceptEncoding> for Option<Bytes> {
+    fn from(encoding: AcceptEncoding) -> Self {
+        if encoding.encodings.is_empty() && encoding.any.is_none() && encoding.identity.is_none() {
+            return None;
+        }
+
+        let encs = encoding
+            .encodings
+            .into_iter()
+            .map(|(enc, q)| (enc.to_string(), q))
+            .chain(encoding.identity.map(|q| ("identity".to_string(), q)))
+            .chain(encoding.any.map(|q| ("*".to_string(), q)))
+            .map(
+                |(enc, q)| {
+                    if q < 1.0 {
+                        format!("{enc};q={q}")
+                    } else {
+                        enc
+                    }
+                },
+            );
+
+        Some(itertools::join(encs, ", ").into())
+    }
+}
+
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct ContentEncoding(Encoding);
+
+impl std::fmt::Display for ContentEncoding {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<Encoding> for ContentEncoding {
+    #[inline]
+    fn from(encoding: Encoding) -> Self {
+        Self(encoding)
+    }
+}
+
+impl From<&ContentEncoding> for Encoding {
+    #[inline]
+    fn from(ContentEncoding(encoding): &ContentEncoding) -> Self {
+        *encoding
+    }
+}
+
+impl ToHeaderName for ContentEncoding {
+    #[inline]
+    fn header_name() -> Bytes {
+        CONTENT_ENCODING
+    }
+}
+
+impl IntoHeaderValue for ContentEncoding {
+    #[inline]
+    fn into_header_value(self) -> Bytes {
+        self.0.into()
+    }
+}
+
+impl TryFrom<Bytes> for ContentEncoding {
+    type Error = anyhow::Error;
+
+    #[inline]
+    fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
+        Encoding::try_from(bytes).map(Self)
+    }
+}
+
+impl SystemEncoder for ContentEncoding {
+    #[inline]
+    fn program(&self) -> Option<&str> {
+        self.0.program()
+    }
+
+    #[inline]
+    fn command(&self) -> Option<tokio::process::Command> {
+        self.0.command()
+    }
+
+    #[inline]
+    async fn spawn(&self) -> anyhow::Result<tokio::process::Child> {
+        self.0.spawn().await
+    }
+
+    #[inline]
+    async fn compress(&self, body: Body) -> anyhow::Result<Body> {
+        self.0.compress(body).await
+    }
+
+    #[inline]
+    async fn decompress(&self, body: Body) -> anyhow::Result<Body> {
+        self.0.decompress(body).await
+    }
+}
+
+/// Entity tag of a representation (see RFC 9110, section 8.8.3)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ETag {
+    weak: bool,
+    /// Opaque tag without the quotes
+    tag: Bytes,
+}
+
+impl ETag {
+    #[inline]
+    pub(crate) fn strong(tag: impl Into<Bytes>) -> Self {
+        Self {
+            weak: false,
+            tag: tag.into(),
+        }
+    }
+
+    /// Returns `true` iff both tags are strong and equal
+    #[inline]
+    pub(crate) fn strong_eq(&self, other: &Self) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Returns `true` iff both tags are equal regardless of their weakness
+    #[inline]
+    pub(crate) fn weak_eq(&self, other: &Self) -> bool {
+        self.tag == other.tag
+    }
+
+    /// Tag of the representation encoded with given content encoding.
+    ///
+    /// Encoded representations differ from the original one, so these must be tagged differently.
+    pub(crate) fn encoded(&self, encoding: &ContentEncoding) -> Self {
+        let mut tag = BytesMut::with_capacity(self.tag.len() + 8);
+        tag.extend_from_slice(&self.tag);
+        tag.extend_from_slice(format!("-{encoding}").as_bytes());
+        Self {
+            weak: self.weak,
+            tag: tag.freeze(),
+        }
+    }
+
+    /// Returns `true` iff `other` tags this representation or its encoded variant (compared weakly)
+    fn matches_any_encoding(&self, other: &Self) -> bool {
+        if self.weak_eq(other) {
+            return true;
+        }
+
+        other
+            .tag
+            .strip_prefix(self.tag.as_ref())
+            .and_then(|suffix| suffix.strip_prefix(b"-"))
+            .is_some_and(|encoding| Encoding::try_from(encoding).is_ok())
+    }
+}
+
+impl ToHeaderName for ETag {
+    #[inline]
+    fn header_name() -> Bytes {
+        ETAG
+    }
+}
+
+impl IntoHeaderValue for ETag {
+    fn into_header_value(self) -> Bytes {
+        let mut value = BytesMut::with_capacity(self.tag.len() + 4);
+        if self.weak {
+            value.extend_from_slice(b"W/");
+        }
+        value.extend_from_slice(b"\"");
+        value.extend_from_slice(&self.tag);
+        value.extend_from_slice(b"\"");
+        value.freeze()
+    }
+}
+
+impl TryFrom<Bytes> for ETag {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        let trimmed = trim_whitespace(&value);
+
+        let (weak, tag) = match trimmed.strip_prefix(b"W/") {
+            Some(tag) => (true, tag),
+            None => (false, trimmed),
+        };
+
+        let Some(tag) = tag.strip_prefix(b"\"").and_then(|t| t.strip_suffix(b"\"")) else {
+            anyhow::bail!("entity tag must be quoted");
+        };
+
+        anyhow::ensure!(!tag.contains(&b'"'), "invalid entity tag");
+
+        Ok(Self {
+            weak,
+            tag: value.slice_ref(tag),
+        })
+    }
+}
+
+/// Entity tags listed in `If-None-Match` (see RFC 9110, section 13.1.2)
+#[derive(Debug)]
+pub enum IfNoneMatch {
+    /// `*` matching any current representation
+    Any,
+    Tags(Vec<ETag>),
+}
+
+impl IfNoneMatch {
+    /// Find the listed tag which matches the current representation tagged by `etag`.
+    ///
+    /// Tags of encoded variants of the representation match as well (see [`ETag::encoded`]).
+    pub(crate) fn find(&self, etag: &ETag) -> Option<ETag> {
+        match self {
+            Self::Any => Some(etag.clone()),
+            Self::Tags(tags) => tags.iter().find(|t| etag.matches_any_encoding(t)).cloned(),
+        }
+    }
+}
+
+impl ToHeaderName for IfNoneMatch {
+    #[inline]
+    fn header_name() -> Bytes {
+        IF_NONE_MATCH
+    }
+}
+
+impl IntoHeaderValue for IfNoneMatch {
+    fn into_header_value(self) -> Bytes {
+        match self {
+            Self::Any => Bytes::from_static(b"*"),
+            Self::Tags(tags) => {
+                let tags: Vec<_> = tags
+                    .into_iter()
+                    .map(|tag| tag.into_header_value())
+                    .collect();
+                Bytes::from(tags.join(&b", "[..]))
+            }
+        }
+    }
+}
+
+impl TryFrom<Bytes> for IfNoneMatch {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        if trim_whitespace(&value) == b"*" {
+            return Ok(Self::Any);
+        }
+
+        value
+            .split(|&b| b == b',')
+            .map(trim_whitespace)
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| ETag::try_from(value.slice_ref(tag)))
+            .collect::<Result<_, _>>()
+            .map(Self::Tags)
+    }
+}
+
+/// Entity tags listed in `If-Match` (see RFC 9110, section 13.1.1)
+#[derive(Debug)]
+pub enum IfMatch {
+    /// `*` matching any current representation
+    Any,
+    Tags(Vec<ETag>),
+}
+
+impl IfMatch {
+    /// Returns `true` iff the current representation tagged by `etag` (`None` if there's no such
+    /// representation) matches any of the listed tags, which are compared strongly.
+    pub(crate) fn matches(&self, etag: Option<&ETag>) -> bool {
+        match (self, etag) {
+            (_, None) => false,
+            (Self::Any, Some(_)) => true,
+            (Self::Tags(tags), Some(etag)) => tags.iter().any(|tag| etag.strong_eq(tag)),
+        }
+    }
+}
+
+impl ToHeaderName for IfMatch {
+    #[inline]
+    fn header_name() -> Bytes {
+        IF_MATCH
+    }
+}
+
+impl TryFrom<Bytes> for IfMatch {
+    type Error = anyhow::Error;
+
+    #[inline]
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        // NOTE: both headers share the same syntax
+        IfNoneMatch::try_from(value).map(|tags| match tags {
+            IfNoneMatch::Any => Self::Any,
+            IfNoneMatch::Tags(tags) => Self::Tags(tags),
+        })
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Timestamp with a second precision formatted as an HTTP-date (see RFC 9110, section 5.6.7)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct HttpDate(u64);
+
+impl HttpDate {
+    #[inline]
+    pub fn now() -> Self {
+        Self::from(SystemTime::now())
+    }
+
+    fn from_parts(year: i64, month: u32, day: u32, time: &str) -> anyhow::Result<Self> {
+        let mut hms = time.splitn(3, ':').map(|t| t.parse::<u64>());
+
+        let (Some(Ok(h)), Some(Ok(m)), Some(Ok(s))) = (hms.next(), hms.next(), hms.next()) else {
+            anyhow::bail!("invalid time of day '{time}'");
+        };
+
+        anyhow::ensure!(h < 24 && m < 60 && s < 61, "invalid time of day '{time}'");
+        anyhow::ensure!((1..=31).contains(&day), "invalid day {day}");
+
+        let days = days_from_civil(year, month, day);
+        anyhow::ensure!(days >= 0, "date before the Unix epoch");
+
+        Ok(Self(days as u64 * 86400 + h * 3600 + m * 60 + s))
+    }
+}
+
+/// Number of days since the Unix epoch of given (proleptic Gregorian) date
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Date `(year, month, day)` of given number of days since the Unix epoch
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn parse_month(month: &str) -> anyhow::Result<u32> {
+    MONTHS
+        .iter()
+        .position(|m| *m == month)
+        .map(|m| m as u32 + 1)
+        .with_context(|| format!("invalid month '{month}'"))
+}
+
+impl From<SystemTime> for HttpDate {
+    #[inline]
+    fn from(time: SystemTime) -> Self {
+        Self(time.duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs()))
+    }
+}
+
+impl From<HttpDate> for SystemTime {
+    #[inline]
+    fn from(HttpDate(secs): HttpDate) -> Self {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+}
+
+/// Formats the date as an IMF-fixdate (e.g., `Sun, 06 Nov 1994 08:49:37 GMT`)
+impl std::fmt::Display for HttpDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let days = (self.0 / 86400) as i64;
+        let secs = self.0 % 86400;
+
+        let (year, month, day) = civil_from_days(days);
+
+        write!(
+            f,
+            "{}, {day:02} {} {year:04} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[(days % 7) as usize],
+            MONTHS[month as usize - 1],
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+        )
+    }
+}
+
+/// Parses IMF-fixdate as well as the obsolete RFC 850 and asctime formats
+impl FromStr for HttpDate {
+    type Err = anyhow::Error;
+
+    fn from_str(date: &str) -> Result<Self, Self::Err> {
+        let parts = date.split_ascii_whitespace().collect::<Vec<_>>();
+
+        match parts.as_slice() {
+            // Sun, 06 Nov 1994 08:49:37 GMT
+            [_, day, month, year, time, "GMT"] => {
+                Self::from_parts(year.parse()?, parse_month(month)?, day.parse()?, time)
+            }
+
+            // Sunday, 06-Nov-94 08:49:37 GMT
+            [_, date, time, "GMT"] => {
+                let mut dmy = date.splitn(3, '-');
+
+                let (Some(day), Some(month), Some(year)) = (dmy.next(), dmy.next(), dmy.next())
+                else {
+                    anyhow::bail!("invalid date '{date}'");
+                };
+
+                // NOTE: two-digit years are resolved as the nearest in the past (RFC 9110)
+                let year = match year.parse::<i64>()? {
+                    year @ 0..=69 => 2000 + year,
+                    year @ 70..=99 => 1900 + year,
+                    _ => anyhow::bail!("invalid year '{year}'"),
+                };
+
+                Self::from_parts(year, parse_month(month)?, day.parse()?, time)
+            }
+
+            // Sun Nov  6 08:49:37 1994
+            [_, month, day, time, year] => {
+                Self::from_parts(year.parse()?, parse_month(month)?, day.parse()?, time)
+            }
+
+            _ => anyhow::bail!("invalid HTTP-date '{date}'"),
+        }
+    }
+}
+
+impl TryFrom<Bytes> for HttpDate {
+    type Error = anyhow::Error;
+
+    #[inline]
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        std::str::from_utf8(&value)?.parse()
+    }
+}
+
+impl IntoHeaderValue for HttpDate {
+    #[inline]
+    fn into_header_value(self) -> Bytes {
+        self.to_string().into()
+    }
+}
+
+/// Time of the last modification of a representation (see RFC 9110, section 8.8.2)
+#[derive(Clone, Copy, Debug)]
+#[repr(transparent)]
+pub struct LastModified(pub HttpDate);
+
+impl ToHeaderName for LastModified {
+    #[inline]
+    fn header_name() -> Bytes {
+        LAST_MODIFIED
+    }
+}
+
+impl IntoHeaderValue for LastModified {
+    #[inline]
+    fn into_header_value(self) -> Bytes {
+        self.0.into_header_value()
+    }
+}
+
+/// Time of the modification of the client's cached representation (RFC 9110, section 13.1.3)
+#[derive(Clone, Copy, Debug)]
+#[repr(transparent)]
+pub struct IfModifiedSince(pub HttpDate);
+
+impl ToHeaderName for IfModifiedSince {
+    #[inline]
+    fn header_name() -> Bytes {
+        IF_MODIFIED_SINCE
+    }
+}
+
+impl TryFrom<Bytes> for IfModifiedSince {
+    type Error = anyhow::Error;
+
+    #[inline]
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        HttpDate::try_from(value).map(Self)
+    }
+}
+
+/// Time of the modification of the representation the client expects to be current (RFC 9110,
+/// section 13.1.4)
+#[derive(Clone, Copy, Debug)]
+#[repr(transparent)]
+pub struct IfUnmodifiedSince(pub HttpDate);
+
+impl ToHeaderName for IfUnmodifiedSince {
+    #[inline]
+    fn header_name() -> Bytes {
+        IF_UNMODIFIED_SINCE
+    }
+}
+
+impl TryFrom<Bytes> for IfUnmodifiedSince {
+    type Error = anyhow::Error;
+
+    #[inline]
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        HttpDate::try_from(value).map(Self)
+    }
+}
+
+/// Time at which a message was originated (see RFC 9110, section 6.6.1)
+#[derive(Clone, Copy, Debug)]
+#[repr(transparent)]
+pub struct Date(pub HttpDate);
+
+impl Date {
+    #[inline]
+    pub fn now() -> Self {
+        Self(HttpDate::now())
+    }
+
+    /// Current date rendered as a header value, which is shared by all the responses sent within
+    /// the same second (see [`run_date_clock`])
+    pub(crate) fn current() -> Bytes {
+        let current = CURRENT_DATE
+            .read()
+            .map(|date| date.clone())
+            .unwrap_or_default();
+
+        // NOTE: the date is rendered for each response if the clock is not running
+        if current.is_empty() {
+            Self::now().into_header_value()
+        } else {
+            current
+        }
+    }
+}
+
+/// Rendered date of the current second (empty until the clock starts)
+static CURRENT_DATE: RwLock<Bytes> = RwLock::new(Bytes::new());
+
+/// Re-render the shared current date at the start of every second (see [`Date::current`])
+pub(crate) async fn run_date_clock() {
+    loop {
+        let now = SystemTime::now();
+
+        if let Ok(mut date) = CURRENT_DATE.write() {
+            *date = Date(HttpDate::from(now)).into_header_value();
+        }
+
+        let subsec = now
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.subsec_nanos())
+            .unwrap_or_default();
+
+        tokio::time::sleep(Duration::from_nanos(1_000_000_000 - u64::from(subsec))).await;
+    }
+}
+
+impl ToHeaderName for Date {
+    #[inline]
+    fn header_name() -> Bytes {
+        DATE
+    }
+}
+
+impl IntoHeaderValue for Date {
+    #[inline]
+    fn into_header_value(self) -> Bytes {
+        self.0.into_header_value()
+    }
+}
+
+impl TryFrom<Bytes> for Date {
+    type Error = anyhow::Error;
+
+    #[inline]
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        HttpDate::try_from(value).map(Self)
+    }
+}
+
+/// Validator of the client's partial representation (see RFC 9110, section 13.1.5)
+#[derive(Debug)]
+pub enum IfRange {
+    ETag(ETag),
+    Date(HttpDate),
+}
+
+impl IfRange {
+    /// Returns `true` iff the validator matches the current representation, i.e., the `Range`
+    /// request can be served.
+    ///
+    /// Entity tags are compared strongly and dates must match the last modification exactly.
+    pub(crate) fn matches(&self, etag: Option<&ETag>, last_modified: Option<HttpDate>) -> bool {
+        match self {
+            Self::ETag(tag) => etag.is_some_and(|etag| etag.strong_eq(tag)),
+            Self::Date(date) => last_modified == Some(*date),
+        }
+    }
+}
+
+impl ToHeaderName for IfRange {
+    #[inline]
+    fn header_name() -> Bytes {
+        IF_RANGE
+    }
+}
+
+impl TryFrom<Bytes> for IfRange {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        let trimmed = trim_whitespace(&value);
+        if trimmed.starts_with(b"\"") || trimmed.starts_with(b"W/") {
+            ETag::try_from(value).map(Self::ETag)
+        } else {
+            HttpDate::try_from(value).map(Self::Date)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ByteRange {
+    /// `first-last` or `first-` (i.e., until the end)
+    Bounded(u64, Option<u64>),
+    /// `-len` (i.e., last `len` bytes)
+    Suffix(u64),
+}
+
+/// Byte ranges requested by the `Range` header (see RFC 9110, section 14.2)
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct Range(Vec<ByteRange>);
+
+impl Range {
+    /// Resolve requested ranges against a representation of given length.
+    ///
+    /// Returns the satisfiable ranges as inclusive `(first, last)` byte positions in the order in
+    /// which they were requested. Unsatisfiable ranges are left out.
+    pub(crate) fn resolve(&self, len: u64) -> Vec<(u64, u64)> {
+        self.0
+            .iter()
+            .filter_map(|range| match *range {
+                _ if len == 0 => None,
+                ByteRange::Bounded(first, _) if first >= len => None,
+                ByteRange::Bounded(first, last) => {
+                    Some((first, last.map_or(len - 1, |last| last.min(len - 1))))
+                }
+                ByteRange::Suffix(0) => None,
+                ByteRange::Suffix(n) => Some((len.saturating_sub(n), len - 1)),
+            })
+            .collect()
+    }
+}
+
+impl ToHeaderName for Range {
+    #[inline]
+    fn header_name() -> Bytes {
+        RANGE
+    }
+}
+
+impl IntoHeaderValue for Range {
+    fn into_header_value(self) -> Bytes {
+        let ranges = self.0.iter().map(|range| match *range {
+            ByteRange::Bounded(first, Some(last)) => format!("{first}-{last}"),
+            ByteRange::Bounded(first, None) => format!("{first}-"),
+            ByteRange::Suffix(len) => format!("-{len}"),
+        });
+        format!("bytes={}", itertools::join(ranges, ", ")).into()
+    }
+}
+
+impl TryFrom<Bytes> for Range {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        fn position(pos: &[u8]) -> anyhow::Result<u64> {
+            let pos = std::str::from_utf8(pos)?;
+            anyhow::ensure!(pos.bytes().all(|b| b.is_ascii_digit()), "invalid position");
+            Ok(pos.parse()?)
+        }
+
+        let value = trim_whitespace(&value);
+
+        let Some((unit, ranges)) = value
+            .iter()
+            .position(|&b| b == b'=')
+            .map(|at| (&value[..at], &value[at + 1..]))
+        else {
+            anyhow::bail!("missing range unit");
+        };
+
+        anyhow::ensure!(unit.matches(BYTES), "unsupported range unit");
+
+        let mut specs = Vec::new();
+
+        for spec in ranges.split(|&b| b == b',').map(trim_whitespace) {
+            if spec.is_empty() {
+                continue;
+            }
+
+            let Some(dash) = spec.iter().position(|&b| b == b'-') else {
+                anyhow::bail!("invalid range");
+            };
+
+            let (first, last) = (&spec[..dash], &spec[dash + 1..]);
+
+            let spec = match (first.is_empty(), last.is_empty()) {
+                (true, true) => anyhow::bail!("invalid range"),
+                (true, false) => ByteRange::Suffix(position(last)?),
+                (false, true) => ByteRange::Bounded(position(first)?, None),
+                (false, false) => {
+                    let (first, last) = (position(first)?, position(last)?);
+                    anyhow::ensure!(first <= last, "invalid range");
+                    ByteRange::Bounded(first, Some(last))
+                }
+            };
+
+            specs.push(spec);
+        }
+
+        anyhow::ensure!(!specs.is_empty(), "empty range set");
+
+        Ok(Self(specs))
+    }
+}
+
+/// Position of a partial request body within the complete representation (see RFC 9110,
+/// section 14.4)
+#[derive(Clone, Copy, Debug)]
+pub struct ContentRange {
+    /// Position of the first byte of the body
+    pub first: u64,
+    /// Position of the last byte of the body (inclusive)
+    pub last: u64,
+    /// Length of the complete representation, if known
+    pub complete: Option<u64>,
+}
+
+impl ToHeaderName for ContentRange {
+    #[inline]
+    fn header_name() -> Bytes {
+        CONTENT_RANGE
+    }
+}
+
+impl TryFrom<Bytes> for ContentRange {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        fn position(pos: &str) -> anyhow::Result<u64> {
+            anyhow::ensure!(pos.bytes().all(|b| b.is_ascii_digit()), "invalid position");
+            Ok(pos.parse()?)
+        }
+
+        let value = std::str::from_utf8(trim_whitespace(&value))?;
+
+        let Some((unit, range)) = value.split_once(' ') else {
+            anyhow::bail!("missing range unit");
+        };
+
+        anyhow::ensure!(unit.as_bytes().matches(BYTES), "unsupported range unit");
+
+        let Some((range, complete)) = range.trim().split_once('/') else {
+            anyhow::bail!("missing complete length");
+        };
+
+        let Some((first, last)) = range.split_once('-') else {
+            anyhow::bail!("invalid range");
+        };
+
+        let (first, last) = (position(first)?, position(last)?);
+
+        let complete = match complete {
+            "*" => None,
+            complete => Some(position(complete)?),
+        };
+
+        anyhow::ensure!(first <= last, "invalid range");
+
+        if let Some(complete) = complete {
+            anyhow::ensure!(last < complete, "range exceeds the complete length");
+        }
+
+        Ok(Self {
+            first,
+            last,
+            complete,
+        })
+    }
+}
+
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct ContentLength(Bytes);
+
+impl ToHeaderName for ContentLength {
+    #[inline]
+    fn header_name() -> Bytes {
+        CONTENT_LENGTH
+    }
+}
+
+impl IntoHeaderValue for ContentLength {
+    #[inline]
+    fn into_header_value(self) -> Bytes {
+        self.0
+    }
+}
+
+impl From<Bytes> for ContentLength {
+    #[inline]
+    fn from(bytes: Bytes) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<u64> for ContentLength {
+    #[inline]
+    fn from(len: u64) -> Self {
+        Self(match len {
+            0 => Bytes::from_static(b"0"),
+            len => len.to_string().into(),
+        })
+    }
 }
 
-#[derive(Debug, Default)]
-#[repr(transparent)]
-pub struct AcceptEncoding(Vec<Encoding>);
+impl From<ContentLength> for Bytes {
+    #[inline]
+    fn from(ContentLength(len): ContentLength) -> Self {
+        len
+    }
+}
 
-impl AcceptEncoding {
+/// Host and port of the target URI (see RFC 9110, section 7.2)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Host {
+    /// Registered name or IP address (IPv6 addresses are enclosed in brackets)
+    pub name: String,
+    pub port: Option<u16>,
+}
+
+impl std::fmt::Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}:{port}", self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+impl ToHeaderName for Host {
     #[inline]
-    pub(crate) fn select(&self, supported: &HashSet<Encoding>) -> Option<Encoding> {
-        self.0.iter().find(|enc| supported.contains(enc)).copied()
+    fn header_name() -> Bytes {
+        HOST
     }
 }
 
-impl From<Bytes> for AcceptEncoding {
-    fn from(value: Bytes) -> Self {
-        let mut value = value.clone();
+impl IntoHeaderValue for Host {
+    #[inline]
+    fn into_header_value(self) -> Bytes {
+        self.to_string().into()
+    }
+}
 
-        let mut encs = Vec::with_capacity(8);
+impl TryFrom<Bytes> for Host {
+    type Error = anyhow::Error;
 
-        while let Some(pos) = value.iter().position(|&b| b == b',') {
-            let enc = value.split_to(pos);
-            let _ = value.split_to(1);
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        let value = std::str::from_utf8(trim_whitespace(&value)).context("invalid host")?;
 
-            // skip over whitespace
-            if let Some(at) = value.iter().position(|&b| !b.is_ascii_whitespace()) {
-                let _ = value.split_to(at);
+        let (name, port) = if value.starts_with('[') {
+            let Some(end) = value.find(']') else {
+                anyhow::bail!("unterminated IP literal");
+            };
+            let (name, rest) = value.split_at(end + 1);
+            name[1..end]
+                .parse::<std::net::Ipv6Addr>()
+                .context("invalid IPv6 address")?;
+            match rest {
+                "" => (name, None),
+                rest => match rest.strip_prefix(':') {
+                    Some(port) => (name, Some(port)),
+                    None => anyhow::bail!("invalid host"),
+                },
             }
-
-            if let Ok(enc) = Encoding::try_from(enc) {
-                encs.push(enc);
+        } else {
+            match value.split_once(':') {
+                Some((name, port)) => (name, Some(port)),
+                None => (value, None),
             }
-        }
+        };
 
-        if let Ok(enc) = Encoding::try_from(value.as_ref()) {
-            encs.push(enc);
-        }
+        // NOTE: registered names consist of unreserved, percent-encoded and sub-delim characters
+        //  (see RFC 3986, section 3.2.2)
+        let valid = name.starts_with('[')
+            || name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-._~%!$&'()*+,;=".contains(&b));
+        anyhow::ensure!(valid, "invalid host name '{name}'");
+
+        let port = match port {
+            // NOTE: the port may be empty (see RFC 3986, section 3.2.3)
+            None | Some("") => None,
+            Some(port) => {
+                anyhow::ensure!(port.bytes().all(|b| b.is_ascii_digit()), "invalid port");
+                Some(port.parse().context("invalid port")?)
+            }
+        };
 
-        Self(encs)
+        Ok(Self {
+            name: name.to_string(),
+            port,
+        })
     }
 }
 
-impl ToHeaderName for AcceptEncoding {
+/// Connection options listed in the `Connection` header (see RFC 9110, section 7.6.1)
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct Connection(Vec<Bytes>);
+
+impl Connection {
     #[inline]
-    fn header_name() -> Bytes {
-        ACCEPT_ENCODING
+    pub fn close() -> Self {
+        Self(vec![CLOSE])
     }
-}
 
-impl From<AcceptEncoding> for Option<Bytes> {
-    fn from(encoding: AcceptEncoding) -> Self {
-        const SEP: Bytes = Bytes::from_static(b", ");
+    #[inline]
+    pub fn keep_alive() -> Self {
+        Self(vec![KEEP_ALIVE])
+    }
 
-        if encoding.0.is_empty() {
-            return None;
-        }
+    #[inline]
+    pub fn upgrade() -> Self {
+        Self(vec![UPGRADE])
+    }
 
-        let mut bytes = BytesMut::with_capacity(32);
+    /// Returns `true` iff given option is listed (compared case-insensitively)
+    #[inline]
+    pub fn contains(&self, option: impl AsRef<[u8]>) -> bool {
+        self.0.iter().any(|o| o.matches(option.as_ref()))
+    }
+}
+
+impl ToHeaderName for Connection {
+    #[inline]
+    fn header_name() -> Bytes {
+        CONNECTION
+    }
+}
 
-        let encs = encoding.0.into_iter().map(Bytes::from);
-        for enc in itertools::intersperse(encs, SEP) {
-            bytes.extend_from_slice(&enc);
+impl IntoHeaderValue for Connection {
+    fn into_header_value(self) -> Bytes {
+        match <[Bytes; 1]>::try_from(self.0) {
+            Ok([option]) => option,
+            Err(options) => Bytes::from(options.join(&b", "[..])),
         }
+    }
+}
 
-        Some(bytes.freeze())
+impl From<Bytes> for Connection {
+    fn from(value: Bytes) -> Self {
+        Self(
+            value
+                .split(|&b| b == b',')
+                .map(trim_whitespace)
+                .filter(|option| !option.is_empty())
+                .map(|option| value.slice_ref(option))
+                .collect(),
+        )
     }
 }
 
-#[derive(Debug)]
+/// Cookies sent by the client in the `Cookie` header (see RFC 6265, section 5.4)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 #[repr(transparent)]
-pub struct ContentEncoding(Encoding);
+pub struct Cookies(Vec<(String, String)>);
 
-impl std::fmt::Display for ContentEncoding {
+impl Cookies {
+    /// Value of the first cookie called `name` (compared case-sensitively)
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Names and values of the cookies in the order in which the client sent them
     #[inline]
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Add a cookie (e.g., to send it to another server)
+    #[inline]
+    pub fn add(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.push((name.into(), value.into()));
     }
 }
 
-impl ToHeaderName for ContentEncoding {
+impl ToHeaderName for Cookies {
     #[inline]
     fn header_name() -> Bytes {
-        CONTENT_ENCODING
+        COOKIE
     }
 }
 
-impl IntoHeaderValue for ContentEncoding {
-    #[inline]
+impl IntoHeaderValue for Cookies {
     fn into_header_value(self) -> Bytes {
-        self.0.into()
+        let cookies = self
+            .0
+            .iter()
+            .map(|(name, value)| format!("{name}={}", percent_encode_cookie(value)));
+        itertools::join(cookies, "; ").into()
     }
 }
 
-impl TryFrom<Bytes> for ContentEncoding {
-    type Error = anyhow::Error;
+/// Malformed pairs are skipped and values are percent-decoded
+impl From<Bytes> for Cookies {
+    fn from(value: Bytes) -> Self {
+        let cookies = value
+            .split(|&b| b == b';')
+            .map(trim_whitespace)
+            .filter_map(|pair| {
+                let eq = pair.iter().position(|&b| b == b'=')?;
+                let (name, value) = (
+                    trim_whitespace(&pair[..eq]),
+                    trim_whitespace(&pair[eq + 1..]),
+                );
 
-    #[inline]
-    fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
-        Encoding::try_from(bytes).map(Self)
+                if !is_token(name) {
+                    return None;
+                }
+
+                // NOTE: values may be enclosed in double quotes, which are not part of the value
+                let value = value
+                    .strip_prefix(b"\"")
+                    .and_then(|v| v.strip_suffix(b"\""))
+                    .unwrap_or(value);
+
+                let name = String::from_utf8_lossy(name).into_owned();
+                let value = String::from_utf8_lossy(&percent_decode(value)).into_owned();
+
+                Some((name, value))
+            })
+            .collect();
+
+        Self(cookies)
     }
 }
 
-impl SystemEncoder for ContentEncoding {
-    #[inline]
-    fn program(&self) -> Option<&str> {
-        self.0.program()
+/// Decode `%XX` escapes, invalid escapes are left as they are
+pub(crate) fn percent_decode(bytes: &[u8]) -> Vec<u8> {
+    fn hex(b: u8) -> Option<u8> {
+        (b as char).to_digit(16).map(|d| d as u8)
     }
 
-    #[inline]
-    fn command(&self) -> Option<tokio::process::Command> {
-        self.0.command()
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = match bytes[i..] {
+            [b'%', hi, lo, ..] => hex(hi).zip(hex(lo)).map(|(hi, lo)| hi << 4 | lo),
+            _ => None,
+        };
+
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
     }
+
+    decoded
 }
 
-#[derive(Debug)]
+/// Percent-encode all the characters which are not allowed in cookie values (see RFC 6265,
+/// section 4.1.1) along with `%` itself
+fn percent_encode_cookie(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'!' | b'#'..=b'$' | b'&'..=b'+' | b'-'..=b':' | b'<'..=b'[' | b']'..=b'~' => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{b:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Description of the client software (see RFC 9110, section 10.1.5)
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[repr(transparent)]
-pub struct ContentLength(Bytes);
+pub struct UserAgent(pub Bytes);
 
-impl ToHeaderName for ContentLength {
+impl ToHeaderName for UserAgent {
     #[inline]
     fn header_name() -> Bytes {
-        CONTENT_LENGTH
+        USER_AGENT
     }
 }
 
-impl IntoHeaderValue for ContentLength {
+impl IntoHeaderValue for UserAgent {
     #[inline]
     fn into_header_value(self) -> Bytes {
         self.0
     }
 }
 
-impl From<Bytes> for ContentLength {
+impl From<Bytes> for UserAgent {
     #[inline]
-    fn from(bytes: Bytes) -> Self {
-        Self(bytes)
+    fn from(value: Bytes) -> Self {
+        Self(value)
     }
 }
 
-impl From<u64> for ContentLength {
-    #[inline]
-    fn from(len: u64) -> Self {
-        Self(match len {
-            0 => Bytes::from_static(b"0"),
-            len => len.to_string().into(),
-        })
+/// Headers with comma-separated list values, so that their repeated field lines can be combined
+/// into one (see RFC 9110, section 5.3)
+const LIST_HEADERS: &[&[u8]] = &[
+    b"Accept",
+    b"Accept-Charset",
+    b"Accept-Encoding",
+    b"Accept-Language",
+    b"Accept-Ranges",
+    b"Allow",
+    b"Cache-Control",
+    b"Connection",
+    b"Content-Encoding",
+    b"Content-Language",
+    b"Expect",
+    b"Forwarded",
+    b"If-Match",
+    b"If-None-Match",
+    b"Link",
+    b"Pragma",
+    b"TE",
+    b"Trailer",
+    b"Transfer-Encoding",
+    b"Upgrade",
+    b"Vary",
+    b"Via",
+    b"Warning",
+    b"X-Forwarded-For",
+];
+
+/// Separator of the values of repeated field lines of given header when combined into one, or
+/// `None` if the header is not combinable
+fn list_separator(name: &[u8]) -> Option<&'static [u8]> {
+    if name.matches(COOKIE) {
+        // NOTE: cookie pairs are separated by semicolons (see RFC 6265, section 5.4)
+        Some(b"; ")
+    } else if LIST_HEADERS.iter().any(|h| name.matches(h)) {
+        Some(b", ")
+    } else {
+        None
     }
 }
 
-impl From<ContentLength> for Bytes {
-    #[inline]
-    fn from(ContentLength(len): ContentLength) -> Self {
-        len
+/// Hash of a header name which ignores casing of ASCII characters
+fn name_hash(name: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for b in name {
+        hasher.write_u8(b.to_ascii_lowercase());
+    }
+    hasher.finish()
+}
+
+/// Headers in the order in which they were added, indexed by their (case-insensitive) names
+#[derive(Clone, Default)]
+struct Entries {
+    entries: Vec<(Bytes, Bytes)>,
+    /// Positions of entries by the hash of their name (see [`name_hash`])
+    index: HashMap<u64, Vec<usize>>,
+}
+
+impl Entries {
+    fn push(&mut self, name: Bytes, value: Bytes) {
+        let pos = self.entries.len();
+        self.index.entry(name_hash(&name)).or_default().push(pos);
+        self.entries.push((name, value));
+    }
+
+    /// Positions of all the entries of given header (in the order in which they were added)
+    fn positions<'a>(&'a self, name: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        self.index
+            .get(&name_hash(name))
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(move |&pos| self.entries[pos].0.matches(name))
+    }
+}
+
+impl FromIterator<(Bytes, Bytes)> for Entries {
+    fn from_iter<I: IntoIterator<Item = (Bytes, Bytes)>>(iter: I) -> Self {
+        let mut entries = Self::default();
+        for (name, value) in iter {
+            entries.push(name, value);
+        }
+        entries
     }
 }
 
 // TODO: ideally some persistent map (immutable, with structural sharing)
-#[derive(Clone, Debug)]
+#[derive(Clone, Default)]
 #[repr(transparent)]
-pub struct HeaderMap(Arc<[(Bytes, Bytes)]>);
+pub struct HeaderMap(Arc<Entries>);
+
+impl std::fmt::Debug for HeaderMap {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("HeaderMap").field(&self.0.entries).finish()
+    }
+}
+
+impl FromIterator<(Bytes, Bytes)> for HeaderMap {
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = (Bytes, Bytes)>>(iter: I) -> Self {
+        Self(Arc::new(iter.into_iter().collect()))
+    }
+}
 
 impl HeaderMap {
+    /// Number of header field lines (i.e., repeated headers are counted for each occurrence)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.entries.len()
+    }
+
     #[inline]
-    pub fn from_iter(iter: impl IntoIterator<Item = (Bytes, Bytes)>) -> Self {
-        Self(iter.into_iter().collect())
+    pub fn is_empty(&self) -> bool {
+        self.0.entries.is_empty()
     }
 
     #[inline]
     pub fn iter(&self) -> impl Iterator<Item = (Bytes, Bytes)> + '_ {
         self.0
+            .entries
             .iter()
             .map(|(name, value)| (name.clone(), value.clone()))
     }
 
+    /// Value of header `key`.
+    ///
+    /// If the header is repeated, then the values of list-valued headers (and `Cookie`) are
+    /// combined into one in the order of their field lines. Otherwise the first value is returned,
+    /// see [`Self::get_all`] for the rest.
     pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<Bytes> {
         let key = key.as_ref();
-        self.0.iter().find_map(|(name, value)| {
-            if name.matches(key) {
-                Some(value.clone())
-            } else {
-                None
+        let mut positions = self.0.positions(key);
+
+        let first = self.0.entries[positions.next()?].1.clone();
+
+        let Some(separator) = list_separator(key) else {
+            return Some(first);
+        };
+
+        let Some(pos) = positions.next() else {
+            return Some(first);
+        };
+
+        let mut value = BytesMut::from(&first[..]);
+        for pos in std::iter::once(pos).chain(positions) {
+            let next = &self.0.entries[pos].1;
+            // NOTE: empty list elements are ignored by recipients anyway
+            if next.is_empty() {
+                continue;
             }
+            if !value.is_empty() {
+                value.extend_from_slice(separator);
+            }
+            value.extend_from_slice(next);
+        }
+
+        Some(value.freeze())
+    }
+
+    /// Values of all the field lines of header `key` (in the order in which they were added)
+    pub fn get_all<'a, K>(&'a self, key: K) -> impl Iterator<Item = Bytes> + 'a
+    where
+        K: AsRef<[u8]> + 'a,
+    {
+        let positions = self.0.index.get(&name_hash(key.as_ref()));
+        positions.into_iter().flatten().filter_map(move |&pos| {
+            let (name, value) = &self.0.entries[pos];
+            name.matches(key.as_ref()).then(|| value.clone())
         })
     }
 
+    /// Length of the body declared by the `Content-Length` header (if present).
+    ///
+    /// The header may be repeated (even within a single field line) only with the same value,
+    /// otherwise the message framing is ambiguous and this fails (see RFC 9112, section 6.3).
+    pub fn content_length(&self) -> anyhow::Result<Option<u64>> {
+        let mut length = None;
+
+        for value in self.get_all(CONTENT_LENGTH) {
+            for value in value.split(|&b| b == b',').map(trim_whitespace) {
+                let value = std::str::from_utf8(value)
+                    .ok()
+                    .filter(|v| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()))
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .with_context(|| {
+                        format!(
+                            "invalid Content-Length: {:?}",
+                            String::from_utf8_lossy(value)
+                        )
+                    })?;
+
+                match length {
+                    Some(length) if length != value => {
+                        anyhow::bail!("conflicting Content-Length: {length} and {value}")
+                    }
+                    _ => length = Some(value),
+                }
+            }
+        }
+
+        Ok(length)
+    }
+
     #[inline]
     pub fn extract<V>(&self) -> Option<V>
     where
@@ -231,6 +1899,8 @@ impl HeaderMap {
         self.assoc(H::header_name(), header.into_header_value())
     }
 
+    /// Set header `key` to given value, replacing any previous values in place (or adding the
+    /// header at the end)
     // NOTE: here we'd really benefit from a persistent data structure with structural sharing
     pub fn assoc<K, V>(&self, key: K, val: V) -> Self
     where
@@ -240,17 +1910,61 @@ impl HeaderMap {
         let key = key.into();
         let val = val.into();
 
-        Self::from_iter(self.iter().map(|(k, v)| {
-            if k.matches(&key) {
-                (key.clone(), val.clone())
+        let (first, duplicate) = {
+            let mut positions = self.0.positions(&key);
+            (positions.next(), positions.next().is_some())
+        };
+
+        if !duplicate {
+            let mut map = self.clone();
+            let entries = Arc::make_mut(&mut map.0);
+            match first {
+                Some(pos) => entries.entries[pos] = (key, val),
+                None => entries.push(key, val),
+            }
+            return map;
+        }
+
+        // NOTE: duplicates are dropped, so the positions have to be indexed again
+        Self::from_iter(self.iter().enumerate().filter_map(|(pos, (k, v))| {
+            if Some(pos) == first {
+                Some((key.clone(), val.clone()))
+            } else if k.matches(&key) {
+                None
             } else {
-                (k, v)
+                Some((k, v))
             }
         }))
     }
 
+    /// Remove header `key` (if present)
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Self {
+        let key = key.as_ref();
+
+        if self.0.positions(key).next().is_none() {
+            return self.clone();
+        }
+
+        Self::from_iter(self.iter().filter(|(k, _)| !k.matches(key)))
+    }
+
+    /// Returns `true` iff header `key` is a comma-separated list containing given `token`
+    /// (compared case-insensitively)
+    pub fn contains_token<K: AsRef<[u8]>>(&self, key: K, token: impl AsRef<[u8]>) -> bool {
+        let Some(value) = self.get(key) else {
+            return false;
+        };
+
+        let token = token.as_ref();
+
+        value
+            .split(|&b| b == b',')
+            .map(trim_whitespace)
+            .any(|t| t.matches(token))
+    }
+
     #[inline]
-    pub(crate) fn builder() -> HeaderMapBuilder {
+    pub fn builder() -> HeaderMapBuilder {
         HeaderMapBuilder::default()
     }
 }
@@ -260,9 +1974,32 @@ impl HeaderMap {
 pub struct HeaderMapBuilder(Vec<(Bytes, Bytes)>);
 
 impl HeaderMapBuilder {
-    // TODO: handle duplicate headers
-    #[inline]
+    /// Set header `name` to given value, replacing any previous values (see [`HeaderMap::assoc`])
     pub fn assoc(&mut self, name: Bytes, value: impl Into<Bytes>) {
+        let value = value.into();
+
+        let mut replaced = false;
+        self.0.retain_mut(|(n, v)| {
+            if !n.matches(&name) {
+                return true;
+            }
+            if replaced {
+                return false;
+            }
+            (*n, *v) = (name.clone(), value.clone());
+            replaced = true;
+            true
+        });
+
+        if !replaced {
+            self.0.push((name, value));
+        }
+    }
+
+    /// Add another field line of header `name` after any previous ones (see [`HeaderMap::get`]
+    /// and [`HeaderMap::get_all`])
+    #[inline]
+    pub fn append(&mut self, name: Bytes, value: impl Into<Bytes>) {
         self.0.push((name, value.into()))
     }
 
@@ -272,7 +2009,7 @@ impl HeaderMapBuilder {
 
     #[inline]
     pub fn build(self) -> HeaderMap {
-        HeaderMap(Arc::from(self.0.into_boxed_slice()))
+        HeaderMap::from_iter(self.0)
     }
 }
 
@@ -294,6 +2031,88 @@ where
     data.iter().zip(target.iter()).all(|(&x, &y)| cmp(x, y))
 }
 
+/// Returns `true` iff given bytes form a non-empty `token` (see RFC 9110, section 5.6.2)
+pub(crate) fn is_token(bytes: &[u8]) -> bool {
+    !bytes.is_empty()
+        && bytes
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(b))
+}
+
+/// Header names whose conventional spelling differs from the plain `Title-Case`
+const IRREGULAR_NAMES: &[&[u8]] = &[
+    b"Content-MD5",
+    b"DNT",
+    b"ETag",
+    b"Sec-WebSocket-Accept",
+    b"Sec-WebSocket-Extensions",
+    b"Sec-WebSocket-Key",
+    b"Sec-WebSocket-Protocol",
+    b"Sec-WebSocket-Version",
+    b"TE",
+    b"WWW-Authenticate",
+    b"X-Checksum-SHA256",
+];
+
+/// Canonical spelling of a header name, i.e. `Title-Case` (e.g., `content-type` becomes
+/// `Content-Type`) except for a few names conventionally spelled otherwise (e.g., `ETag`)
+pub(crate) fn canonical_name(name: Bytes) -> Bytes {
+    if let Some(irregular) = IRREGULAR_NAMES.iter().find(|n| name.matches(n)) {
+        return Bytes::from_static(irregular);
+    }
+
+    // NOTE: words start at the beginning and after each dash
+    let starts_word = |i: usize| i == 0 || name[i - 1] == b'-';
+
+    let is_canonical = name.iter().enumerate().all(|(i, b)| {
+        if starts_word(i) {
+            !b.is_ascii_lowercase()
+        } else {
+            !b.is_ascii_uppercase()
+        }
+    });
+
+    if is_canonical {
+        return name;
+    }
+
+    let canonical: Vec<u8> = name
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            if starts_word(i) {
+                b.to_ascii_uppercase()
+            } else {
+                b.to_ascii_lowercase()
+            }
+        })
+        .collect();
+
+    canonical.into()
+}
+
+/// Returns `true` iff given field value contains no CR, LF or NUL characters, which could
+/// otherwise be used to smuggle extra header lines (see RFC 9110, section 5.5)
+#[inline]
+pub(crate) fn is_field_value(bytes: &[u8]) -> bool {
+    !bytes.iter().any(|b| matches!(b, b'\r' | b'\n' | b'\0'))
+}
+
+/// Strips leading and trailing ASCII whitespace
+pub(crate) fn trim_whitespace(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+
+    let end = bytes
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |end| end + 1);
+
+    &bytes[start..end]
+}
+
 fn ignore_case_eq(x: u8, y: u8) -> bool {
     x == y || (x.is_ascii_alphabetic() && y.is_ascii_alphabetic() && x.abs_diff(y) == CASE_SHIFT)
 }
@@ -303,7 +2122,7 @@ trait BytesExt {
     fn matches(&self, target: impl AsRef<[u8]>) -> bool;
 }
 
-impl<'a> BytesExt for &'a [u8] {
+impl BytesExt for &[u8] {
     #[inline]
     fn matches(&self, target: impl AsRef<[u8]>) -> bool {
         compare(ignore_case_eq, self, target)
@@ -316,3 +2135,104 @@ impl BytesExt for Bytes {
         compare(ignore_case_eq, self, target)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Request, Response, StatusCode};
+
+    fn accepted(value: &'static str) -> AcceptEncoding {
+        AcceptEncoding::from(Bytes::from_static(value.as_bytes()))
+    }
+
+    #[test]
+    fn select_by_weight() {
+        let accepted = accepted("gzip;q=0.5, deflate;q=0.8, br");
+
+        assert_eq!(accepted.select(|_| true), Some(Encoding::Br));
+        assert_eq!(
+            accepted.select(|e| *e != Encoding::Br),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(
+            accepted.select(|e| *e == Encoding::Gzip),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(accepted.select(|e| *e == Encoding::Zstd), None);
+    }
+
+    #[test]
+    fn select_in_listed_order() {
+        let accepted = accepted("zstd, GZIP, br;q=1");
+        assert_eq!(accepted.select(|_| true), Some(Encoding::Zstd));
+        assert_eq!(
+            accepted.select(|e| *e != Encoding::Zstd),
+            Some(Encoding::Gzip)
+        );
+    }
+
+    #[test]
+    fn select_any() {
+        let accepted = accepted("br;q=0.5, gzip;q=0, *");
+
+        assert_eq!(
+            accepted.select(|e| *e != Encoding::Compress),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(accepted.select(|e| *e == Encoding::Br), Some(Encoding::Br));
+        assert_eq!(accepted.select(|e| *e == Encoding::Gzip), None);
+    }
+
+    #[test]
+    fn select_none_acceptable() {
+        assert_eq!(accepted("").select(|_| true), None);
+        assert_eq!(accepted("identity").select(|_| true), None);
+        assert_eq!(accepted("gzip;q=0, br;q=x").select(|_| true), None);
+    }
+
+    #[test]
+    fn accepts_identity() {
+        assert!(accepted("").accepts_identity());
+        assert!(accepted("gzip").accepts_identity());
+        assert!(accepted("*;q=0, identity").accepts_identity());
+        assert!(!accepted("gzip, identity;q=0").accepts_identity());
+        assert!(!accepted("gzip, *;q=0").accepts_identity());
+    }
+
+    #[test]
+    fn refuse_unencoded_response() {
+        let refused =
+            HeaderMap::from_iter([(ACCEPT_ENCODING, Bytes::from_static(b"gzip, identity;q=0"))]);
+
+        let resp = || Response::builder(Request::HTTP_1_1).negotiate_encoding(&refused);
+
+        // e.g., the body was too small or of a type not worth compressing
+        let unencoded = resp()
+            .plain(Bytes::from("hello"))
+            .uncompressed()
+            .acceptable();
+        assert_eq!(unencoded.status, StatusCode::NOT_ACCEPTABLE);
+        assert!(unencoded.body.is_empty());
+
+        let encoded = resp()
+            .header(CONTENT_ENCODING, Encoding::GZIP)
+            .plain(Bytes::from("hello"))
+            .acceptable();
+        assert_eq!(encoded.status, StatusCode::OK);
+
+        let error = resp()
+            .status(StatusCode::NOT_FOUND)
+            .plain(Bytes::from("not found"))
+            .uncompressed()
+            .acceptable();
+        assert_eq!(error.status, StatusCode::NOT_FOUND);
+
+        let accepted = HeaderMap::from_iter([(ACCEPT_ENCODING, Bytes::from_static(b"gzip"))]);
+        let unencoded = Response::builder(Request::HTTP_1_1)
+            .negotiate_encoding(&accepted)
+            .plain(Bytes::from("hello"))
+            .uncompressed()
+            .acceptable();
+        assert_eq!(unencoded.status, StatusCode::OK);
+    }
+}
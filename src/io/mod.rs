@@ -1,3 +1,4 @@
+pub(crate) mod pool;
 pub(crate) mod reader;
 pub(crate) mod writer;
 
@@ -0,0 +1,71 @@
+//! Pool of buffers reused for reading request heads across requests and connections
+use std::sync::Mutex;
+
+use bytes::BytesMut;
+
+/// Capacity of newly allocated buffers
+const INIT_CAPACITY: usize = 1024;
+
+/// Maximum number of idle buffers kept in the pool
+const MAX_IDLE: usize = 64;
+
+/// Buffers grown larger than this (e.g., by an unusually large head) are not kept in the pool
+const MAX_CAPACITY: usize = 64 * 1024;
+
+static POOL: Mutex<Vec<PooledBuf>> = Mutex::new(Vec::new());
+
+/// Buffer checked out of the pool, which returns there once dropped.
+///
+/// Parts of the buffer are split off and frozen into the [`bytes::Bytes`] of parsed requests, so
+/// its allocation can only be reclaimed once these are dropped, otherwise a new one is made.
+#[derive(Debug)]
+pub(crate) struct PooledBuf {
+    buf: BytesMut,
+    /// The largest number of bytes used by any single request so far
+    high_water: usize,
+}
+
+impl PooledBuf {
+    /// Take an idle buffer from the pool or allocate a new one
+    pub(crate) fn checkout() -> Self {
+        let idle = POOL.lock().ok().and_then(|mut pool| pool.pop());
+        idle.unwrap_or_else(|| Self {
+            buf: BytesMut::with_capacity(INIT_CAPACITY),
+            high_water: INIT_CAPACITY,
+        })
+    }
+
+    /// Take the buffer for reading next request, with at least the high-water mark of capacity
+    pub(crate) fn take(&mut self) -> BytesMut {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        buf.reserve(self.high_water);
+        buf
+    }
+
+    /// Give back the buffer taken by [`Self::take`] after `used` bytes of it have been read
+    #[inline]
+    pub(crate) fn restore(&mut self, buf: BytesMut, used: usize) {
+        self.buf = buf;
+        self.high_water = self.high_water.max(used.min(MAX_CAPACITY));
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        if self.buf.capacity() > MAX_CAPACITY {
+            return;
+        }
+
+        let Ok(mut pool) = POOL.lock() else {
+            return;
+        };
+
+        if pool.len() < MAX_IDLE {
+            pool.push(Self {
+                buf: std::mem::take(&mut self.buf),
+                high_water: self.high_water,
+            });
+        }
+    }
+}
@@ -1,14 +1,42 @@
-use anyhow::{bail, Context, Result};
+use std::io::ErrorKind;
+
+use anyhow::{Context, Result};
 use bytes::{Bytes, BytesMut};
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 
-use crate::header::{HeaderMap, CONTENT_LENGTH};
+use crate::body::BodySender;
+use crate::header::{is_field_value, is_token, trim_whitespace, HeaderMap, Host, HOST};
+use crate::io::pool::PooledBuf;
 use crate::io::CRLF;
 use crate::{Body, Method, Request};
 
+/// Request bodies longer than this are streamed to handlers as they arrive (see
+/// [`RequestReader::stream_body`]) instead of being read into memory up front
+pub(crate) const MAX_BUFFERED_BODY: usize = 64 * 1024;
+
+/// Maximum size of chunks in which streamed request bodies are forwarded
+const BODY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Default maximum number of header fields of a request
+pub(crate) const MAX_HEADERS: usize = 100;
+
+/// Default maximum total size in bytes of the header section of a request (and also of its chunk
+/// size lines and trailers)
+pub(crate) const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Default maximum size in bytes of a request line (including the line terminator)
+pub(crate) const MAX_REQUEST_LINE: usize = 8 * 1024;
+
 pub struct RequestReader<R> {
     reader: BufReader<R>,
-    // here we'd ideally use some sort of buffer pooling
+    strict: bool,
+    max_headers: usize,
+    max_header_size: usize,
+    max_request_line: usize,
+    /// Buffer for request heads (i.e., request lines and headers)
+    buf: PooledBuf,
+    /// Number of bytes of the current request head read into the buffer so far
+    head_len: usize,
 }
 
 impl<R> RequestReader<R>
@@ -19,54 +47,151 @@ where
     pub fn new(reader: R) -> Self {
         Self {
             reader: BufReader::new(reader),
+            strict: false,
+            max_headers: MAX_HEADERS,
+            max_header_size: MAX_HEADER_SIZE,
+            max_request_line: MAX_REQUEST_LINE,
+            buf: PooledBuf::checkout(),
+            head_len: 0,
         }
     }
 
-    async fn read_segment(&mut self, buf: &mut BytesMut) -> Result<usize> {
-        // TODO: ideally this would read directly into buf or use an inline buffer (i.e., no alloc)
-        let mut aux = Vec::new();
-        let mut len = 0;
+    /// Reject requests with more than `max_headers` header fields or with a header section
+    /// longer than `max_size` bytes with `431 Request Header Fields Too Large`
+    #[inline]
+    pub fn with_header_limits(mut self, max_headers: usize, max_size: usize) -> Self {
+        self.max_headers = max_headers;
+        self.max_header_size = max_size;
+        self
+    }
+
+    /// Reject requests with a request line longer than `max_len` bytes with `414 URI Too Long`
+    #[inline]
+    pub fn with_request_line_limit(mut self, max_len: usize) -> Self {
+        self.max_request_line = max_len;
+        self
+    }
 
+    /// Reject requests with obsolete line folding or lines terminated by a bare LF instead of
+    /// tolerating them (see RFC 9112, sections 2.2 and 5.2)
+    #[inline]
+    pub fn with_strict_parsing(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Release the underlying reader along with any data buffered but not consumed yet
+    #[inline]
+    pub fn into_inner(self) -> BufReader<R> {
+        self.reader
+    }
+
+    /// Read next line into `buf` and return its length without the line terminator, which is
+    /// left out of `buf` as well.
+    ///
+    /// Lines are terminated by CRLF, but a bare LF is accepted too unless parsing is strict.
+    /// Lines longer than `limit` bytes (including the terminator) fail with [`HeadersTooLarge`].
+    async fn read_segment(&mut self, buf: &mut BytesMut, limit: usize) -> Result<usize> {
+        let start = buf.len();
+
+        // NOTE: the line is copied straight from the reader's buffer and read just up to the
+        //  limit, so that the buffer can't grow unboundedly
         loop {
-            let n = self.reader.read_until(b'\n', &mut aux).await?;
-            len += n;
-            if n > 0 && aux[..len].ends_with(CRLF) {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+            }
+
+            let remaining = limit - (buf.len() - start);
+            let available = &available[..available.len().min(remaining)];
+
+            if let Some(end) = available.iter().position(|&b| b == b'\n') {
+                buf.extend_from_slice(&available[..=end]);
+                self.reader.consume(end + 1);
                 break;
             }
+
+            let n = available.len();
+            buf.extend_from_slice(available);
+            self.reader.consume(n);
+
+            if buf.len() - start == limit {
+                return Err(HeadersTooLarge("line too long").into());
+            }
         }
 
-        buf.extend(aux);
+        let line = &buf[start..];
+
+        let len = if line.ends_with(CRLF) {
+            line.len() - 2
+        } else if self.strict {
+            return Err(MalformedRequest("line terminated by a bare LF").into());
+        } else {
+            line.len() - 1
+        };
+
+        buf.truncate(start + len);
+        self.head_len += len;
         Ok(len)
     }
 
     async fn read_request_line(&mut self, buf: &mut BytesMut) -> Result<RequestLine> {
-        let n = self.read_segment(buf).await?;
+        // NOTE: empty lines before the request line are ignored, since some clients send an extra
+        //  CRLF after a request body (see RFC 9112, section 2.2)
+        let n = loop {
+            match self.read_segment(buf, self.max_request_line).await {
+                Ok(0) => continue,
+                Ok(n) => break n,
+                Err(e) if e.is::<HeadersTooLarge>() => return Err(UriTooLong.into()),
+                Err(e) => return Err(e),
+            }
+        };
 
-        // NOTE: strips trailing CRLF
-        let mut req_line = buf.split_to(n - 2);
-        let _ = buf.split_to(2);
+        let req_line = buf.split_to(n).freeze();
 
-        let method = freeze_to_whitespace(&mut req_line);
+        // NOTE: the parts are separated by exactly one space (see RFC 9112, section 3)
+        let mut parts = req_line.split(|&b| b == b' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(MalformedRequest("invalid request line").into());
+        };
 
-        Ok(RequestLine {
-            method: Method::try_from(method).context("invalid method")?,
-            target: freeze_to_whitespace(&mut req_line),
-            version: freeze_to_whitespace(&mut req_line),
-        })
-    }
+        let method = Method::try_from(req_line.slice_ref(method))?;
 
-    async fn read_header(&mut self, buf: &mut BytesMut) -> Result<Option<(Bytes, Bytes)>> {
-        let n = self.read_segment(buf).await.context("header")?;
+        // NOTE: the target must not contain whitespace or control characters
+        if target.is_empty() || target.iter().any(|b| b.is_ascii_control() || *b == b' ') {
+            return Err(MalformedRequest("invalid request target").into());
+        }
 
-        let mut header = buf.split_to(n - 2); // strips trailing CRLF
-        let _ = buf.split_to(2);
+        // NOTE: the version must be `HTTP/x.y` (see RFC 9112, section 2.3)
+        let major = match version {
+            [b'H', b'T', b'T', b'P', b'/', major, b'.', minor]
+                if major.is_ascii_digit() && minor.is_ascii_digit() =>
+            {
+                *major
+            }
+            _ => return Err(MalformedRequest("invalid HTTP version").into()),
+        };
 
-        if header.is_empty() {
-            return Ok(None);
+        if major != b'1' {
+            return Err(VersionNotSupported.into());
         }
 
+        let target = req_line.slice_ref(target);
+        let version = req_line.slice_ref(version);
+
+        Ok(RequestLine {
+            method,
+            target,
+            version,
+        })
+    }
+
+    /// Split a header line into its name and value (without the leading whitespace)
+    fn parse_header(mut header: BytesMut) -> Result<(Bytes, BytesMut)> {
         let Some(colon) = header.iter().position(|&b| b == b':') else {
-            bail!("invalid header: {header:?}");
+            return Err(MalformedRequest("missing colon in header").into());
         };
 
         let mut value = header.split_off(colon).split_off(1); // strip colon
@@ -74,62 +199,389 @@ where
             let _ = value.split_to(non_whitespace);
         }
 
-        Ok(Some((header.freeze(), value.freeze())))
+        // NOTE: this also rejects whitespace before the colon (see RFC 9112, section 5.1)
+        if !is_token(&header) {
+            return Err(MalformedRequest("invalid header name").into());
+        }
+
+        Ok((header.freeze(), value))
     }
 
     async fn read_headers(&mut self, buf: &mut BytesMut) -> Result<HeaderMap> {
         let mut headers = HeaderMap::builder();
 
-        while let Some((name, value)) = self.read_header(buf).await? {
-            headers.assoc(name, value);
+        // NOTE: the last header is kept aside, because its value may continue on next lines
+        let mut last: Option<(Bytes, BytesMut)> = None;
+
+        let mut remaining = self.max_header_size;
+        let mut count = 0;
+
+        loop {
+            let n = self.read_segment(buf, remaining).await.context("header")?;
+            let line = buf.split_to(n);
+
+            // NOTE: the line terminator is counted as well (assuming CRLF)
+            remaining = remaining.saturating_sub(n + 2);
+
+            if line.is_empty() {
+                break;
+            }
+
+            if matches!(line[0], b' ' | b'\t') {
+                if self.strict {
+                    return Err(MalformedRequest("obsolete line folding").into());
+                }
+
+                let Some((_, value)) = last.as_mut() else {
+                    return Err(MalformedRequest("whitespace before the first header").into());
+                };
+
+                // NOTE: the fold is replaced by a single space (see RFC 9112, section 5.2)
+                value.extend_from_slice(b" ");
+                value.extend_from_slice(trim_whitespace(&line));
+                continue;
+            }
+
+            count += 1;
+            if count > self.max_headers {
+                return Err(HeadersTooLarge("too many header fields").into());
+            }
+
+            let header = Self::parse_header(line)?;
+
+            if let Some((name, value)) = last.replace(header) {
+                headers.append(name, Self::header_value(value)?);
+            }
+        }
+
+        if let Some((name, value)) = last {
+            headers.append(name, Self::header_value(value)?);
         }
 
         Ok(headers.build())
     }
 
-    async fn read_body(&mut self, len: usize, buf: &mut BytesMut) -> Result<Body> {
+    #[inline]
+    fn header_value(value: BytesMut) -> Result<Bytes> {
+        if !is_field_value(&value) {
+            return Err(MalformedRequest("invalid header value").into());
+        }
+        Ok(value.freeze())
+    }
+
+    async fn read_body_bytes(&mut self, len: usize) -> Result<Body> {
         if len == 0 {
             return Ok(Body::empty());
         }
 
-        buf.reserve(len);
-        buf.resize(len, 0);
+        let mut buf = BytesMut::zeroed(len);
 
         self.reader.read_exact(&mut buf[..len]).await?;
 
-        Ok(buf.split_to(len).into())
+        Ok(buf.into())
+    }
+
+    /// Wait until the client starts sending next request.
+    ///
+    /// Returns `false` if the client closed the connection instead.
+    pub async fn await_request(&mut self) -> Result<bool> {
+        Ok(!self.reader.fill_buf().await?.is_empty())
+    }
+
+    /// Returns `true` iff the client has already sent more data which does not start with
+    /// a complete request line (e.g., because it sent a longer body than it declared).
+    ///
+    /// Just the data buffered so far is inspected, so this never waits for the client. Since that
+    /// data arrived in the same read as the end of the request, a pipelined request whose request
+    /// line is not there whole is considered unexpected too and the connection should be closed
+    /// rather than risking parsing the rest of a body as the next request.
+    ///
+    /// Note that a body overrun which itself contains a valid request line cannot be told apart
+    /// from a pipelined request.
+    pub fn has_unexpected_data(&self) -> bool {
+        let buf = self.reader.buffer();
+
+        let start = buf.iter().position(|&b| !matches!(b, b'\r' | b'\n'));
+        let Some(start) = start else {
+            return false;
+        };
+
+        let data = &buf[start..];
+        let Some(end) = data.iter().position(|&b| b == b'\n') else {
+            return true;
+        };
+
+        let line = &data[..end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        !is_request_line(line)
     }
 
-    pub async fn read_request(&mut self) -> Result<Request> {
-        let mut buf = BytesMut::with_capacity(1024);
+    /// Read the request line and headers of the next request from the underlying stream.
+    ///
+    /// Returns `None` if the client closed the connection before sending another request.
+    ///
+    /// The request body (if any) is left in the stream and must be consumed by a subsequent call
+    /// to [`Self::read_body`] before reading next request.
+    pub async fn read_head(&mut self) -> Result<Option<RequestHead>> {
+        if self
+            .reader
+            .fill_buf()
+            .await
+            .context("await request")?
+            .is_empty()
+        {
+            return Ok(None);
+        }
+
+        let mut buf = self.buf.take();
+        self.head_len = 0;
 
+        let head = self.read_head_into(&mut buf).await;
+
+        let used = self.head_len;
+        self.buf.restore(buf, used);
+
+        head.map(Some)
+    }
+
+    async fn read_head_into(&mut self, buf: &mut BytesMut) -> Result<RequestHead> {
         let RequestLine {
             method,
             target,
             version,
-        } = self
-            .read_request_line(&mut buf)
-            .await
-            .context("request line")?;
+        } = self.read_request_line(buf).await.context("request line")?;
 
-        let headers = self.read_headers(&mut buf).await.context("headers")?;
+        let headers = self.read_headers(buf).await.context("headers")?;
 
-        // TODO: if we don't know body length after headers, then we should respond with 400/411
-        // determine expected body length (https://stackoverflow.com/a/4826320)
-        let content_length = headers.read(CONTENT_LENGTH).unwrap_or_default();
+        Ok(RequestHead {
+            method,
+            target,
+            version,
+            headers,
+        })
+    }
 
+    /// Read the body of a request with given head and assemble the whole [`Request`]
+    pub async fn read_body(&mut self, head: RequestHead) -> Result<Request> {
         let body = self
-            .read_body(content_length, &mut buf)
+            .read_body_bytes(head.content_length())
             .await
             .context("body")?;
 
-        Ok(Request {
-            method,
-            target,
-            version,
-            headers,
+        Ok(head.into_request(body))
+    }
+
+    /// Forward the request body of `len` bytes in bounded chunks to given `body` as it arrives.
+    ///
+    /// If the body is dropped before the end (e.g., the handler responded without reading it),
+    /// the rest is read and discarded, so that next request can be read from the stream. Read
+    /// failures terminate the body with an error, so the handler does not mistake a partial body
+    /// for a complete one.
+    pub async fn stream_body(&mut self, len: usize, body: &BodySender) -> Result<()> {
+        let mut closed = false;
+
+        if let Err(e) = self.forward_body(len as u64, body, &mut closed).await {
+            let _ = body.abort(e.kind().into()).await;
+            return Err(e).context("body");
+        }
+
+        Ok(())
+    }
+
+    /// Forward a request body with the chunked transfer coding (see RFC 9112, section 7.1) to
+    /// given `body` as it arrives, see [`Self::stream_body`].
+    ///
+    /// Chunk extensions and trailer fields are ignored. Malformed bodies and bodies longer than
+    /// `max` bytes are terminated with an error.
+    pub async fn stream_chunked_body(&mut self, max: Option<u64>, body: &BodySender) -> Result<()> {
+        let mut closed = false;
+
+        if let Err(e) = self.forward_chunked_body(max, body, &mut closed).await {
+            let _ = body.abort(e.kind().into()).await;
+            return Err(e).context("chunked body");
+        }
+
+        Ok(())
+    }
+
+    async fn forward_body(
+        &mut self,
+        len: u64,
+        body: &BodySender,
+        closed: &mut bool,
+    ) -> std::io::Result<()> {
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let size = remaining.min(BODY_CHUNK_SIZE as u64) as usize;
+            let mut chunk = BytesMut::with_capacity(size);
+
+            let n = (&mut self.reader)
+                .take(remaining)
+                .read_buf(&mut chunk)
+                .await?;
+
+            if n == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+
+            remaining -= n as u64;
+
+            if !*closed {
+                *closed = body.send(chunk).await.is_err();
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn forward_chunked_body(
+        &mut self,
+        max: Option<u64>,
+        body: &BodySender,
+        closed: &mut bool,
+    ) -> std::io::Result<()> {
+        let invalid = |msg: &str| std::io::Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        let mut buf = BytesMut::new();
+        let mut total = 0u64;
+
+        loop {
+            buf.clear();
+            let n = self.read_line(&mut buf).await?;
+
+            // NOTE: strips chunk extensions
+            let line = &buf[..n];
+            let size = line.split(|&b| b == b';').next().map(trim_whitespace);
+
+            let size = size
+                .filter(|size| !size.is_empty() && size.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|size| std::str::from_utf8(size).ok())
+                .and_then(|size| u64::from_str_radix(size, 16).ok())
+                .ok_or_else(|| invalid("invalid chunk size"))?;
+
+            if size == 0 {
+                break;
+            }
+
+            total = total.saturating_add(size);
+            if max.is_some_and(|max| total > max) {
+                return Err(std::io::Error::new(ErrorKind::InvalidData, BodyTooLarge));
+            }
+
+            self.forward_body(size, body, closed).await?;
+
+            let mut end = [0; 2];
+            self.reader.read_exact(&mut end).await?;
+            if end != CRLF {
+                return Err(invalid("missing chunk end"));
+            }
+        }
+
+        // the trailer section ends with an empty line
+        loop {
+            buf.clear();
+            if self.read_line(&mut buf).await? == 0 {
+                break Ok(());
+            }
+        }
+    }
+
+    /// Read a line into `buf` (see [`Self::read_segment`])
+    #[inline]
+    async fn read_line(&mut self, buf: &mut BytesMut) -> std::io::Result<usize> {
+        self.read_segment(buf, self.max_header_size)
+            .await
+            .map_err(|e| match e.downcast() {
+                Ok(e) => e,
+                Err(e) if e.is::<MalformedRequest>() || e.is::<HeadersTooLarge>() => {
+                    ErrorKind::InvalidData.into()
+                }
+                Err(_) => ErrorKind::UnexpectedEof.into(),
+            })
+    }
+}
+
+/// Syntax error in a request head, to which the server responds with `400 Bad Request`
+#[derive(Debug, thiserror::Error)]
+#[error("malformed request: {0}")]
+pub(crate) struct MalformedRequest(&'static str);
+
+/// Request head exceeding the limits of the header section, to which the server responds with
+/// `431 Request Header Fields Too Large`
+#[derive(Debug, thiserror::Error)]
+#[error("request header fields too large: {0}")]
+pub(crate) struct HeadersTooLarge(&'static str);
+
+/// Request with a major protocol version other than HTTP/1, to which the server responds with
+/// `505 HTTP Version Not Supported`
+#[derive(Debug, thiserror::Error)]
+#[error("HTTP version not supported")]
+pub(crate) struct VersionNotSupported;
+
+/// Chunked request body exceeding the size limit, to which the server responds with `413 Content
+/// Too Large` (like to a body of too large declared length)
+#[derive(Debug, thiserror::Error)]
+#[error("body is too large")]
+pub(crate) struct BodyTooLarge;
+
+/// Request line exceeding the size limit, to which the server responds with `414 URI Too Long`
+#[derive(Debug, thiserror::Error)]
+#[error("request line too long")]
+pub(crate) struct UriTooLong;
+
+#[derive(Debug)]
+pub struct RequestHead {
+    pub(crate) method: Method,
+    pub(crate) target: Bytes,
+    pub(crate) version: Bytes,
+    pub(crate) headers: HeaderMap,
+}
+
+impl RequestHead {
+    /// Expected length of the request body (`0` if there's no `Content-Length`).
+    ///
+    /// Note that `POST` and `PUT` requests without a declared length are rejected with
+    /// `411 Length Required` (unless their body is chunked).
+    #[inline]
+    pub fn content_length(&self) -> usize {
+        let len = self.headers.content_length().ok().flatten();
+        len.unwrap_or_default() as usize
+    }
+
+    /// Host of the target URI (if sent).
+    ///
+    /// Fails if the `Host` header is invalid or repeated with differing values, or if it's missing
+    /// from an HTTP/1.1 request (see RFC 9112, section 3.2).
+    pub fn host(&self) -> Result<Option<Host>> {
+        let mut hosts = self.headers.get_all(HOST);
+
+        let Some(host) = hosts.next() else {
+            anyhow::ensure!(self.version != Request::HTTP_1_1, "missing Host header");
+            return Ok(None);
+        };
+
+        anyhow::ensure!(hosts.all(|h| h == host), "conflicting Host headers");
+
+        Host::try_from(host)
+            .map(Some)
+            .context("invalid Host header")
+    }
+
+    #[inline]
+    pub fn into_request(self, body: Body) -> Request {
+        Request {
+            method: self.method,
+            target: self.target,
+            path: None,
+            version: self.version,
+            headers: self.headers,
             body,
-        })
+            params: Default::default(),
+            peer: None,
+            client_ip: None,
+        }
     }
 }
 
@@ -140,17 +592,149 @@ struct RequestLine {
     version: Bytes,
 }
 
-fn freeze_to_whitespace(bytes: &mut BytesMut) -> Bytes {
-    let at = bytes
-        .iter()
-        .position(|&b| b.is_ascii_whitespace())
-        .unwrap_or(bytes.len());
+/// Returns `true` iff the `line` (without its terminator) is syntactically a request line, i.e.,
+/// `method SP request-target SP HTTP-version` (see RFC 9112, section 3)
+fn is_request_line(line: &[u8]) -> bool {
+    let mut parts = line.split(|&b| b == b' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+
+    let valid_target = !target.is_empty() && !target.iter().any(|b| b.is_ascii_control());
+
+    let valid_version = matches!(
+        version,
+        [b'H', b'T', b'T', b'P', b'/', major, b'.', minor]
+            if major.is_ascii_digit() && minor.is_ascii_digit()
+    );
+
+    is_token(method) && valid_target && valid_version
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let result = bytes.split_to(at);
+    const REQUEST: &[u8] = b"GET /index.html HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n";
 
-    if let Some(at) = bytes.iter().position(|&b| !b.is_ascii_whitespace()) {
-        let _ = bytes.split_to(at);
+    async fn read_head(reader: &mut RequestReader<&[u8]>) -> Result<RequestHead> {
+        reader
+            .read_head()
+            .await
+            .map(|head| head.expect("request head"))
     }
 
-    result.freeze()
+    async fn read_chunked(data: &'static [u8], max: Option<u64>) -> Result<Bytes> {
+        let mut reader = RequestReader::new(data);
+        let (tx, body) = Body::channel(16);
+
+        reader.stream_chunked_body(max, &tx).await?;
+        drop(tx);
+
+        Ok(body.into_bytes().await?)
+    }
+
+    #[tokio::test]
+    async fn read_head_within_limits() {
+        let mut reader = RequestReader::new(REQUEST)
+            .with_request_line_limit(32)
+            .with_header_limits(2, 64);
+
+        let head = read_head(&mut reader).await.expect("valid request");
+
+        assert_eq!(head.method, Method::Get);
+        assert_eq!(head.target, "/index.html");
+        assert_eq!(head.version, Request::HTTP_1_1);
+        assert_eq!(head.headers.get(HOST), Some(Bytes::from("localhost")));
+    }
+
+    #[tokio::test]
+    async fn request_line_too_long() {
+        let mut reader = RequestReader::new(REQUEST).with_request_line_limit(16);
+
+        let error = read_head(&mut reader).await.expect_err("long request line");
+        assert!(error.is::<UriTooLong>());
+    }
+
+    #[tokio::test]
+    async fn too_many_headers() {
+        let mut reader = RequestReader::new(REQUEST).with_header_limits(1, MAX_HEADER_SIZE);
+
+        let error = read_head(&mut reader).await.expect_err("too many headers");
+        assert!(error.is::<HeadersTooLarge>());
+    }
+
+    #[tokio::test]
+    async fn header_section_too_large() {
+        let mut reader = RequestReader::new(REQUEST).with_header_limits(MAX_HEADERS, 24);
+
+        let error = read_head(&mut reader)
+            .await
+            .expect_err("large header section");
+        assert!(error.is::<HeadersTooLarge>());
+    }
+
+    #[tokio::test]
+    async fn chunked_body() {
+        let body = read_chunked(
+            b"5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nTrailer: x\r\n\r\n",
+            None,
+        )
+        .await
+        .expect("valid chunked body");
+
+        assert_eq!(body, "hello world");
+    }
+
+    #[tokio::test]
+    async fn chunked_body_too_large() {
+        let error = read_chunked(b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n", Some(8))
+            .await
+            .expect_err("body over the limit");
+
+        let error = error.downcast_ref::<std::io::Error>().expect("I/O error");
+
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.get_ref().is_some_and(|e| e.is::<BodyTooLarge>()));
+    }
+
+    #[tokio::test]
+    async fn chunked_body_malformed() {
+        let error = read_chunked(b"x\r\nhello\r\n0\r\n\r\n", None)
+            .await
+            .expect_err("invalid chunk size");
+
+        let error = error.downcast_ref::<std::io::Error>().expect("I/O error");
+
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(!error.get_ref().is_some_and(|e| e.is::<BodyTooLarge>()));
+    }
+
+    async fn unexpected_data(data: &[u8]) -> bool {
+        let mut reader = RequestReader::new(data);
+
+        let head = read_head(&mut reader).await.expect("valid request");
+        reader.read_body(head).await.expect("request body");
+
+        reader.has_unexpected_data()
+    }
+
+    #[tokio::test]
+    async fn body_overrun() {
+        const POST: &str = "POST /files/a HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\nabc";
+
+        // longer bodies than declared are detected even if the overrun looks like a method
+        assert!(unexpected_data(format!("{POST}defgh").as_bytes()).await);
+        assert!(unexpected_data(&[POST.as_bytes(), b"\xff\xfe"].concat()).await);
+
+        // pipelined requests and extra empty lines are fine
+        assert!(!unexpected_data(POST.as_bytes()).await);
+        assert!(!unexpected_data(format!("{POST}\r\n").as_bytes()).await);
+        assert!(!unexpected_data(format!("{POST}GET / HTTP/1.1\r\n").as_bytes()).await);
+
+        // a request line which has not arrived whole can't be told apart from an overrun
+        assert!(unexpected_data(format!("{POST}GET / HT").as_bytes()).await);
+    }
 }
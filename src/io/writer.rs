@@ -1,17 +1,35 @@
-use std::io::{Cursor, Write as _};
+use std::fmt::Write as _;
+use std::io::{Cursor, ErrorKind, IoSlice};
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use tokio::fs::File;
-use tokio::io::{self, AsyncWriteExt, BufWriter};
+use tokio::io::{self, AsyncReadExt as _, AsyncWriteExt, BufWriter};
 
 use crate::body::Body;
-use crate::header::HeaderMap;
+use crate::header::{
+    canonical_name, ContentType, Date, HeaderMap, CHUNKED, DATE, TRANSFER_ENCODING,
+};
 use crate::io::CRLF;
-use crate::{Response, StatusCode};
+use crate::{Request, Response, StatusCode};
+
+/// Maximum size of bodies which are copied into the buffer with the response head, so that small
+/// responses are sent in a single write
+const COALESCE_SIZE: usize = 4 * 1024;
+
+/// Initial capacity of the buffer for rendering response heads
+const HEAD_CAPACITY: usize = 1024;
 
 pub struct ResponseWriter<W> {
     writer: BufWriter<W>,
+    timeout: Option<Duration>,
+    min_compress_size: u64,
+    compress_types: Arc<[String]>,
+    exact_header_case: bool,
+    /// Buffer reused for rendering the head of each response written on the connection
+    head: BytesMut,
 }
 
 impl<W> ResponseWriter<W>
@@ -22,61 +40,252 @@ where
     pub fn new(writer: W) -> Self {
         Self {
             writer: BufWriter::new(writer),
+            timeout: None,
+            min_compress_size: 0,
+            compress_types: Arc::from([String::from("*/*")]),
+            exact_header_case: false,
+            head: BytesMut::with_capacity(HEAD_CAPACITY),
         }
     }
 
-    async fn write_status_line(&mut self, status: StatusCode, version: Bytes) -> Result<()> {
-        self.writer.write_all(&version).await.context("version")?;
+    /// Fail writes of whole responses that don't finish within given `timeout`
+    #[inline]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
 
-        self.writer.write_u8(b' ').await?;
+    /// Send bodies smaller than `min_size` bytes without compressing them
+    #[inline]
+    pub fn with_min_compress_size(mut self, min_size: u64) -> Self {
+        self.min_compress_size = min_size;
+        self
+    }
+
+    /// Compress just bodies whose `Content-Type` matches any of given media ranges (e.g.,
+    /// `text/*`), others are sent as they are
+    #[inline]
+    pub fn with_compress_types(mut self, types: impl Into<Arc<[String]>>) -> Self {
+        self.compress_types = types.into();
+        self
+    }
 
-        let mut buf = [0; 4];
-        let mut w = Cursor::new(&mut buf[..]);
-        let n = write!(w, "{} ", status.as_u16()).map(move |_| w.position())?;
-        debug_assert_eq!(n, 4);
+    /// Write header names exactly as they were given instead of in their canonical `Title-Case`
+    /// (see [`canonical_name`])
+    #[inline]
+    pub fn with_exact_header_case(mut self, exact: bool) -> Self {
+        self.exact_header_case = exact;
+        self
+    }
 
-        self.writer.write_all(&buf).await.context("status code")?;
+    /// Returns `true` iff the response body is worth compressing
+    fn is_compressible(&self, response: &Response) -> bool {
+        response.body.len() >= self.min_compress_size
+            && response
+                .headers
+                .extract::<ContentType>()
+                .is_some_and(|content_type| content_type.is_in(&self.compress_types))
+    }
 
+    #[inline]
+    pub fn into_inner(self) -> BufWriter<W> {
         self.writer
-            .write_all(status.as_str().as_bytes())
-            .await
-            .context("status text")?;
-
-        self.writer.write_all(CRLF).await.context("status end")
     }
 
-    async fn write_header(&mut self, name: Bytes, value: Bytes) -> Result<()> {
-        self.writer.write_all(&name).await.context("name")?;
-        self.writer.write_all(b": ").await.context("separator")?;
-        self.writer.write_all(&value).await.context("value")?;
-        self.writer.write_all(CRLF).await.context("end")
+    /// Render the status line into `head`
+    fn encode_status_line(head: &mut BytesMut, status: StatusCode, version: &[u8]) {
+        head.extend_from_slice(version);
+        // NOTE: writing into a BytesMut never fails
+        let _ = write!(head, " {} {}", status.as_u16(), status.as_str());
+        head.extend_from_slice(CRLF);
     }
 
-    async fn write_headers(&mut self, headers: HeaderMap) -> Result<()> {
+    /// Render the header section, including the empty line that terminates it, into `head`.
+    ///
+    /// Responses are sent with the current `Date` unless they already have one (see RFC 9110,
+    /// section 6.6.1).
+    fn encode_headers(&self, head: &mut BytesMut, headers: HeaderMap) {
+        if headers.get(DATE).is_none() {
+            head.extend_from_slice(&DATE);
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(&Date::current());
+            head.extend_from_slice(CRLF);
+        }
+
         for (name, value) in headers.iter() {
-            self.write_header(name, value).await?;
+            // NOTE: header names are case-insensitive, but some legacy clients only recognize the
+            //  conventional spelling
+            let name = if self.exact_header_case {
+                name
+            } else {
+                canonical_name(name)
+            };
+
+            head.extend_from_slice(&name);
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(&value);
+            head.extend_from_slice(CRLF);
         }
-        self.writer.write_all(CRLF).await.context("headers end")
+
+        head.extend_from_slice(CRLF);
+    }
+
+    /// Write all the `parts` with as few vectored writes as possible, so that e.g. a response head
+    /// is sent in a single `writev` (unless the peer does not keep up)
+    async fn write_parts(&mut self, parts: &[&[u8]]) -> std::io::Result<()> {
+        // NOTE: index of the first part not written completely and the offset of its unwritten rest
+        let (mut i, mut offset) = (0, 0);
+
+        while i < parts.len() {
+            let slices = std::iter::once(&parts[i][offset..])
+                .chain(parts[i + 1..].iter().copied())
+                .map(IoSlice::new)
+                .collect::<Vec<_>>();
+
+            let mut n = self.writer.write_vectored(&slices).await?;
+            if n == 0 {
+                return Err(ErrorKind::WriteZero.into());
+            }
+
+            while i < parts.len() && n >= parts[i].len() - offset {
+                n -= parts[i].len() - offset;
+                i += 1;
+                offset = 0;
+            }
+
+            offset += n;
+        }
+
+        Ok(())
     }
 
+    /// Write an informational (1xx) response consisting of just the status line.
+    pub async fn write_interim(&mut self, status: StatusCode, version: Bytes) -> Result<()> {
+        let mut head = std::mem::take(&mut self.head);
+        Self::encode_status_line(&mut head, status, &version);
+        head.extend_from_slice(CRLF);
+
+        let written = self.writer.write_all(&head).await.context("status line");
+        self.recycle(head);
+        written?;
+
+        self.writer.flush().await.context("flush")
+    }
+
+    /// Return the head buffer for rendering the next response (keeping its capacity)
+    #[inline]
+    fn recycle(&mut self, mut head: BytesMut) {
+        head.clear();
+        self.head = head;
+    }
+
+    #[inline]
     pub async fn write_response(&mut self, response: Response) -> Result<()> {
-        let response = response.compress().await;
+        self.write(response, true).await
+    }
 
-        self.write_status_line(response.status, response.version)
-            .await
-            .context("status line")?;
+    /// Write the response just like [`Self::write_response`] but omit the body (e.g., when
+    /// responding to a `HEAD` request).
+    #[inline]
+    pub async fn write_response_head(&mut self, response: Response) -> Result<()> {
+        self.write(response, false).await
+    }
+
+    async fn write(&mut self, response: Response, with_body: bool) -> Result<()> {
+        match self.timeout {
+            // NOTE: streams may last arbitrarily long, so the timeout applies to each chunk instead
+            Some(_) if response.body.is_stream() => self.write_all(response, with_body).await,
+            Some(timeout) => tokio::time::timeout(timeout, self.write_all(response, with_body))
+                .await
+                .context("response write timed out")?,
+            None => self.write_all(response, with_body).await,
+        }
+    }
 
-        self.write_headers(response.headers)
+    async fn write_all(&mut self, response: Response, with_body: bool) -> Result<()> {
+        // NOTE: compressing tiny bodies costs a program spawn and often even inflates them, and
+        //  already compressed media (e.g., images or archives) just don't get any smaller
+        let response = if self.is_compressible(&response) {
+            response
+        } else {
+            response.uncompressed()
+        };
+
+        let mut response = response
+            .compress()
             .await
-            .context("headers")?;
+            .acceptable()
+            .transfer_encode()
+            .await;
+
+        // HTTP/1.0 clients don't understand chunked encoding and read until the connection closes
+        let chunked = !response.body.is_sized() && response.version == Request::HTTP_1_1;
+        if chunked {
+            // NOTE: chunked must be the final transfer coding (e.g., `gzip, chunked`)
+            let codings = match response.headers.get(TRANSFER_ENCODING) {
+                Some(coding) if !coding.eq_ignore_ascii_case(&CHUNKED) => {
+                    Bytes::from([&coding[..], b", ", &CHUNKED].concat())
+                }
+                _ => CHUNKED,
+            };
+            response.headers = response.headers.assoc(TRANSFER_ENCODING, codings);
+        }
+
+        let mut head = std::mem::take(&mut self.head);
+        Self::encode_status_line(&mut head, response.status, &response.version);
+        self.encode_headers(&mut head, response.headers);
+
+        // NOTE: the head goes out in a single write, together with the body if it's small enough
+        //  to be copied over or at least in a vectored write with a body held in memory
+        let written = match &response.body {
+            Body::Bytes(body) if with_body && body.len() <= COALESCE_SIZE => {
+                head.extend_from_slice(body);
+                self.writer.write_all(&head).await
+            }
+            Body::Bytes(body) if with_body => self.write_parts(&[&head, body]).await,
+            _ => self.writer.write_all(&head).await,
+        };
+
+        self.recycle(head);
+        written.context("response head")?;
 
         match response.body {
-            body if body.is_empty() => {}
+            _ if !with_body => {}
+
+            Body::Stream(mut body) => {
+                self.writer.flush().await.context("flush")?;
+
+                while let Some(chunk) = body.next().await {
+                    let chunk = chunk.context("body")?;
+
+                    // NOTE: an empty chunk would terminate the chunked body
+                    if chunk.is_empty() {
+                        continue;
+                    }
+
+                    match self.timeout {
+                        Some(timeout) => {
+                            tokio::time::timeout(timeout, self.write_chunk(&chunk, chunked))
+                                .await
+                                .context("chunk write timed out")?
+                        }
+                        None => self.write_chunk(&chunk, chunked).await,
+                    }?;
+                }
 
-            Body::Bytes(body) => {
-                self.writer.write_all(&body).await.context("body")?;
+                if chunked {
+                    self.writer
+                        .write_all(b"0\r\n\r\n")
+                        .await
+                        .context("last chunk")?;
+                }
             }
 
+            body if body.is_empty() => {}
+
+            // NOTE: bodies in memory have already been written along with the head
+            Body::Bytes(_) => {}
+
             Body::File(body) => {
                 let mut reader = body.into_reader();
                 io::copy(&mut reader, &mut self.writer)
@@ -87,6 +296,25 @@ where
 
         self.writer.flush().await.context("flush")
     }
+
+    /// Write and flush a single chunk of a streamed body
+    async fn write_chunk(&mut self, chunk: &[u8], chunked: bool) -> Result<()> {
+        if chunked {
+            let size = format!("{:x}\r\n", chunk.len());
+            self.writer
+                .write_all(size.as_bytes())
+                .await
+                .context("chunk size")?;
+        }
+
+        self.writer.write_all(chunk).await.context("chunk")?;
+
+        if chunked {
+            self.writer.write_all(CRLF).await.context("chunk end")?;
+        }
+
+        self.writer.flush().await.context("flush")
+    }
 }
 
 #[repr(transparent)]
@@ -98,15 +326,42 @@ impl FileWriter {
         Self(BufWriter::new(file))
     }
 
-    pub async fn write(&mut self, body: Body) -> io::Result<u64> {
+    /// Write the body into the file and pass each written chunk to `inspect` as well (e.g., to
+    /// compute a digest of the contents)
+    pub async fn write_inspect(
+        &mut self,
+        body: Body,
+        mut inspect: impl FnMut(&[u8]) + Send,
+    ) -> io::Result<u64> {
         let n = match body {
             Body::Bytes(bytes) => {
+                inspect(&bytes);
                 let mut reader = io::BufReader::new(Cursor::new(bytes));
                 io::copy_buf(&mut reader, &mut self.0).await?
             }
             Body::File(file) => {
                 let mut reader = file.into_reader();
-                io::copy(&mut reader, &mut self.0).await?
+                let mut buf = vec![0; 64 * 1024];
+                let mut n = 0;
+                loop {
+                    let read = reader.read(&mut buf).await?;
+                    if read == 0 {
+                        break n;
+                    }
+                    inspect(&buf[..read]);
+                    self.0.write_all(&buf[..read]).await?;
+                    n += read as u64;
+                }
+            }
+            Body::Stream(mut stream) => {
+                let mut n = 0;
+                while let Some(chunk) = stream.next().await {
+                    let chunk = chunk?;
+                    inspect(&chunk);
+                    self.0.write_all(&chunk).await?;
+                    n += chunk.len() as u64;
+                }
+                n
             }
         };
 
@@ -1,39 +1,87 @@
 use std::collections::HashMap;
 use std::io::ErrorKind;
+use std::net::IpAddr;
 use std::num::NonZeroU16;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 use anyhow::{bail, Context, Result};
 use bytes::{Bytes, BytesMut};
-use encoding::SystemEncoder;
+use encoding::{Encoding, SystemEncoder};
 use header::{
-    ContentEncoding, ContentLength, HeaderMapBuilder, ToHeaderName, CONTENT_TYPE, TEXT_PLAIN,
+    is_field_value, is_token, ContentEncoding, ContentLength, ETag, IntoHeaderValue, ToHeaderName,
+    CONTENT_TYPE,
 };
 use tokio::fs;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::timeout;
 
-use crate::body::Body;
-use crate::header::{AcceptEncoding, HeaderMap, CONTENT_ENCODING, OCTET_STREAM};
-use crate::io::{FileWriter, RequestReader, ResponseWriter};
+use crate::header::{
+    AcceptEncoding, Te, ACCEPT_ENCODING, CLOSE, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTINUE, EXPECT, KEEP_ALIVE, TRANSFER_ENCODING,
+};
+use crate::io::reader::{
+    BodyTooLarge, HeadersTooLarge, UriTooLong, VersionNotSupported, MAX_BUFFERED_BODY,
+};
+use crate::io::{RequestReader, ResponseWriter};
+use crate::router::{get, Params};
+use crate::upgrade::{OnUpgrade, Upgraded};
 
-pub use config::Config;
+pub use body::Body;
+pub use config::{Config, ListenAddr, Mount};
+pub use error::ServerError;
+pub use header::{
+    Connection, ContentType, Cookies, Date, HeaderMap, HeaderMapBuilder, Host, HttpDate,
+    IfNoneMatch, MimeType, Range, UserAgent,
+};
+pub use net::Cidr;
+pub use router::Router;
+pub use server::{Listener, PeerAddr, Server};
 
 pub(crate) mod body;
 pub(crate) mod config;
+pub(crate) mod digest;
 pub(crate) mod encoding;
+pub(crate) mod error;
+pub mod extract;
+pub(crate) mod files;
+pub(crate) mod handlers;
 pub(crate) mod header;
 pub(crate) mod io;
+pub(crate) mod lzw;
+pub(crate) mod net;
+pub mod router;
+pub(crate) mod server;
+pub mod sse;
+pub mod upgrade;
+pub mod websocket;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Method {
     Get,
+    Head,
     Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    /// Any other syntactically valid method token (see RFC 9110, section 9.1)
+    Extension(Bytes),
 }
 
 macro_rules! method {
     ($(($method:ident, $name:ident, $enc:literal)),+) => {
         impl Method {
             $(pub const $name: Bytes = Bytes::from_static($enc);)+
+
+            #[inline]
+            pub fn as_bytes(&self) -> &[u8] {
+                match self {
+                    $(Self::$method => $enc,)+
+                    Self::Extension(method) => method.as_ref(),
+                }
+            }
         }
 
         impl TryFrom<Bytes> for Method {
@@ -43,27 +91,220 @@ macro_rules! method {
             fn try_from(method: Bytes) -> Result<Self, Self::Error> {
                 match method.as_ref() {
                     $($enc => Ok(Self::$method),)+
-                    _ => bail!("unknown method '{}'", String::from_utf8_lossy(&method)),
+                    m if header::is_token(m) => Ok(Self::Extension(method)),
+                    _ => bail!("invalid method '{}'", String::from_utf8_lossy(&method)),
                 }
             }
         }
     };
 }
 
-// TODO: other methods
 method! {
     (Get, GET, b"GET"),
-    (Post, POST, b"POST")
+    (Head, HEAD, b"HEAD"),
+    (Post, POST, b"POST"),
+    (Put, PUT, b"PUT"),
+    (Delete, DELETE, b"DELETE"),
+    (Connect, CONNECT, b"CONNECT"),
+    (Options, OPTIONS, b"OPTIONS"),
+    (Trace, TRACE, b"TRACE"),
+    (Patch, PATCH, b"PATCH")
+}
+
+impl std::fmt::Display for Method {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&String::from_utf8_lossy(self.as_bytes()))
+    }
 }
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub struct Request {
     method: Method,
     target: Bytes,
+    /// Normalized path of the target once the request has been routed
+    path: Option<Bytes>,
     version: Bytes,
     headers: HeaderMap,
     body: Body,
+    params: Params,
+    peer: Option<IpAddr>,
+    client_ip: Option<IpAddr>,
+}
+
+impl Request {
+    pub const HTTP_1_0: Bytes = Bytes::from_static(b"HTTP/1.0");
+    pub const HTTP_1_1: Bytes = Bytes::from_static(b"HTTP/1.1");
+
+    /// Start building a `GET / HTTP/1.1` request (e.g., to call a handler without a connection)
+    #[inline]
+    pub fn builder() -> RequestBuilder {
+        RequestBuilder {
+            method: Method::Get,
+            target: Bytes::from_static(b"/"),
+            version: Self::HTTP_1_1,
+            headers: HeaderMap::builder(),
+            peer: None,
+        }
+    }
+
+    #[inline]
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// Request target as sent by the client (i.e., the path along with the query)
+    #[inline]
+    pub fn target(&self) -> &Bytes {
+        &self.target
+    }
+
+    /// Protocol version of the request (e.g., [`Request::HTTP_1_1`])
+    #[inline]
+    pub fn version(&self) -> &Bytes {
+        &self.version
+    }
+
+    #[inline]
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    #[inline]
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    #[inline]
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+
+    /// Path component of the request target (i.e., without the query).
+    ///
+    /// Once the request has been routed, this is the normalized path: percent-decoded (except for
+    /// `%`, `/` and control characters) and without any dot segments (see [`Router::handle`]).
+    pub fn path(&self) -> Bytes {
+        if let Some(ref path) = self.path {
+            return path.clone();
+        }
+
+        match self.target.iter().position(|&b| b == b'?') {
+            Some(at) => self.target.slice(..at),
+            None => self.target.clone(),
+        }
+    }
+
+    /// Query component of the request target (i.e., the part after `?`) if there's any
+    pub fn query(&self) -> Option<&[u8]> {
+        let at = self.target.iter().position(|&b| b == b'?')?;
+        Some(&self.target[at + 1..])
+    }
+
+    /// IP address of the connected peer (`None` if not connected over TCP)
+    #[inline]
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        self.peer
+    }
+
+    /// IP address of the client which originated the request.
+    ///
+    /// This is the peer address unless the peer is a trusted proxy (see
+    /// [`Config::trusted_proxies`]), in which case the client is resolved from the `Forwarded` or
+    /// `X-Forwarded-For` headers.
+    #[inline]
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    /// Host of the target URI as sent by the client in the `Host` header (required for HTTP/1.1)
+    #[inline]
+    pub fn host(&self) -> Option<Host> {
+        self.headers.extract()
+    }
+
+    /// Cookies sent by the client (empty if there are none)
+    #[inline]
+    pub fn cookies(&self) -> Cookies {
+        self.headers.extract().unwrap_or_default()
+    }
+
+    /// Returns `true` iff the connection should persist after responding to this request.
+    ///
+    /// HTTP/1.1 connections are persistent unless the client sends `Connection: close`, whereas
+    /// older protocol versions must opt in with `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let connection: Connection = self.headers.extract().unwrap_or_default();
+        if connection.contains(CLOSE) {
+            false
+        } else if connection.contains(KEEP_ALIVE) {
+            true
+        } else {
+            self.version == Self::HTTP_1_1
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RequestBuilder {
+    method: Method,
+    target: Bytes,
+    version: Bytes,
+    headers: HeaderMapBuilder,
+    peer: Option<IpAddr>,
+}
+
+impl RequestBuilder {
+    #[inline]
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = method;
+        self
+    }
+
+    #[inline]
+    pub fn target(mut self, target: impl Into<Bytes>) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    #[inline]
+    pub fn version(mut self, version: Bytes) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Add another field line of header `name` after any previous ones
+    #[inline]
+    pub fn header(mut self, name: Bytes, value: impl Into<Bytes>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Set the IP address of the peer, which is also taken as the client address
+    #[inline]
+    pub fn peer_ip(mut self, ip: IpAddr) -> Self {
+        self.peer = Some(ip);
+        self
+    }
+
+    #[inline]
+    pub fn build(self) -> Request {
+        self.body(Body::empty())
+    }
+
+    pub fn body(self, body: impl Into<Body>) -> Request {
+        Request {
+            method: self.method,
+            target: self.target,
+            path: None,
+            version: self.version,
+            headers: self.headers.build(),
+            body: body.into(),
+            params: Params::new(),
+            peer: self.peer,
+            client_ip: self.peer,
+        }
+    }
 }
 
 macro_rules! status_code {
@@ -92,11 +333,39 @@ macro_rules! status_code {
 pub struct StatusCode(NonZeroU16);
 
 status_code! {
+    (CONTINUE, 100, "Continue"),
+    (SWITCHING_PROTOCOLS, 101, "Switching Protocols"),
     (OK, 200, "OK"),
     (CREATED, 201, "Created"),
+    (NO_CONTENT, 204, "No Content"),
+    (PARTIAL_CONTENT, 206, "Partial Content"),
+    (MOVED_PERMANENTLY, 301, "Moved Permanently"),
+    (NOT_MODIFIED, 304, "Not Modified"),
+    (PERMANENT_REDIRECT, 308, "Permanent Redirect"),
     (BAD_REQUEST, 400, "Bad Request"),
+    (UNAUTHORIZED, 401, "Unauthorized"),
+    (FORBIDDEN, 403, "Forbidden"),
     (NOT_FOUND, 404, "Not Found"),
-    (INTERNAL_SERVER_ERROR, 500, "Internal Server Error")
+    (METHOD_NOT_ALLOWED, 405, "Method Not Allowed"),
+    (NOT_ACCEPTABLE, 406, "Not Acceptable"),
+    (REQUEST_TIMEOUT, 408, "Request Timeout"),
+    (CONFLICT, 409, "Conflict"),
+    (LENGTH_REQUIRED, 411, "Length Required"),
+    (PRECONDITION_FAILED, 412, "Precondition Failed"),
+    (CONTENT_TOO_LARGE, 413, "Content Too Large"),
+    (URI_TOO_LONG, 414, "URI Too Long"),
+    (UNSUPPORTED_MEDIA_TYPE, 415, "Unsupported Media Type"),
+    (RANGE_NOT_SATISFIABLE, 416, "Range Not Satisfiable"),
+    (EXPECTATION_FAILED, 417, "Expectation Failed"),
+    (UNPROCESSABLE_CONTENT, 422, "Unprocessable Content"),
+    (UPGRADE_REQUIRED, 426, "Upgrade Required"),
+    (REQUEST_HEADER_FIELDS_TOO_LARGE, 431, "Request Header Fields Too Large"),
+    (INTERNAL_SERVER_ERROR, 500, "Internal Server Error"),
+    (NOT_IMPLEMENTED, 501, "Not Implemented"),
+    (BAD_GATEWAY, 502, "Bad Gateway"),
+    (SERVICE_UNAVAILABLE, 503, "Service Unavailable"),
+    (GATEWAY_TIMEOUT, 504, "Gateway Timeout"),
+    (HTTP_VERSION_NOT_SUPPORTED, 505, "HTTP Version Not Supported")
 }
 
 impl StatusCode {
@@ -119,29 +388,184 @@ pub struct Response {
     pub(crate) status: StatusCode,
     pub(crate) headers: HeaderMap,
     pub(crate) body: Body,
+    pub(crate) upgrade: Option<OnUpgrade>,
+    /// Whether the client refused unencoded content (e.g., `identity;q=0`), see
+    /// [`Self::acceptable`]
+    pub(crate) identity_refused: bool,
 }
 
 impl Response {
-    #[inline]
-    pub fn from_request(request: &Request) -> ResponseBuilder {
-        let mut headers = HashMap::with_capacity(4);
+    /// Empty HTTP/1.1 response with given status which closes the connection.
+    ///
+    /// This is useful when responding before a request could be read.
+    pub(crate) fn closing(status: StatusCode) -> Self {
+        let mut headers = HeaderMap::builder();
+        headers.insert(Connection::close());
+        headers.insert(ContentLength::from(0));
 
-        let accept_encoding = request.headers.extract::<AcceptEncoding>();
-        let supported = Config::encodings();
+        Self {
+            version: Request::HTTP_1_1,
+            status,
+            headers: headers.build(),
+            body: Body::empty(),
+            upgrade: None,
+            identity_refused: false,
+        }
+    }
 
-        let content_encoding = accept_encoding
-            .and_then(|enc| enc.select(supported))
-            .map(Bytes::from);
+    /// Plain text HTTP/1.1 response with given status and reason which closes the connection (see
+    /// [`Self::closing`])
+    pub(crate) fn closing_with_reason(status: StatusCode, reason: impl Into<Bytes>) -> Self {
+        let reason = reason.into();
 
-        if let Some(encoding) = content_encoding {
-            headers.insert(CONTENT_ENCODING, encoding);
+        let mut headers = HeaderMap::builder();
+        headers.insert(Connection::close());
+        headers.assoc(CONTENT_TYPE, MimeType::TextPlain);
+        headers.insert(ContentLength::from(reason.len() as u64));
+
+        Self {
+            version: Request::HTTP_1_1,
+            status,
+            headers: headers.build(),
+            body: reason.into(),
+            upgrade: None,
+            identity_refused: false,
         }
+    }
 
+    /// HTTP/1.1 `101 Switching Protocols` response with given headers which hands the connection
+    /// over to `on_upgrade` once it's sent.
+    ///
+    /// Note that the response has no body nor `Content-Length`.
+    pub(crate) fn switching_protocols(headers: HeaderMap, on_upgrade: OnUpgrade) -> Self {
+        Self {
+            version: Request::HTTP_1_1,
+            status: StatusCode::SWITCHING_PROTOCOLS,
+            headers,
+            body: Body::empty(),
+            upgrade: Some(on_upgrade),
+            identity_refused: false,
+        }
+    }
+
+    /// Start building a response to given request, with content and transfer codings negotiated
+    /// from its headers (see [`ResponseBuilder::negotiate_encoding`])
+    #[inline]
+    pub fn from_request(request: &Request) -> ResponseBuilder {
+        Self::builder(request.version.clone()).negotiate_encoding(&request.headers)
+    }
+
+    /// Start building a response of given protocol version (e.g., [`Request::HTTP_1_1`]).
+    ///
+    /// Unlike [`Self::from_request`], the body is sent as it is unless encoding is negotiated
+    /// explicitly with [`ResponseBuilder::negotiate_encoding`].
+    #[inline]
+    pub fn builder(version: Bytes) -> ResponseBuilder {
         ResponseBuilder {
-            version: request.version.clone(),
+            version,
             status: StatusCode::default(),
-            headers,
+            headers: HashMap::with_capacity(4),
             body: BytesMut::new(),
+            identity_refused: false,
+        }
+    }
+
+    /// Replace a successful response whose body ends up without any content coding by an empty
+    /// `406 Not Acceptable` if the client refused unencoded content (see RFC 9110, section 12.5.3).
+    ///
+    /// This must be decided only after [`Self::compress`], since the body may be sent unencoded
+    /// despite the negotiated `Content-Encoding` (e.g., when it's too small or not compressible).
+    pub(crate) fn acceptable(self) -> Self {
+        let unencoded = (200..300).contains(&self.status.as_u16())
+            && !self.body.is_empty()
+            && self.headers.get(CONTENT_ENCODING).is_none();
+
+        if !self.identity_refused || !unencoded {
+            return self;
+        }
+
+        let mut headers = HeaderMap::builder();
+        if let Some(connection) = self.headers.get(CONNECTION) {
+            headers.assoc(CONNECTION, connection);
+        }
+        headers.insert(ContentLength::from(0));
+
+        Self {
+            version: self.version,
+            status: StatusCode::NOT_ACCEPTABLE,
+            headers: headers.build(),
+            body: Body::empty(),
+            upgrade: None,
+            identity_refused: false,
+        }
+    }
+
+    /// Send the body as it is without `Content-Encoding` nor a compressing `Transfer-Encoding`,
+    /// unless it's a file that is already encoded (see [`Self::compress`])
+    pub(crate) fn uncompressed(self) -> Self {
+        if matches!(&self.body, Body::File(file) if file.is_precompressed()) {
+            return self;
+        }
+
+        Self {
+            headers: self
+                .headers
+                .remove(CONTENT_ENCODING)
+                .remove(TRANSFER_ENCODING),
+            ..self
+        }
+    }
+
+    /// Apply the transfer coding given by the `Transfer-Encoding` header, the encoded body is
+    /// streamed (and thus sent chunked, see RFC 9112, section 6.1).
+    ///
+    /// The coding is dropped if the body is empty, streamed or already compressed (see
+    /// [`Self::compress`]). It's also dropped if the compression program cannot be started, and
+    /// if it's unavailable altogether, the encoding is not used for any subsequent responses.
+    pub async fn transfer_encode(self) -> Self {
+        let Some(encoding) = self
+            .headers
+            .get(TRANSFER_ENCODING)
+            .and_then(|coding| Encoding::try_from(coding).ok())
+        else {
+            return self;
+        };
+
+        // NOTE: like with compression, streams are sent as they are, so that e.g. events are not
+        //  held back by the program
+        if self.body.is_empty()
+            || self.body.is_stream()
+            || self.headers.get(CONTENT_ENCODING).is_some()
+        {
+            return Self {
+                headers: self.headers.remove(TRANSFER_ENCODING),
+                ..self
+            };
+        }
+
+        let cmd = match encoding.spawn().await {
+            Ok(cmd) => cmd,
+            Err(error) => {
+                eprintln!("failed to apply {encoding} transfer coding: {error:#}");
+                if encoding::is_program_unavailable(&error) {
+                    encoding::set_unavailable(encoding);
+                }
+                return Self {
+                    headers: self.headers.remove(TRANSFER_ENCODING),
+                    ..self
+                };
+            }
+        };
+
+        let body = encoding::stream_through(cmd, self.body, || {
+            std::io::Error::other("compression program failed")
+        });
+
+        Self {
+            // the length of the encoded body is not known in advance
+            headers: self.headers.remove(CONTENT_LENGTH),
+            body,
+            ..self
         }
     }
 
@@ -150,20 +574,93 @@ impl Response {
     /// Returns
     ///  - Original response if no Content-Encoding was given in headers
     ///  - Response with (`Byte`) body encoded by the `Content-Encoding` algorithm
-    ///  - Internal Server Error response with a plain text body with a compression error
+    ///  - Original response without Content-Encoding if the compression failed, the encoding is
+    ///    then not used for any subsequent responses if its program is unavailable altogether
+    ///  - Internal Server Error response with a plain text body with a compression error if the
+    ///    original body could not be recovered
     pub async fn compress(self) -> Self {
+        if self.body.is_stream() {
+            // NOTE: compression programs need the whole body, so streams are sent as they are
+            return Self {
+                headers: self.headers.remove(CONTENT_ENCODING),
+                ..self
+            };
+        }
+
         let Some(content_encoding) = self.headers.extract::<ContentEncoding>() else {
             return self;
         };
 
+        if matches!(&self.body, Body::File(file) if file.is_precompressed()) {
+            return self;
+        }
+
         let version = self.version.clone();
 
-        content_encoding.compress(self.body).await.map_or_else(
+        // NOTE: whole files are compressed just once, until they're modified (or evicted)
+        let file = match &self.body {
+            Body::File(file) if !file.is_partial() => Some(file.metadata().clone()),
+            _ => None,
+        };
+
+        let encoding = Encoding::from(&content_encoding);
+
+        let compressed = match file
+            .as_ref()
+            .and_then(|meta| encoding::cached(meta, encoding))
+        {
+            Some(cached) => Ok(Body::bytes(cached)),
+            None => {
+                // NOTE: the original body is kept aside, so it can be sent if the compression fails
+                let original = match &self.body {
+                    Body::Bytes(bytes) => Some(Body::Bytes(bytes.clone())),
+                    Body::File(file) => file.try_clone().await.ok().map(Body::File),
+                    Body::Stream(_) => None,
+                };
+
+                let compressed = content_encoding.compress(self.body).await;
+                if let (Some(meta), Ok(Body::Bytes(bytes))) = (&file, &compressed) {
+                    encoding::cache(meta, encoding, bytes.clone());
+                }
+
+                match (compressed, original) {
+                    (Err(error), Some(original)) => {
+                        eprintln!("failed to compress response with {encoding}: {error:#}");
+                        if encoding::is_program_unavailable(&error) {
+                            encoding::set_unavailable(encoding);
+                        }
+
+                        let body = match original {
+                            // NOTE: the clone shares the offset which the program has moved
+                            Body::File(mut file) => file.rewind().await.ok().map(|_| file.into()),
+                            body => Some(body),
+                        };
+
+                        match body {
+                            Some(body) => {
+                                return Self {
+                                    version,
+                                    status: self.status,
+                                    headers: self.headers.remove(CONTENT_ENCODING),
+                                    body,
+                                    upgrade: self.upgrade,
+                                    identity_refused: self.identity_refused,
+                                }
+                            }
+                            None => Err(error),
+                        }
+                    }
+                    (compressed, _) => compressed,
+                }
+            }
+        };
+
+        compressed.map_or_else(
             |error| {
                 let body = Body::bytes(error.to_string());
 
                 let mut headers = HeaderMapBuilder::default();
-                headers.assoc(CONTENT_TYPE, TEXT_PLAIN);
+                headers.assoc(CONTENT_TYPE, MimeType::TextPlain);
                 headers.insert(body.content_length());
 
                 Response {
@@ -171,13 +668,25 @@ impl Response {
                     status: StatusCode::INTERNAL_SERVER_ERROR,
                     headers: headers.build(),
                     body,
+                    upgrade: None,
+                    identity_refused: false,
                 }
             },
-            |body| Response {
-                version: self.version,
-                status: self.status,
-                headers: self.headers.insert(body.content_length()),
-                body,
+            |body| {
+                let mut headers = self.headers.insert(body.content_length());
+
+                if let Some(etag) = headers.extract::<ETag>() {
+                    headers = headers.insert(etag.encoded(&content_encoding));
+                }
+
+                Response {
+                    version: self.version,
+                    status: self.status,
+                    headers,
+                    body,
+                    upgrade: self.upgrade,
+                    identity_refused: self.identity_refused,
+                }
             },
         )
     }
@@ -189,35 +698,95 @@ pub struct ResponseBuilder {
     status: StatusCode,
     headers: HashMap<Bytes, Bytes>,
     body: BytesMut,
+    identity_refused: bool,
 }
 
 impl ResponseBuilder {
+    /// Select the `Content-Encoding` and (on HTTP/1.1) a compressing `Transfer-Encoding` of the
+    /// response from the `Accept-Encoding` and `TE` request headers
+    pub fn negotiate_encoding(mut self, headers: &HeaderMap) -> Self {
+        let supported = Config::encodings();
+
+        let accepted = headers.extract::<AcceptEncoding>();
+
+        let content_encoding = accepted
+            .as_ref()
+            .and_then(|enc| enc.select(|e| supported.contains(e) && encoding::is_available(*e)))
+            .map(Bytes::from);
+
+        // NOTE: whether the body is eventually compressed is decided just before it's sent
+        self.identity_refused = accepted.is_some_and(|enc| !enc.accepts_identity());
+
+        if let Some(encoding) = content_encoding {
+            self.headers.insert(CONTENT_ENCODING, encoding);
+        }
+
+        // NOTE: transfer codings are hop-by-hop, so they're applied only on HTTP/1.1 connections
+        if self.version == Request::HTTP_1_1 {
+            let transfer_encoding = headers.extract::<Te>().and_then(|te| {
+                te.select(|e| {
+                    e.is_transfer_coding() && supported.contains(e) && encoding::is_available(*e)
+                })
+            });
+
+            if let Some(encoding) = transfer_encoding {
+                self.headers
+                    .insert(TRANSFER_ENCODING, Bytes::from(encoding));
+            }
+        }
+
+        self
+    }
+
     #[inline]
     pub fn status(mut self, status: StatusCode) -> Self {
         self.status = status;
         self
     }
 
+    /// Set header `name` to given value.
+    ///
+    /// Headers with invalid names or values (e.g., containing line breaks) are dropped, so that
+    /// echoing user input in a header can't split the response.
     pub fn header(mut self, name: Bytes, value: Bytes) -> Self {
+        if !is_token(&name) || !is_field_value(&value) {
+            eprintln!("dropping invalid response header {name:?}: {value:?}");
+            return self;
+        }
         self.headers.insert(name, value);
         self
     }
 
-    fn build_response(
-        version: Bytes,
-        status: StatusCode,
-        mut headers: HashMap<Bytes, Bytes>,
-        body: Body,
-    ) -> Response {
-        // insert/overwrite with the final content length
-        let content_length = body.content_length();
-        headers.insert(ContentLength::header_name(), content_length.into());
+    fn build_response(self, body: Body) -> Response {
+        let Self {
+            version,
+            status,
+            mut headers,
+            identity_refused,
+            ..
+        } = self;
+
+        // NOTE: responses which never have content must not have Content-Length either (see RFC
+        //  9110, section 8.6)
+        let contentless = status.as_u16() < 200
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED;
+
+        // insert/overwrite with the final content length (streams are sent chunked instead)
+        if contentless {
+            headers.remove(&ContentLength::header_name());
+        } else if body.is_sized() {
+            let content_length = body.content_length();
+            headers.insert(ContentLength::header_name(), content_length.into());
+        }
 
         Response {
             version,
             status,
             headers: HeaderMap::from_iter(headers),
             body,
+            upgrade: None,
+            identity_refused,
         }
     }
 
@@ -227,134 +796,595 @@ impl ResponseBuilder {
     }
 
     #[inline]
-    pub fn plain(mut self, body: impl Into<Body>) -> Response {
-        self = self.header(CONTENT_TYPE, TEXT_PLAIN);
-        Self::build_response(self.version, self.status, self.headers, body.into())
+    pub fn plain(self, body: impl Into<Body>) -> Response {
+        self.with_content_type(MimeType::TextPlain, body)
     }
 
-    pub async fn file(mut self, path: PathBuf) -> Response {
-        let file = match fs::OpenOptions::new().read(true).open(path.as_path()).await {
-            Ok(file) => file,
-            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::PermissionDenied) => {
-                return self.status(StatusCode::NOT_FOUND).empty()
-            }
-            Err(_) => return self.status(StatusCode::INTERNAL_SERVER_ERROR).empty(),
-        };
+    #[inline]
+    pub fn html(self, body: impl Into<Body>) -> Response {
+        self.with_content_type(MimeType::TextHtml, body)
+    }
+
+    /// Respond with an already serialized JSON document (`application/json`)
+    #[inline]
+    pub fn json(self, body: impl Into<Body>) -> Response {
+        self.with_content_type(MimeType::Json, body)
+    }
 
-        let body = match Body::file(path, file).await {
-            Ok(body) => body,
-            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::PermissionDenied) => {
-                return self.status(StatusCode::NOT_FOUND).empty()
+    /// Respond with given body of given media type (e.g., a [`MimeType`] or a parsed
+    /// [`ContentType`] with custom parameters)
+    pub fn with_content_type(
+        mut self,
+        content_type: impl Into<ContentType>,
+        body: impl Into<Body>,
+    ) -> Response {
+        self = self.header(CONTENT_TYPE, content_type.into().into_header_value());
+        self.build_response(body.into())
+    }
+
+    /// Respond with the contents of given file.
+    ///
+    /// Fails with [`ServerError::NotFound`] if the file does not exist or is not accessible.
+    pub async fn file(mut self, path: PathBuf) -> Result<Response, ServerError> {
+        fn not_found(e: std::io::Error) -> ServerError {
+            if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::PermissionDenied) {
+                ServerError::NotFound
+            } else {
+                ServerError::Io(e)
             }
-            Err(_) => return self.status(StatusCode::INTERNAL_SERVER_ERROR).empty(),
-        };
+        }
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .open(path.as_path())
+            .await
+            .map_err(not_found)?;
+
+        let body = Body::file(file).await.map_err(not_found)?;
 
-        self = self.header(CONTENT_TYPE, OCTET_STREAM);
+        self = self.header(CONTENT_TYPE, MimeType::OctetStream.into());
 
-        Self::build_response(self.version, self.status, self.headers, body)
+        Ok(self.build_response(body))
     }
 
     #[inline]
-    pub fn build(self) -> Response {
-        Self::build_response(self.version, self.status, self.headers, self.body.into())
+    pub fn build(mut self) -> Response {
+        let body = std::mem::take(&mut self.body).into();
+        self.build_response(body)
     }
+
+    /// Build the response with given body without setting any `Content-Type`
+    #[inline]
+    pub fn body(self, body: impl Into<Body>) -> Response {
+        self.build_response(body.into())
+    }
+}
+
+/// Conversion of handler results into a [`Response`].
+///
+/// The conversion is given a [`ResponseBuilder`] created from the request being handled, so the
+/// resulting response uses the correct protocol version and content encoding.
+pub trait IntoResponse {
+    fn into_response(self, resp: ResponseBuilder) -> Response;
+}
+
+impl IntoResponse for Response {
+    #[inline]
+    fn into_response(self, _resp: ResponseBuilder) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for ResponseBuilder {
+    #[inline]
+    fn into_response(self, _resp: ResponseBuilder) -> Response {
+        self.build()
+    }
+}
+
+/// Empty response with given status
+impl IntoResponse for StatusCode {
+    #[inline]
+    fn into_response(self, resp: ResponseBuilder) -> Response {
+        resp.status(self).build()
+    }
+}
+
+/// Response with given body but no `Content-Type`
+impl IntoResponse for Body {
+    #[inline]
+    fn into_response(self, resp: ResponseBuilder) -> Response {
+        resp.body(self)
+    }
+}
+
+/// Plain text response
+impl IntoResponse for Bytes {
+    #[inline]
+    fn into_response(self, resp: ResponseBuilder) -> Response {
+        resp.plain(self)
+    }
+}
+
+/// Plain text response
+impl IntoResponse for String {
+    #[inline]
+    fn into_response(self, resp: ResponseBuilder) -> Response {
+        resp.plain(self)
+    }
+}
+
+/// Plain text response
+impl IntoResponse for &'static str {
+    #[inline]
+    fn into_response(self, resp: ResponseBuilder) -> Response {
+        resp.plain(Bytes::from_static(self.as_bytes()))
+    }
+}
+
+/// Response given by the second component with the status overridden by the first one
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    #[inline]
+    fn into_response(self, resp: ResponseBuilder) -> Response {
+        let (status, inner) = self;
+        let mut resp = inner.into_response(resp);
+        resp.status = status;
+        resp
+    }
+}
+
+impl<T, E> IntoResponse for Result<T, E>
+where
+    T: IntoResponse,
+    E: IntoResponse,
+{
+    #[inline]
+    fn into_response(self, resp: ResponseBuilder) -> Response {
+        match self {
+            Ok(ok) => ok.into_response(resp),
+            Err(err) => err.into_response(resp),
+        }
+    }
+}
+
+/// Default routes of the server
+pub fn routes(cfg: &Config) -> Router {
+    let router = Router::new()
+        .route("/", get(handlers::index))
+        .trailing_slash(cfg.trailing_slash())
+        .route("/user-agent", get(handlers::user_agent))
+        .route("/echo/{*msg}", get(handlers::echo))
+        .route("/ws/echo", get(handlers::ws_echo))
+        .nest(
+            "/files",
+            Router::new().route("/{*file}", files::files(cfg.files_dir(), cfg.into())),
+        );
+
+    cfg.mounts().iter().fold(router, |router, mount| {
+        router.mount_dir(
+            mount.prefix(),
+            mount.dir(),
+            files::ServeOptions::mount(cfg, mount),
+        )
+    })
 }
 
+/// Number of chunks of a streamed request body buffered before the handler consumes them
+const UPLOAD_CAPACITY: usize = 4;
+
 /// Handle a HTTP/1.1 client connection
-pub async fn handle_connection(mut stream: TcpStream, cfg: &Config) -> Result<()> {
-    let (reader, writer) = stream.split();
-    let mut reader = RequestReader::new(reader);
-    let mut writer = ResponseWriter::new(writer);
+///
+/// Requests are served in a loop over the same connection until either side asks to close it
+/// (see [`Request::keep_alive`]), the client disconnects or stays idle for longer than
+/// [`Config::idle_timeout`]. The connection is also closed after serving
+/// [`Config::max_requests_per_conn`] requests.
+///
+/// If a handler upgrades the connection (see [`upgrade`]), the connection is handed over to the
+/// upgrade handler after the `101 Switching Protocols` response is sent and no further requests
+/// are served.
+pub async fn handle_connection<S>(
+    stream: S,
+    peer: &PeerAddr,
+    router: &Router,
+    cfg: &Config,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    let mut reader = RequestReader::new(reader)
+        .with_strict_parsing(cfg.strict_parsing())
+        .with_header_limits(cfg.max_headers(), cfg.max_header_size())
+        .with_request_line_limit(cfg.max_request_line());
+    let mut writer = ResponseWriter::new(writer)
+        .with_timeout(cfg.write_timeout())
+        .with_min_compress_size(cfg.min_compress_size())
+        .with_compress_types(cfg.compress_types())
+        .with_exact_header_case(cfg.preserve_header_case());
 
-    let req = reader.read_request().await.context("read request")?;
+    let mut requests = 0;
 
-    println!("{req:?}");
+    loop {
+        let Ok(request) = timeout(cfg.idle_timeout(), reader.await_request()).await else {
+            break; // client stayed idle for too long
+        };
 
-    // TODO: extract to a router and magic handlers
-    let resp = match req.target.as_ref() {
-        b"/" => Response::from_request(&req).status(StatusCode::OK).build(),
+        if !request.context("await request")? {
+            break; // client closed the connection
+        }
+        // NOTE: the deadline protects against clients trickling the request head byte by byte
+        let head = match timeout(cfg.header_timeout(), reader.read_head()).await {
+            // NOTE: I/O errors mean the client is gone, otherwise it gets to know what was wrong
+            Ok(Err(e)) if !e.is::<std::io::Error>() => {
+                eprintln!("rejecting request: {e:#}");
 
-        b"/user-agent" | b"/user-agent/" => req.headers.get(b"user-agent").map_or_else(
-            || {
-                Response::from_request(&req)
-                    .status(StatusCode::NOT_FOUND)
-                    .build()
-            },
-            |user_agent| {
-                Response::from_request(&req)
-                    .status(StatusCode::OK)
-                    .plain(user_agent)
-            },
-        ),
-
-        url if url.starts_with(b"/files") => {
-            let file = url
-                .strip_prefix(b"/files/")
-                .filter(|f| !f.is_empty())
-                .and_then(|f| std::str::from_utf8(f).map(Path::new).ok())
-                .map(|f| cfg.files_dir().join(f));
-
-            match (req.method, file) {
-                (Method::Get, Some(file)) if file.is_file() => {
-                    Response::from_request(&req)
-                        .status(StatusCode::OK)
-                        .file(file)
-                        .await
+                let status = if e.is::<HeadersTooLarge>() {
+                    StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+                } else if e.is::<UriTooLong>() {
+                    StatusCode::URI_TOO_LONG
+                } else if e.is::<VersionNotSupported>() {
+                    StatusCode::HTTP_VERSION_NOT_SUPPORTED
+                } else {
+                    StatusCode::BAD_REQUEST
+                };
+
+                let resp = Response::closing_with_reason(status, e.root_cause().to_string());
+                return writer.write_response(resp).await.context("write response");
+            }
+            Ok(head) => head.context("read request")?,
+            Err(_) => {
+                let resp = Response::closing(StatusCode::REQUEST_TIMEOUT);
+                return writer.write_response(resp).await.context("write response");
+            }
+        };
+
+        let Some(head) = head else {
+            break;
+        };
+
+        if let Err(e) = head.host() {
+            // the body is left unread, so the connection cannot be reused
+            let req = head.into_request(Body::empty());
+            println!("{req:?}");
+            eprintln!("rejecting request: {e:#}");
+
+            let resp = Response::from_request(&req)
+                .status(StatusCode::BAD_REQUEST)
+                .plain(e.to_string());
+
+            return send_response(&mut writer, resp, &req.method, &req.version, false).await;
+        }
+
+        // NOTE: ambiguous framing could make the server and a proxy in front of it disagree on
+        //  where the request ends, which would allow smuggling requests (see RFC 9112, section 6.3)
+        let framing = head.headers.content_length().and_then(|len| {
+            let transfer_coded = head.headers.get(TRANSFER_ENCODING).is_some();
+            anyhow::ensure!(
+                !transfer_coded || len.is_none(),
+                "both Content-Length and Transfer-Encoding"
+            );
+            anyhow::ensure!(
+                !transfer_coded || head.version != Request::HTTP_1_0,
+                "Transfer-Encoding in HTTP/1.0 request"
+            );
+            Ok(())
+        });
+
+        if let Err(e) = framing {
+            // the end of the body is unknown, so the connection cannot be reused
+            let req = head.into_request(Body::empty());
+            println!("{req:?}");
+            eprintln!("rejecting request: {e:#}");
+
+            let resp = Response::from_request(&req)
+                .status(StatusCode::BAD_REQUEST)
+                .plain(e.to_string());
+
+            return send_response(&mut writer, resp, &req.method, &req.version, false).await;
+        }
+
+        if cfg
+            .max_body_size()
+            .is_some_and(|max| head.content_length() as u64 > max)
+        {
+            // the body is left unread, so the connection cannot be reused for further requests
+            let req = head.into_request(Body::empty());
+            println!("{req:?}");
+
+            let resp = Response::from_request(&req)
+                .status(StatusCode::CONTENT_TOO_LARGE)
+                .build();
+
+            return send_response(&mut writer, resp, &req.method, &req.version, false).await;
+        }
+
+        // NOTE: request bodies are decoded by the same programs which encode responses
+        let codings = match encoding::request_codings(&head.headers, &Config::encodings()) {
+            Ok(codings) => codings,
+            Err(e) => {
+                // the body is left unread, so the connection cannot be reused either
+                let req = head.into_request(Body::empty());
+                println!("{req:?}");
+                eprintln!("rejecting request body: {e:#}");
+
+                let resp = Response::from_request(&req)
+                    .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                    .header(
+                        ACCEPT_ENCODING,
+                        itertools::join(Config::encodings().iter(), ", ").into(),
+                    )
+                    .build();
+
+                return send_response(&mut writer, resp, &req.method, &req.version, false).await;
+            }
+        };
+
+        // NOTE: a transfer-coded body is always chunked, so its length is not known up front
+        let transfer_codings =
+            match encoding::request_transfer_codings(&head.headers, &Config::encodings()) {
+                Ok(codings) => codings,
+                Err(status) => {
+                    // the end of the body is unknown, so the connection cannot be reused
+                    let req = head.into_request(Body::empty());
+                    println!("{req:?}");
+
+                    let resp = Response::from_request(&req).status(status).build();
+
+                    return send_response(&mut writer, resp, &req.method, &req.version, false)
+                        .await;
                 }
+            };
+
+        let chunked = transfer_codings.is_some();
+
+        // NOTE: a body without any framing would be indistinguishable from no body at all
+        if !chunked
+            && matches!(head.method, Method::Post | Method::Put)
+            && head.headers.get(CONTENT_LENGTH).is_none()
+        {
+            // any body the client sends anyway could be mistaken for next request
+            let req = head.into_request(Body::empty());
+            println!("{req:?}");
+
+            let resp = Response::from_request(&req)
+                .status(StatusCode::LENGTH_REQUIRED)
+                .build();
 
-                (Method::Get, _) => Response::from_request(&req)
-                    .status(StatusCode::NOT_FOUND)
-                    .build(),
+            return send_response(&mut writer, resp, &req.method, &req.version, false).await;
+        }
+
+        // NOTE: the client refuses unencoded responses, but none of the codings it accepts is
+        //  available (see RFC 9110, section 12.5.3)
+        let refused = head
+            .headers
+            .extract::<AcceptEncoding>()
+            .is_some_and(|accepted| {
+                !accepted.accepts_identity()
+                    && accepted
+                        .select(|e| Config::encodings().contains(e) && encoding::is_available(*e))
+                        .is_none()
+            });
+
+        if refused {
+            // the body is left unread, so the connection cannot be reused either
+            let req = head.into_request(Body::empty());
+            println!("{req:?}");
 
-                (Method::Post, Some(file)) => upload_file(file, req).await,
+            let resp = Response::from_request(&req)
+                .status(StatusCode::NOT_ACCEPTABLE)
+                .build();
+
+            return send_response(&mut writer, resp, &req.method, &req.version, false).await;
+        }
 
-                (Method::Post, None) => Response::from_request(&req)
-                    .status(StatusCode::BAD_REQUEST)
-                    .build(),
+        // NOTE: expectations of HTTP/1.0 clients are ignored, since these don't understand interim
+        //  responses (see RFC 9110, section 10.1.1)
+        if head.version == Request::HTTP_1_1 && head.headers.get(EXPECT).is_some() {
+            if !head.headers.contains_token(EXPECT, CONTINUE) {
+                // the client holds the body back until we respond, so it's never read and the
+                // connection cannot be reused for further requests
+                let req = head.into_request(Body::empty());
+                println!("{req:?}");
+
+                let resp = Response::from_request(&req)
+                    .status(StatusCode::EXPECTATION_FAILED)
+                    .build();
+
+                return send_response(&mut writer, resp, &req.method, &req.version, false).await;
+            }
+
+            if chunked || head.content_length() > 0 {
+                writer
+                    .write_interim(StatusCode::CONTINUE, head.version.clone())
+                    .await
+                    .context("write interim response")?;
             }
         }
 
-        url if url.starts_with(b"/echo") => {
-            let msg = url.strip_prefix(b"/echo/").unwrap_or_default();
+        let len = head.content_length();
 
-            Response::from_request(&req)
-                .status(StatusCode::OK)
-                .plain(msg)
+        // NOTE: large (or chunked) bodies are streamed to the handler as they arrive instead of
+        //  being buffered
+        let (mut req, upload) = if chunked || len > MAX_BUFFERED_BODY {
+            let (tx, body) = Body::channel(UPLOAD_CAPACITY);
+            (head.into_request(body), Some(tx))
+        } else {
+            match timeout(cfg.body_timeout(), reader.read_body(head)).await {
+                // NOTE: the client may have just shut down its side of the connection
+                Ok(Err(e)) if invalid_body(&e).is_some() => {
+                    eprintln!("rejecting request: {e:#}");
+                    let (status, reason) = invalid_body(&e).unwrap_or_default();
+                    let resp = Response::closing_with_reason(status, reason);
+                    return writer.write_response(resp).await.context("write response");
+                }
+                Ok(req) => (req.context("read request")?, None),
+                Err(_) => {
+                    let resp = Response::closing(StatusCode::REQUEST_TIMEOUT);
+                    return writer.write_response(resp).await.context("write response");
+                }
+            }
+        };
+
+        // NOTE: codings are removed in the reverse order in which they have been applied, transfer
+        //  codings being the last ones
+        let transfer_codings = transfer_codings.unwrap_or_default();
+        for encoding in transfer_codings.iter().rev().chain(codings.iter().rev()) {
+            let body = std::mem::replace(&mut req.body, Body::empty());
+            req.body = encoding
+                .decompress(body)
+                .await
+                .context("decompress request body")?;
         }
 
-        _ => Response::from_request(&req)
-            .status(StatusCode::NOT_FOUND)
-            .build(),
-    };
+        if chunked {
+            // the transfer codings are specific to the connection and thus already consumed
+            req.headers = req.headers.remove(TRANSFER_ENCODING).remove(CONTENT_LENGTH);
+        }
 
-    println!("{resp:?}");
+        if !codings.is_empty() {
+            // the length of the decoded body is not known in advance
+            req.headers = req.headers.remove(CONTENT_ENCODING).remove(CONTENT_LENGTH);
+        }
+
+        req.peer = peer.ip();
+        req.client_ip = req
+            .peer
+            .map(|ip| net::client_ip(ip, &req.headers, cfg.trusted_proxies()));
+
+        println!("{req:?}");
+
+        requests += 1;
+
+        let keep_alive = req.keep_alive()
+            && !matches!(cfg.max_requests_per_conn(), Some(max) if requests >= max);
+        let version = req.version.clone();
+        let method = req.method.clone();
+
+        let mut resp = match upload {
+            None => router.handle(req).await,
+            Some(tx) => {
+                let reader = &mut reader;
+
+                // NOTE: the sender is dropped once the whole body is read, which ends the body
+                let read = async move {
+                    let stream = async {
+                        if chunked {
+                            reader.stream_chunked_body(cfg.max_body_size(), &tx).await
+                        } else {
+                            reader.stream_body(len, &tx).await
+                        }
+                    };
+                    let read = timeout(cfg.body_timeout(), stream).await;
+                    if read.is_err() {
+                        let _ = tx.abort(ErrorKind::TimedOut.into()).await;
+                    }
+                    read
+                };
+
+                let (resp, read) = tokio::join!(router.handle(req), read);
+
+                match read {
+                    // NOTE: the rest of an invalid body can't be told apart from next request
+                    Ok(Err(e)) if invalid_body(&e).is_some() => {
+                        eprintln!("rejecting request: {e:#}");
+                        let (status, reason) = invalid_body(&e).unwrap_or_default();
+                        let resp = Response::closing_with_reason(status, reason);
+                        return writer.write_response(resp).await.context("write response");
+                    }
+                    Ok(read) => {
+                        read.context("read request")?;
+                        resp
+                    }
+                    Err(_) => {
+                        let resp = Response::closing(StatusCode::REQUEST_TIMEOUT);
+                        return writer.write_response(resp).await.context("write response");
+                    }
+                }
+            }
+        };
+
+        // streamed bodies cannot be chunked for HTTP/1.0 clients, so the end of the body is
+        // signalled by closing the connection instead
+        let keep_alive = keep_alive && (version == Request::HTTP_1_1 || resp.body.is_sized());
+
+        let on_upgrade = match resp.upgrade.take() {
+            Some(on_upgrade) if resp.status == StatusCode::SWITCHING_PROTOCOLS => Some(on_upgrade),
+            _ => None,
+        };
 
-    writer.write_response(resp).await.context("write response")
+        // NOTE: data following the body which does not start with a request line (e.g., the body
+        //  was longer than declared) is discarded along with the connection instead of being
+        //  parsed as a request
+        let overrun = on_upgrade.is_none() && reader.has_unexpected_data();
+        if overrun {
+            eprintln!("discarding unexpected data after the request");
+        }
+
+        let keep_alive = keep_alive && !overrun;
+
+        send_response(&mut writer, resp, &method, &version, keep_alive).await?;
+
+        if let Some(on_upgrade) = on_upgrade {
+            let io = Upgraded::new(reader.into_inner(), writer.into_inner());
+            on_upgrade.call(io).await;
+            break;
+        }
+
+        if !keep_alive {
+            break;
+        }
+    }
+
+    Ok(())
 }
 
-async fn upload_file(file: PathBuf, req: Request) -> Response {
-    let resp = Response::from_request(&req);
+/// Status and reason of a failed read of a request body if it's the client's fault, i.e. the body
+/// is either invalid (e.g., malformed chunks), too large or incomplete, rather than the connection
+/// failed otherwise
+fn invalid_body(error: &anyhow::Error) -> Option<(StatusCode, String)> {
+    let io_error = error.downcast_ref::<std::io::Error>()?;
 
-    let file = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(file)
-        .await;
+    if io_error.get_ref().is_some_and(|e| e.is::<BodyTooLarge>()) {
+        return Some((
+            StatusCode::CONTENT_TOO_LARGE,
+            error.root_cause().to_string(),
+        ));
+    }
 
-    let mut file = match file {
-        Ok(file) => FileWriter::new(file),
-        Err(_) => return resp.status(StatusCode::INTERNAL_SERVER_ERROR).empty(),
+    let reason = match io_error.kind() {
+        ErrorKind::InvalidData => error.root_cause().to_string(),
+        ErrorKind::UnexpectedEof => "incomplete request body".to_string(),
+        _ => return None,
     };
 
-    let bytes_read = req.body.len();
+    Some((StatusCode::BAD_REQUEST, reason))
+}
 
-    // TODO: stream body from the request based on Content-Type (i.e., don't materialize in memory)
-    let Ok(bytes_written) = file.write(req.body).await else {
-        return resp.status(StatusCode::INTERNAL_SERVER_ERROR).empty();
-    };
+async fn send_response<W>(
+    writer: &mut ResponseWriter<W>,
+    mut resp: Response,
+    method: &Method,
+    version: &Bytes,
+    keep_alive: bool,
+) -> Result<()>
+where
+    W: tokio::io::AsyncWriteExt + Send + Unpin,
+{
+    if resp.status == StatusCode::SWITCHING_PROTOCOLS {
+        // the connection is taken over by the upgrade, so its persistence does not apply
+    } else if !keep_alive {
+        resp.headers = resp.headers.insert(Connection::close());
+    } else if *version != Request::HTTP_1_1 {
+        // HTTP/1.0 clients must be told explicitly that the connection persists
+        resp.headers = resp.headers.insert(Connection::keep_alive());
+    }
 
-    debug_assert_eq!(bytes_read, bytes_written, "corrupted file upload");
+    println!("{resp:?}");
 
-    resp.status(StatusCode::CREATED).build()
+    if method == &Method::Head {
+        writer.write_response_head(resp).await
+    } else {
+        writer.write_response(resp).await
+    }
+    .context("write response")
 }
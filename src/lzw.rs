@@ -0,0 +1,478 @@
+//! LZW coding in the format of the `compress` program (see RFC 9110, section 8.4.1.1)
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+/// Magic bytes which start the encoded data
+const MAGIC: [u8; 2] = [0x1f, 0x9d];
+
+/// Flag of the third header byte, which allows the use of [`CLEAR`] codes
+const BLOCK_MODE: u8 = 0x80;
+
+/// Mask of the third header byte with the maximal width of codes
+const MAX_BITS_MASK: u8 = 0x1f;
+
+/// Code which resets the dictionary (in the block mode)
+const CLEAR: u32 = 256;
+
+/// Width of codes at the start (and after each [`CLEAR`])
+const INIT_BITS: u32 = 9;
+
+/// Maximal width of codes produced by [`Encoder`] (also the limit of the `compress` program)
+const MAX_BITS: u32 = 16;
+
+/// Codes are written in groups of this size, a group is padded whenever the width changes
+const GROUP: u32 = 8;
+
+/// The largest code of given width before the width has to grow
+#[inline]
+fn max_code(bits: u32, max_bits: u32) -> u32 {
+    if bits >= max_bits {
+        1 << max_bits
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// Number of codes which complete the current group after given number of codes
+#[inline]
+fn padding(codes: u32) -> u32 {
+    (GROUP - codes % GROUP) % GROUP
+}
+
+#[inline]
+fn invalid(msg: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid compressed data: {msg}"),
+    )
+}
+
+/// Incremental LZW encoder.
+///
+/// Note that the dictionary is never reset, so the codes just stop adapting to the data once it's
+/// full.
+#[derive(Debug)]
+pub(crate) struct Encoder {
+    /// Codes of known strings indexed by the code of their prefix and the last byte
+    dict: HashMap<(u32, u8), u32>,
+    /// Code of the longest known string matching the end of the input so far
+    prefix: Option<u32>,
+    /// Next free code
+    next: u32,
+    bits: u32,
+    /// Number of codes written with the current width
+    written: u32,
+    acc: u64,
+    acc_bits: u32,
+    header: bool,
+}
+
+impl Encoder {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self {
+            dict: HashMap::new(),
+            prefix: None,
+            next: CLEAR + 1,
+            bits: INIT_BITS,
+            written: 0,
+            acc: 0,
+            acc_bits: 0,
+            header: false,
+        }
+    }
+
+    /// Encode next part of the input, appending the output to `out`
+    pub(crate) fn update(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        self.write_header(out);
+
+        for &byte in input {
+            let Some(prefix) = self.prefix else {
+                self.prefix = Some(byte.into());
+                continue;
+            };
+
+            if let Some(&code) = self.dict.get(&(prefix, byte)) {
+                self.prefix = Some(code);
+                continue;
+            }
+
+            self.write(prefix, out);
+
+            if self.next < 1 << MAX_BITS {
+                self.dict.insert((prefix, byte), self.next);
+                self.next += 1;
+            }
+
+            self.prefix = Some(byte.into());
+        }
+    }
+
+    /// Encode the rest of the input and flush the output into `out`
+    pub(crate) fn finish(mut self, out: &mut Vec<u8>) {
+        self.write_header(out);
+
+        if let Some(prefix) = self.prefix.take() {
+            self.write(prefix, out);
+        }
+
+        if self.acc_bits > 0 {
+            out.push(self.acc as u8);
+        }
+    }
+
+    #[inline]
+    fn write_header(&mut self, out: &mut Vec<u8>) {
+        if !self.header {
+            out.extend_from_slice(&MAGIC);
+            out.push(BLOCK_MODE | MAX_BITS as u8);
+            self.header = true;
+        }
+    }
+
+    fn write(&mut self, code: u32, out: &mut Vec<u8>) {
+        self.push_bits(code, out);
+        self.written += 1;
+
+        // NOTE: the next code would not fit, so the rest of the group is padded with zeros
+        if self.next > max_code(self.bits, MAX_BITS) {
+            for _ in 0..padding(self.written) {
+                self.push_bits(0, out);
+            }
+            self.bits += 1;
+            self.written = 0;
+        }
+    }
+
+    #[inline]
+    fn push_bits(&mut self, code: u32, out: &mut Vec<u8>) {
+        self.acc |= u64::from(code) << self.acc_bits;
+        self.acc_bits += self.bits;
+        while self.acc_bits >= 8 {
+            out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.acc_bits -= 8;
+        }
+    }
+}
+
+impl Default for Encoder {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Header of the encoded data
+#[derive(Debug)]
+struct Header {
+    max_bits: u32,
+    block_mode: bool,
+}
+
+/// Incremental LZW decoder (of the output of both [`Encoder`] and the `compress` program)
+#[derive(Debug, Default)]
+pub(crate) struct Decoder {
+    /// Bytes of an incomplete header
+    partial: Vec<u8>,
+    header: Option<Header>,
+    /// Codes of the prefixes of strings in the dictionary indexed by their codes
+    prefixes: Vec<u32>,
+    /// Last bytes of strings in the dictionary indexed by their codes
+    suffixes: Vec<u8>,
+    /// Previous code and the first byte of its string
+    prev: Option<(u32, u8)>,
+    /// Next free code
+    next: u32,
+    bits: u32,
+    /// Number of codes read with the current width
+    read: u32,
+    acc: u64,
+    acc_bits: u32,
+    /// Number of padding bits to skip before the next code
+    skip: u32,
+    /// String of the current code in the reverse order
+    stack: Vec<u8>,
+}
+
+impl Decoder {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode next part of the input, appending the output to `out`.
+    ///
+    /// Fails if the input is not a valid LZW encoding.
+    pub(crate) fn update(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        if self.header.is_none() {
+            let n = input.len().min(3 - self.partial.len());
+            self.partial.extend_from_slice(&input[..n]);
+            input = &input[n..];
+
+            if self.partial.len() < 3 {
+                return Ok(());
+            }
+
+            self.start()?;
+        }
+
+        for &byte in input {
+            self.acc |= u64::from(byte) << self.acc_bits;
+            self.acc_bits += 8;
+
+            if self.skip > 0 {
+                let skip = self.skip.min(self.acc_bits);
+                self.acc >>= skip;
+                self.acc_bits -= skip;
+                self.skip -= skip;
+            }
+
+            while self.skip == 0 && self.acc_bits >= self.bits {
+                let code = (self.acc & ((1 << self.bits) - 1)) as u32;
+                self.acc >>= self.bits;
+                self.acc_bits -= self.bits;
+                self.read += 1;
+                self.decode(code, out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that the whole input has been decoded.
+    ///
+    /// Fails if the input ended before the header was complete.
+    pub(crate) fn finish(self) -> Result<()> {
+        if self.header.is_none() {
+            return Err(invalid("missing header"));
+        }
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if self.partial[..2] != MAGIC {
+            return Err(invalid("unknown format"));
+        }
+
+        let max_bits = u32::from(self.partial[2] & MAX_BITS_MASK);
+        if !(INIT_BITS..=MAX_BITS).contains(&max_bits) {
+            return Err(invalid("unsupported code width"));
+        }
+
+        let block_mode = self.partial[2] & BLOCK_MODE != 0;
+
+        self.prefixes = vec![0; 1 << max_bits];
+        self.suffixes = (0..1 << max_bits).map(|code| code as u8).collect();
+        self.next = if block_mode { CLEAR + 1 } else { CLEAR };
+        self.bits = INIT_BITS;
+        self.header = Some(Header {
+            max_bits,
+            block_mode,
+        });
+
+        Ok(())
+    }
+
+    fn decode(&mut self, code: u32, out: &mut Vec<u8>) -> Result<()> {
+        let Some(Header {
+            max_bits,
+            block_mode,
+        }) = self.header
+        else {
+            unreachable!("codes are decoded after the header");
+        };
+
+        if block_mode && code == CLEAR {
+            self.prev = None;
+            self.next = CLEAR + 1;
+            self.pad();
+            self.bits = INIT_BITS;
+            return Ok(());
+        }
+
+        let Some((prev, prev_first)) = self.prev else {
+            if code >= CLEAR {
+                return Err(invalid("first code is not a literal"));
+            }
+            out.push(code as u8);
+            self.prev = Some((code, code as u8));
+            return Ok(());
+        };
+
+        self.stack.clear();
+
+        let mut current = match code.cmp(&self.next) {
+            std::cmp::Ordering::Less => code,
+            // NOTE: the string is not yet in the dictionary, but it must be the previous string
+            //  followed by its first byte
+            std::cmp::Ordering::Equal => {
+                self.stack.push(prev_first);
+                prev
+            }
+            std::cmp::Ordering::Greater => return Err(invalid("unknown code")),
+        };
+
+        while current >= CLEAR {
+            self.stack.push(self.suffixes[current as usize]);
+            current = self.prefixes[current as usize];
+        }
+
+        let first = current as u8;
+        self.stack.push(first);
+        out.extend(self.stack.iter().rev());
+
+        if self.next < 1 << max_bits {
+            self.prefixes[self.next as usize] = prev;
+            self.suffixes[self.next as usize] = first;
+            self.next += 1;
+        }
+
+        self.prev = Some((code, first));
+
+        if self.next > max_code(self.bits, max_bits) {
+            self.pad();
+            self.bits += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Skip the rest of the current group of codes
+    #[inline]
+    fn pad(&mut self) {
+        self.skip = padding(self.read) * self.bits;
+        self.read = 0;
+
+        let skip = self.skip.min(self.acc_bits);
+        self.acc >>= skip;
+        self.acc_bits -= skip;
+        self.skip -= skip;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode given input in chunks of given size
+    fn encode(input: &[u8], chunk: usize) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        let mut out = Vec::new();
+        for part in input.chunks(chunk) {
+            encoder.update(part, &mut out);
+        }
+        encoder.finish(&mut out);
+        out
+    }
+
+    /// Decode given input in chunks of given size
+    fn decode(input: &[u8], chunk: usize) -> Result<Vec<u8>> {
+        let mut decoder = Decoder::new();
+        let mut out = Vec::new();
+        for part in input.chunks(chunk) {
+            decoder.update(part, &mut out)?;
+        }
+        decoder.finish()?;
+        Ok(out)
+    }
+
+    /// Pseudo-random bytes (xorshift), which are hardly compressible
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// Pack codes of given width the way `compress` does (the least significant bits first)
+    fn pack(codes: &[u32], bits: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (mut acc, mut acc_bits) = (0u64, 0);
+        for &code in codes {
+            acc |= u64::from(code) << acc_bits;
+            acc_bits += bits;
+            while acc_bits >= 8 {
+                out.push(acc as u8);
+                acc >>= 8;
+                acc_bits -= 8;
+            }
+        }
+        if acc_bits > 0 {
+            out.push(acc as u8);
+        }
+        out
+    }
+
+    #[test]
+    fn round_trip_empty() {
+        let encoded = encode(b"", 1);
+        assert_eq!(encoded, [MAGIC[0], MAGIC[1], BLOCK_MODE | MAX_BITS as u8]);
+        assert_eq!(decode(&encoded, 1).unwrap(), b"");
+    }
+
+    #[test]
+    fn round_trip_single_byte() {
+        let encoded = encode(b"x", 1);
+        assert_eq!(decode(&encoded, 1).unwrap(), b"x");
+    }
+
+    #[test]
+    fn round_trip_repetitive() {
+        // NOTE: runs of a single byte produce codes which are not yet in the decoder's dictionary
+        let mut input = b"a".repeat(100_000);
+        input.extend_from_slice(&b"abracadabra ".repeat(10_000));
+
+        let encoded = encode(&input, 4096);
+        assert!(encoded.len() < input.len() / 10);
+        assert_eq!(decode(&encoded, 1).unwrap(), input);
+    }
+
+    #[test]
+    fn round_trip_full_dictionary() {
+        let input = noise(1 << 20);
+
+        let mut encoder = Encoder::new();
+        let mut encoded = Vec::new();
+        encoder.update(&input, &mut encoded);
+
+        // all the code widths up to the maximal one have been used and the dictionary is full
+        assert_eq!(encoder.bits, MAX_BITS);
+        assert_eq!(encoder.next, 1 << MAX_BITS);
+
+        encoder.finish(&mut encoded);
+
+        assert_eq!(decode(&encoded, 7).unwrap(), input);
+        assert_eq!(decode(&encoded, encoded.len()).unwrap(), input);
+    }
+
+    #[test]
+    fn decode_clear() {
+        // NOTE: like `compress`, the group of codes is padded after CLEAR and the codes which
+        //  follow it are decoded with a fresh dictionary (i.e., 257 is "cd" and not "ab")
+        let mut codes = vec![u32::from(b'a'), u32::from(b'b'), CLEAR];
+        codes.extend([0; 5]);
+        codes.extend([u32::from(b'c'), u32::from(b'd'), 257]);
+
+        let mut input = vec![MAGIC[0], MAGIC[1], BLOCK_MODE | MAX_BITS as u8];
+        input.extend(pack(&codes, INIT_BITS));
+
+        assert_eq!(decode(&input, 1).unwrap(), b"abcdcd");
+        assert_eq!(decode(&input, input.len()).unwrap(), b"abcdcd");
+    }
+
+    #[test]
+    fn decode_invalid() {
+        assert!(decode(b"\x1f\x8b\x08", 3).is_err());
+        assert!(decode(&[MAGIC[0], MAGIC[1]], 2).is_err());
+
+        let mut input = vec![MAGIC[0], MAGIC[1], BLOCK_MODE | MAX_BITS as u8];
+        input.extend(pack(&[u32::from(b'a'), 300], INIT_BITS));
+        assert!(decode(&input, input.len()).is_err());
+    }
+}
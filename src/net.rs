@@ -0,0 +1,161 @@
+//! Client address resolution behind reverse proxies
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::{bail, Context as _, Result};
+use bytes::Bytes;
+
+use crate::header::{trim_whitespace, HeaderMap, FORWARDED, X_FORWARDED_FOR};
+
+/// Block of IP addresses given in CIDR notation (e.g., `10.0.0.0/8` or `::1/128`).
+///
+/// A plain IP address denotes a block with just that address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Returns `true` iff `ip` belongs to this block
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // NOTE: IPv4 clients may show up as IPv4-mapped addresses on dual-stack sockets
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(u32::from(net).into(), u32::from(ip).into(), 32, self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(u128::from(net), u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_eq(net: u128, ip: u128, bits: u8, prefix: u8) -> bool {
+    let shift = bits - prefix;
+    shift >= bits || net >> shift == ip >> shift
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr = addr
+            .parse::<IpAddr>()
+            .with_context(|| format!("invalid IP address '{addr}'"))?;
+
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .with_context(|| format!("invalid prefix length '{prefix}'"))?,
+            None => bits,
+        };
+
+        if prefix > bits {
+            bail!("prefix length {prefix} is out of range for {addr}");
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Resolve the address of the client which originated the request.
+///
+/// Unless the `peer` is one of the `trusted` proxies, it's the client. Otherwise the chain of
+/// proxies in `Forwarded` (or `X-Forwarded-For` if there's none) is walked from the nearest hop
+/// and the first address which is not trusted is the client. If the chain ends with an address
+/// which is unknown or obfuscated, the last known hop is returned instead.
+pub(crate) fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[Cidr]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    let hops = match headers.get(FORWARDED) {
+        Some(forwarded) => forwarded_for(forwarded),
+        None => match headers.get(X_FORWARDED_FOR) {
+            Some(forwarded) => x_forwarded_for(forwarded),
+            None => return peer,
+        },
+    };
+
+    let mut client = peer;
+
+    for hop in hops.into_iter().rev() {
+        let Some(hop) = hop else {
+            break;
+        };
+
+        client = hop;
+
+        if !is_trusted(hop) {
+            break;
+        }
+    }
+
+    client
+}
+
+/// Addresses in the `X-Forwarded-For` list (`None` if an entry is not a valid address)
+fn x_forwarded_for(value: Bytes) -> Vec<Option<IpAddr>> {
+    value
+        .split(|&b| b == b',')
+        .map(trim_whitespace)
+        .map(|hop| std::str::from_utf8(hop).ok().and_then(parse_node))
+        .collect()
+}
+
+/// Addresses of the `for` parameters of `Forwarded` elements (see RFC 7239, section 4)
+fn forwarded_for(value: Bytes) -> Vec<Option<IpAddr>> {
+    value
+        .split(|&b| b == b',')
+        .map(|element| {
+            element
+                .split(|&b| b == b';')
+                .map(trim_whitespace)
+                .find_map(|pair| {
+                    let (name, node) = pair.split_at(pair.iter().position(|&b| b == b'=')?);
+                    name.eq_ignore_ascii_case(b"for").then_some(&node[1..])
+                })
+                .and_then(|node| std::str::from_utf8(node).ok())
+                .and_then(|node| parse_node(node.trim_matches('"')))
+        })
+        .collect()
+}
+
+/// Parse node identifier which is an IP address optionally with a port (IPv6 in brackets)
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|ip| ip.parse::<Ipv6Addr>().ok())
+        .map(IpAddr::V6)
+}
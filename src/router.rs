@@ -0,0 +1,701 @@
+//! Request routing based on path patterns and methods.
+//!
+//! Path patterns consist of `/`-separated segments, each of which is either
+//!  - static text which must match exactly (e.g., `/user-agent`)
+//!  - a named parameter `{name}` matching any single segment
+//!  - a catch-all parameter `{*name}` (only as the last segment) matching the rest of the path
+//!    including any `/` (possibly nothing)
+use std::future::Future;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::extract::FromRequest;
+use crate::files;
+use crate::header::{percent_decode, ALLOW, LOCATION};
+use crate::{IntoResponse, Method, Request, Response, StatusCode};
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Captured path parameters (name, value) in the order of appearance in the pattern
+pub(crate) type Params = Vec<(Arc<str>, Bytes)>;
+
+/// Async function which takes [`FromRequest`] extractors as arguments and returns anything that
+/// implements [`IntoResponse`].
+pub trait Handler<Args>: Clone + Send + Sync + 'static {
+    fn call(&self, req: Request) -> BoxFuture<Response>;
+}
+
+macro_rules! handler {
+    ($($ty:ident $arg:ident),*) => {
+        impl<F, Fut, R, $($ty,)*> Handler<($($ty,)*)> for F
+        where
+            F: Fn($($ty),*) -> Fut + Clone + Send + Sync + 'static,
+            Fut: Future<Output = R> + Send + 'static,
+            R: IntoResponse,
+            $($ty: FromRequest + Send + 'static,)*
+        {
+            #[allow(unused_mut, unused_variables)]
+            fn call(&self, mut req: Request) -> BoxFuture<Response> {
+                let handler = self.clone();
+                Box::pin(async move {
+                    $(
+                        let $arg = match $ty::from_request(&mut req).await {
+                            Ok(value) => value,
+                            Err(rejection) => {
+                                return rejection.into_response(Response::from_request(&req));
+                            }
+                        };
+                    )*
+                    let resp = handler($($arg),*).await;
+                    resp.into_response(Response::from_request(&req))
+                })
+            }
+        }
+    };
+}
+
+handler!();
+handler!(A a);
+handler!(A a, B b);
+handler!(A a, B b, C c);
+handler!(A a, B b, C c, D d);
+handler!(A a, B b, C c, D d, E e);
+
+/// Object-safe version of [`Handler`] with erased argument types
+trait ErasedHandler: Send + Sync {
+    fn call(&self, req: Request) -> BoxFuture<Response>;
+}
+
+struct HandlerFn<H, Args> {
+    handler: H,
+    _args: PhantomData<fn() -> Args>,
+}
+
+impl<H, Args> ErasedHandler for HandlerFn<H, Args>
+where
+    H: Handler<Args>,
+    Args: 'static,
+{
+    #[inline]
+    fn call(&self, req: Request) -> BoxFuture<Response> {
+        self.handler.call(req)
+    }
+}
+
+type BoxHandler = Arc<dyn ErasedHandler>;
+
+#[inline]
+fn boxed<H, Args>(handler: H) -> BoxHandler
+where
+    H: Handler<Args>,
+    Args: 'static,
+{
+    Arc::new(HandlerFn {
+        handler,
+        _args: PhantomData,
+    })
+}
+
+/// Dispatches requests for a single path to handlers based on the request method.
+///
+/// `HEAD` requests are served by the `GET` handler unless there's a dedicated one, and `OPTIONS`
+/// requests are answered with the `Allow` set of registered methods.
+#[derive(Clone, Default)]
+pub struct MethodRouter {
+    handlers: Vec<(Method, BoxHandler)>,
+}
+
+macro_rules! method_router {
+    ($(($method:ident, $name:ident)),+) => {
+        $(
+            #[inline]
+            pub fn $name<H, Args>(handler: H) -> MethodRouter
+            where
+                H: Handler<Args>,
+                Args: 'static,
+            {
+                MethodRouter::default().on(Method::$method, handler)
+            }
+        )+
+
+        impl MethodRouter {
+            $(
+                #[inline]
+                pub fn $name<H, Args>(self, handler: H) -> Self
+                where
+                    H: Handler<Args>,
+                    Args: 'static,
+                {
+                    self.on(Method::$method, handler)
+                }
+            )+
+        }
+    };
+}
+
+method_router! {
+    (Get, get),
+    (Head, head),
+    (Post, post),
+    (Put, put),
+    (Delete, delete),
+    (Patch, patch)
+}
+
+/// Route requests with given `method` to `handler`
+#[inline]
+pub fn on<H, Args>(method: Method, handler: H) -> MethodRouter
+where
+    H: Handler<Args>,
+    Args: 'static,
+{
+    MethodRouter::default().on(method, handler)
+}
+
+impl MethodRouter {
+    pub fn on<H, Args>(mut self, method: Method, handler: H) -> Self
+    where
+        H: Handler<Args>,
+        Args: 'static,
+    {
+        let handler = boxed(handler);
+        match self.handlers.iter_mut().find(|(m, _)| *m == method) {
+            Some((_, h)) => *h = handler,
+            None => self.handlers.push((method, handler)),
+        }
+        self
+    }
+
+    fn find(&self, method: &Method) -> Option<&BoxHandler> {
+        let handler = self.handlers.iter().find(|(m, _)| m == method);
+
+        match (handler, method) {
+            (Some((_, handler)), _) => Some(handler),
+            (None, Method::Head) => self.find(&Method::Get),
+            (None, _) => None,
+        }
+    }
+
+    /// All the methods this router supports, including the implicit `HEAD` and `OPTIONS`
+    fn methods(&self) -> Vec<&Method> {
+        let mut methods = self.handlers.iter().map(|(m, _)| m).collect::<Vec<_>>();
+
+        let get = self.find(&Method::Get).is_some();
+        if get && self.handlers.iter().all(|(m, _)| *m != Method::Head) {
+            methods.push(&Method::Head);
+        }
+        methods.push(&Method::Options);
+
+        methods
+    }
+
+    /// Value of the `Allow` header listing all the methods this router supports
+    #[inline]
+    pub fn allow(&self) -> Bytes {
+        allow(self.methods())
+    }
+
+    pub async fn call(&self, req: Request) -> Response {
+        if let Some(handler) = self.find(&req.method) {
+            return handler.call(req).await;
+        }
+
+        let status = if req.method == Method::Options {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::METHOD_NOT_ALLOWED
+        };
+
+        Response::from_request(&req)
+            .status(status)
+            .header(ALLOW, self.allow())
+            .build()
+    }
+}
+
+/// Value of the `Allow` header listing given methods
+fn allow(methods: Vec<&Method>) -> Bytes {
+    let methods = methods.into_iter().map(Method::as_bytes);
+
+    let mut allow = BytesMut::with_capacity(64);
+    for method in itertools::intersperse(methods, b", ") {
+        allow.extend_from_slice(method);
+    }
+
+    allow.freeze()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Static(Box<str>),
+    Param(Arc<str>),
+    CatchAll(Arc<str>),
+}
+
+#[derive(Clone, Debug)]
+struct Pattern(Vec<Segment>);
+
+impl Pattern {
+    /// Parse a path pattern, panics on malformed patterns (these are programming errors)
+    fn parse(pattern: &str) -> Self {
+        assert!(
+            pattern.starts_with('/'),
+            "path pattern must start with '/': '{pattern}'"
+        );
+
+        let segments = split_path(pattern.as_bytes())
+            .map(|segment| {
+                let segment = std::str::from_utf8(segment).expect("pattern is a str");
+                match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                    Some(name) => match name.strip_prefix('*') {
+                        Some(name) => Segment::CatchAll(Arc::from(name)),
+                        None => Segment::Param(Arc::from(name)),
+                    },
+                    None => Segment::Static(Box::from(segment)),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let catch_all = segments
+            .iter()
+            .position(|s| matches!(s, Segment::CatchAll(_)));
+
+        if let Some(pos) = catch_all {
+            assert_eq!(
+                pos + 1,
+                segments.len(),
+                "catch-all parameter must be the last segment: '{pattern}'"
+            );
+        }
+
+        Self(segments)
+    }
+
+    /// Match given `path` against this pattern and return captured parameters if it matches
+    fn matches(&self, path: &Bytes) -> Option<Params> {
+        let mut params = Params::new();
+
+        // NOTE: offset of the remaining (unmatched) part of the path excluding the leading '/'
+        let mut offset = 1.min(path.len());
+        let mut segments = split_path(path);
+
+        for pattern in self.0.iter() {
+            match pattern {
+                Segment::CatchAll(name) => {
+                    params.push((Arc::clone(name), path.slice(offset.min(path.len())..)));
+                    return Some(params);
+                }
+
+                Segment::Static(expected) => {
+                    let segment = segments.next()?;
+                    if segment != expected.as_bytes() {
+                        return None;
+                    }
+                    offset += segment.len() + 1;
+                }
+
+                Segment::Param(name) => {
+                    let segment = segments.next()?;
+                    params.push((Arc::clone(name), path.slice(offset..offset + segment.len())));
+                    offset += segment.len() + 1;
+                }
+            }
+        }
+
+        if segments.next().is_some() {
+            return None;
+        }
+
+        Some(params)
+    }
+}
+
+/// Decode percent-encoded characters in an absolute path and remove its `.`, `..` and empty
+/// segments (see RFC 3986, section 5.2.4), so that routes and files can't be reached by
+/// alternative spellings of their paths (e.g., `/files/%2e%2e/secret`).
+///
+/// Encoded slashes are kept encoded, so that these don't split segments, as well as encoded `%`,
+/// so that decoded segments can't be confused with encoded ones (e.g., `%252F` with `%2F`), and
+/// control characters. Returns `None` if the path is not absolute or contains an encoded NUL
+/// character.
+pub(crate) fn normalize_path(path: &[u8]) -> Option<Bytes> {
+    let path = path.strip_prefix(b"/")?;
+
+    let mut segments = Vec::new();
+    let mut dir = false;
+
+    for segment in path.split(|&b| b == b'/') {
+        let segment = percent_decode(segment);
+
+        if segment.contains(&0) {
+            return None;
+        }
+
+        // NOTE: paths ending with a dot segment or a slash refer to a directory
+        dir = true;
+
+        match segment.as_slice() {
+            b"" | b"." => {}
+            b".." => {
+                segments.pop();
+            }
+            _ => {
+                dir = false;
+                // NOTE: the segment itself contains no slashes, so these must have been encoded
+                let segment = segment.into_iter().fold(Vec::new(), |mut segment, b| {
+                    if b == b'/' || b == b'%' || b.is_ascii_control() {
+                        segment.extend_from_slice(format!("%{b:02X}").as_bytes());
+                    } else {
+                        segment.push(b);
+                    }
+                    segment
+                });
+                segments.push(segment);
+            }
+        }
+    }
+
+    let mut normalized = BytesMut::with_capacity(path.len() + 1);
+    for segment in segments.iter() {
+        normalized.extend_from_slice(b"/");
+        normalized.extend_from_slice(segment);
+    }
+
+    if normalized.is_empty() || dir {
+        normalized.extend_from_slice(b"/");
+    }
+
+    Some(normalized.freeze())
+}
+
+/// Percent-encode characters of a normalized `path` which may not appear in a URI as they are
+/// (see RFC 3986, section 3.3), keeping the escapes left by [`normalize_path`]
+fn encode_path(path: &[u8]) -> Bytes {
+    let mut encoded = BytesMut::with_capacity(path.len());
+
+    for &b in path {
+        if b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@/%".contains(&b) {
+            encoded.extend_from_slice(&[b]);
+        } else {
+            encoded.extend_from_slice(format!("%{b:02X}").as_bytes());
+        }
+    }
+
+    encoded.freeze()
+}
+
+/// The path with its trailing `/` removed or with one added (`None` for `/` itself)
+fn toggle_trailing_slash(path: &[u8]) -> Option<Bytes> {
+    match path {
+        b"/" => None,
+        [rest @ .., b'/'] => Some(Bytes::copy_from_slice(rest)),
+        _ => Some(Bytes::from([path, b"/"].concat())),
+    }
+}
+
+/// Split path into segments, skipping the leading `/` (i.e., `/` itself has no segments)
+fn split_path(path: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let path = path.strip_prefix(b"/").unwrap_or(path);
+    let empty = path.is_empty();
+    path.split(|&b| b == b'/').filter(move |_| !empty)
+}
+
+#[derive(Clone)]
+enum Route {
+    Handler(Pattern, MethodRouter),
+    /// Sub-router mounted under a static path prefix (without trailing `/`)
+    Nested(Box<[u8]>, Router),
+}
+
+enum Match<'a> {
+    Route(&'a MethodRouter, Params),
+    Fallback(&'a BoxHandler),
+}
+
+/// How [`Router`] treats request paths which match a route only after adding or removing a
+/// trailing `/` (e.g., `/user-agent/` for route `/user-agent`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Paths must match routes exactly
+    #[default]
+    Strict,
+    /// Paths are routed as if these were spelled as the route
+    Ignore,
+    /// Paths are permanently redirected to the spelling of the route (with `301 Moved
+    /// Permanently` for `GET` and `HEAD`, `308 Permanent Redirect` otherwise)
+    Redirect,
+}
+
+impl FromStr for TrailingSlash {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "strict" => Ok(Self::Strict),
+            "ignore" => Ok(Self::Ignore),
+            "redirect" => Ok(Self::Redirect),
+            _ => anyhow::bail!("unknown trailing slash policy '{policy}'"),
+        }
+    }
+}
+
+/// Routes requests to handlers based on the request path and method.
+///
+/// Routes are tried in the order in which they were registered and the first matching one wins.
+/// Requests that don't match any route are passed to the fallback handler (if registered) or
+/// answered with an empty `404 Not Found`.
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<BoxHandler>,
+    trailing_slash: TrailingSlash,
+}
+
+impl Router {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register handlers for requests with paths matching given `pattern`
+    pub fn route(mut self, pattern: &str, methods: MethodRouter) -> Self {
+        self.routes
+            .push(Route::Handler(Pattern::parse(pattern), methods));
+        self
+    }
+
+    /// Mount given `router` under a path `prefix`.
+    ///
+    /// The prefix is stripped from the request path before matching the routes of the nested
+    /// router, so e.g. a request to `/api/users` is matched against `/users` in a router nested
+    /// under `/api`.
+    pub fn nest(mut self, prefix: &str, router: Router) -> Self {
+        assert!(
+            prefix.starts_with('/'),
+            "path prefix must start with '/': '{prefix}'"
+        );
+
+        let prefix = prefix.trim_end_matches('/').as_bytes();
+        self.routes.push(Route::Nested(Box::from(prefix), router));
+        self
+    }
+
+    /// Serve files from directory `dir` on the local file system for `GET` requests to paths
+    /// under given `prefix`.
+    #[inline]
+    pub fn mount_static(self, prefix: &str, dir: impl Into<PathBuf>) -> Self {
+        self.mount_dir(prefix, dir, Default::default())
+    }
+
+    /// Like [`Self::mount_static`] but with custom options of serving the files
+    pub(crate) fn mount_dir(
+        self,
+        prefix: &str,
+        dir: impl Into<PathBuf>,
+        opts: files::ServeOptions,
+    ) -> Self {
+        let files = Router::new().route("/{*file}", files::serve_dir(dir, opts));
+        self.nest(prefix, files)
+    }
+
+    /// Set how paths differing from routes just in a trailing `/` are handled (strict by default).
+    ///
+    /// The policy applies uniformly to all the routes, including those of nested routers.
+    #[inline]
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Register a handler for requests that don't match any route (regardless of the method).
+    ///
+    /// Fallback of a nested router takes precedence for requests under its prefix.
+    pub fn fallback<H, Args>(mut self, handler: H) -> Self
+    where
+        H: Handler<Args>,
+        Args: 'static,
+    {
+        self.fallback = Some(boxed(handler));
+        self
+    }
+
+    /// Methods supported by any of the routes (see [`MethodRouter::allow`])
+    fn methods(&self) -> Vec<&Method> {
+        let mut methods = Vec::new();
+
+        for route in self.routes.iter() {
+            let supported = match route {
+                Route::Handler(_, router) => router.methods(),
+                Route::Nested(_, router) => router.methods(),
+            };
+
+            for method in supported {
+                if !methods.contains(&method) {
+                    methods.push(method);
+                }
+            }
+        }
+
+        if !methods.contains(&&Method::Options) {
+            methods.push(&Method::Options);
+        }
+
+        methods
+    }
+
+    /// Find the handlers of the first route matching given `path` or the fallback handler
+    fn find(&self, path: Bytes) -> Option<Match<'_>> {
+        let found = self.routes.iter().find_map(|route| match route {
+            Route::Handler(pattern, methods) => pattern
+                .matches(&path)
+                .map(|params| Match::Route(methods, params)),
+
+            Route::Nested(prefix, router) => {
+                let rest = path.strip_prefix(prefix.as_ref())?;
+
+                match rest.first() {
+                    None => router.find(Bytes::from_static(b"/")),
+                    Some(b'/') => router.find(path.slice(prefix.len()..)),
+                    Some(_) => None,
+                }
+            }
+        });
+
+        found.or_else(|| self.fallback.as_ref().map(Match::Fallback))
+    }
+
+    pub async fn handle(&self, mut req: Request) -> Response {
+        if let Method::Extension(_) = req.method {
+            return Response::from_request(&req)
+                .status(StatusCode::NOT_IMPLEMENTED)
+                .build();
+        }
+
+        // NOTE: the asterisk-form targets the server as a whole rather than any particular
+        //  resource and is only valid for OPTIONS (see RFC 9112, section 3.2.4)
+        if req.target.as_ref() == b"*" {
+            let resp = Response::from_request(&req);
+            return if req.method == Method::Options {
+                resp.status(StatusCode::NO_CONTENT)
+                    .header(ALLOW, allow(self.methods()))
+                    .build()
+            } else {
+                resp.status(StatusCode::BAD_REQUEST).build()
+            };
+        }
+
+        let Some(path) = normalize_path(&req.path()) else {
+            return Response::from_request(&req)
+                .status(StatusCode::BAD_REQUEST)
+                .plain(Bytes::from_static(b"invalid request target"));
+        };
+
+        // NOTE: the target is kept as sent, so that the decoded path can't alter its query
+        req.path = Some(path.clone());
+
+        let mut found = self.find(path.clone());
+
+        // NOTE: the path can be respelled only if it does not match any route as it is
+        if self.trailing_slash != TrailingSlash::Strict && !matches!(found, Some(Match::Route(..)))
+        {
+            if let Some(alt) = toggle_trailing_slash(&path) {
+                if let Some(Match::Route(methods, params)) = self.find(alt.clone()) {
+                    if self.trailing_slash == TrailingSlash::Redirect {
+                        let location = encode_path(&alt);
+                        let location = match req.query() {
+                            Some(query) => Bytes::from([&location[..], b"?", query].concat()),
+                            None => location,
+                        };
+
+                        let status = if matches!(req.method, Method::Get | Method::Head) {
+                            StatusCode::MOVED_PERMANENTLY
+                        } else {
+                            StatusCode::PERMANENT_REDIRECT
+                        };
+                        return Response::from_request(&req)
+                            .status(status)
+                            .header(LOCATION, location)
+                            .build();
+                    }
+
+                    req.path = Some(alt);
+                    found = Some(Match::Route(methods, params));
+                }
+            }
+        }
+
+        match found {
+            Some(Match::Route(methods, params)) => {
+                req.params = params;
+                methods.call(req).await
+            }
+
+            Some(Match::Fallback(handler)) => handler.call(req).await,
+
+            None => Response::from_request(&req)
+                .status(StatusCode::NOT_FOUND)
+                .build(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(path: &str) -> Option<String> {
+        normalize_path(path.as_bytes()).map(|path| String::from_utf8_lossy(&path).into_owned())
+    }
+
+    #[test]
+    fn normalize_dot_segments() {
+        assert_eq!(normalize("/").as_deref(), Some("/"));
+        assert_eq!(normalize("/a/./b/../c").as_deref(), Some("/a/c"));
+        assert_eq!(normalize("/a//b").as_deref(), Some("/a/b"));
+        assert_eq!(normalize("/../../a").as_deref(), Some("/a"));
+        assert_eq!(
+            normalize("/files/%2e%2E/secret").as_deref(),
+            Some("/secret")
+        );
+    }
+
+    #[test]
+    fn normalize_directories() {
+        assert_eq!(normalize("/a/").as_deref(), Some("/a/"));
+        assert_eq!(normalize("/a/.").as_deref(), Some("/a/"));
+        assert_eq!(normalize("/a/b/..").as_deref(), Some("/a/"));
+        assert_eq!(normalize("/a/..").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn normalize_escapes() {
+        assert_eq!(normalize("/echo/%61%62c").as_deref(), Some("/echo/abc"));
+        assert_eq!(normalize("/echo/a%20b").as_deref(), Some("/echo/a b"));
+
+        // NOTE: slashes, `%` and control characters stay encoded (in upper case)
+        assert_eq!(normalize("/echo/a%2fb").as_deref(), Some("/echo/a%2Fb"));
+        assert_eq!(normalize("/echo/100%25").as_deref(), Some("/echo/100%25"));
+        assert_eq!(normalize("/echo/%252F").as_deref(), Some("/echo/%252F"));
+        assert_eq!(normalize("/echo/a%0Ab").as_deref(), Some("/echo/a%0Ab"));
+
+        // NOTE: a `%` which does not start a valid escape is encoded itself
+        assert_eq!(normalize("/echo/%zz").as_deref(), Some("/echo/%25zz"));
+    }
+
+    #[test]
+    fn normalize_invalid() {
+        assert_eq!(normalize("relative/path"), None);
+        assert_eq!(normalize("*"), None);
+        assert_eq!(normalize("/a%00b"), None);
+    }
+
+    #[test]
+    fn encode_normalized_path() {
+        assert_eq!(encode_path(b"/a b/%2F/\xc3\xa9"), "/a%20b/%2F/%C3%A9");
+    }
+}
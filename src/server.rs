@@ -0,0 +1,340 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{FromRawFd as _, IntoRawFd as _};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context as _, Result};
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{timeout, timeout_at, Instant};
+
+use crate::config::{self, ListenAddr};
+use crate::encoding;
+use crate::files;
+use crate::header::{self, RETRY_AFTER};
+use crate::io::ResponseWriter;
+use crate::{handle_connection, Config, Response, Router, StatusCode};
+
+/// HTTP server accepting connections on a TCP or Unix socket and serving them with a [`Router`].
+#[derive(Debug)]
+pub struct Server {
+    addr: ListenAddr,
+    cfg: Config,
+}
+
+/// Address of a connected client
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    /// Path of the client's Unix socket (usually unnamed)
+    Unix(Option<PathBuf>),
+}
+
+impl PeerAddr {
+    /// IP address of the client (`None` for Unix sockets)
+    #[inline]
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Self::Tcp(addr) => Some(addr.ip()),
+            Self::Unix(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            Self::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            Self::Unix(None) => f.write_str("unix:<unnamed>"),
+        }
+    }
+}
+
+/// Source of client connections
+pub trait Listener: Send {
+    type Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static;
+
+    /// Accept next client connection and return it along with the client's address
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Stream, PeerAddr)>> + Send;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    async fn accept(&self) -> io::Result<(Self::Stream, PeerAddr)> {
+        let (stream, addr) = TcpListener::accept(self).await?;
+
+        if let Err(e) = stream.set_nodelay(true) {
+            eprintln!("failed to enable TCP_NODELAY on connection: {e:?}");
+        }
+
+        Ok((stream, PeerAddr::Tcp(addr)))
+    }
+}
+
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    async fn accept(&self) -> io::Result<(Self::Stream, PeerAddr)> {
+        let (stream, addr) = UnixListener::accept(self).await?;
+        let addr = addr.as_pathname().map(PathBuf::from);
+        Ok((stream, PeerAddr::Unix(addr)))
+    }
+}
+
+impl Server {
+    /// Create a server which will listen on given address once started with [`Self::serve`]
+    #[inline]
+    pub fn bind(addr: impl Into<ListenAddr>) -> Self {
+        Self {
+            addr: addr.into(),
+            cfg: Config::default(),
+        }
+    }
+
+    /// Use given configuration (note that the listen address set by [`Self::bind`] is kept)
+    #[inline]
+    pub fn with_config(mut self, cfg: Config) -> Self {
+        self.cfg = cfg;
+        self
+    }
+
+    #[inline]
+    pub fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    /// Bind the listen address and serve incoming connections with given router.
+    ///
+    /// Each connection is handled in a separate task. Errors of individual connections are
+    /// logged and do not stop the server. Returns only if the server fails to start.
+    ///
+    /// If [`Config::max_connections`] is set, connections over the limit wait at most
+    /// [`Config::queue_timeout`] for a free slot and are rejected with `503 Service Unavailable`
+    /// afterwards.
+    pub async fn serve(self, router: Router) -> Result<()> {
+        println!("starting server at {}", self.addr);
+
+        match &self.addr {
+            ListenAddr::Tcp(addr) => {
+                let listener = TcpListener::bind(addr).await.context("bind TCP listener")?;
+
+                self.serve_with(listener, router).await
+            }
+
+            ListenAddr::Unix(path) => {
+                // remove stale socket left behind by a previous run
+                match std::fs::remove_file(path) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e).context("remove existing Unix socket"),
+                }
+
+                let listener = UnixListener::bind(path).context("bind Unix listener")?;
+
+                self.serve_with(listener, router).await
+            }
+
+            ListenAddr::Inherited(fd) => {
+                // SAFETY: the descriptor has been passed to this process to own and is used once
+                let listener = unsafe { std::net::TcpListener::from_raw_fd(*fd) };
+
+                // NOTE: getsockname fails for non-IP sockets, so this must be a Unix socket
+                if listener.local_addr().is_ok() {
+                    listener.set_nonblocking(true).context("inherited socket")?;
+                    let listener = TcpListener::from_std(listener).context("inherited socket")?;
+                    return self.serve_with(listener, router).await;
+                }
+
+                // SAFETY: ownership has been released by the TCP listener above
+                let fd = listener.into_raw_fd();
+                let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+
+                listener.set_nonblocking(true).context("inherited socket")?;
+                let listener = UnixListener::from_std(listener).context("inherited socket")?;
+
+                self.serve_with(listener, router).await
+            }
+        }
+    }
+
+    /// Serve connections accepted by an already bound `listener` with given router
+    pub async fn serve_with<L: Listener>(self, listener: L, router: Router) -> Result<()> {
+        let router = Arc::new(router);
+        let cfg = Arc::new(self.cfg);
+
+        header::init_charset(cfg.charset());
+        encoding::init_levels(cfg.compression_levels());
+        encoding::init_cache(cfg.compression_cache_size());
+        files::init_cache(cfg.file_cache_size());
+        files::init_stat_cache(cfg.stat_cache_ttl());
+        encoding::init_temp_file_size(cfg.compression_temp_file_size());
+        config::set_encodings(cfg.enabled_encodings());
+        encoding::init_prespawned(cfg.prespawned_encoders(), &Config::encodings());
+
+        tokio::spawn(redetect_encodings(Arc::clone(&cfg)));
+        tokio::spawn(header::run_date_clock());
+
+        // NOTE: connections which cannot be admitted right away wait in a bounded queue, so that
+        //  these don't hold up accepting others (see [`admit_queued`])
+        let limit = cfg.max_connections().map(|max| {
+            let permits = Arc::new(Semaphore::new(max));
+            let (queue, queued) = mpsc::channel(max);
+
+            tokio::spawn(admit_queued(
+                queued,
+                Arc::clone(&permits),
+                Arc::clone(&router),
+                Arc::clone(&cfg),
+            ));
+
+            (permits, queue)
+        });
+
+        let queue_timeout = cfg.queue_timeout();
+
+        println!("server is ready to accept connections");
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(client) => client,
+                Err(error) => {
+                    eprintln!("cannot get client: {error}");
+                    continue;
+                }
+            };
+
+            let Some((ref permits, ref queue)) = limit else {
+                spawn_connection(stream, addr, None, &router, &cfg);
+                continue;
+            };
+
+            // NOTE: the permit is acquired before spawning a task for the connection, so that no
+            //  more than the limit of tasks serve connections
+            if let Ok(permit) = Arc::clone(permits).try_acquire_owned() {
+                spawn_connection(stream, addr, Some(permit), &router, &cfg);
+                continue;
+            }
+
+            let deadline = Instant::now() + queue_timeout;
+
+            if let Err(error) = queue.try_send((stream, addr, deadline)) {
+                let (stream, addr, _) = match error {
+                    TrySendError::Full(queued) | TrySendError::Closed(queued) => queued,
+                };
+                spawn_shed(stream, addr, cfg.write_timeout());
+            }
+        }
+    }
+}
+
+/// Admit queued connections in order as connection permits become available, rejecting those
+/// which have not got one before their deadline (see [`Config::queue_timeout`])
+async fn admit_queued<S>(
+    mut queued: mpsc::Receiver<(S, PeerAddr, Instant)>,
+    permits: Arc<Semaphore>,
+    router: Arc<Router>,
+    cfg: Arc<Config>,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    while let Some((stream, addr, deadline)) = queued.recv().await {
+        match timeout_at(deadline, Arc::clone(&permits).acquire_owned()).await {
+            Ok(Ok(permit)) => spawn_connection(stream, addr, Some(permit), &router, &cfg),
+            _ => spawn_shed(stream, addr, cfg.write_timeout()),
+        }
+    }
+}
+
+/// Serve the connection in a new task, which holds given connection `permit` until it's closed
+fn spawn_connection<S>(
+    stream: S,
+    addr: PeerAddr,
+    permit: Option<OwnedSemaphorePermit>,
+    router: &Arc<Router>,
+    cfg: &Arc<Config>,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let router = Arc::clone(router);
+    let cfg = Arc::clone(cfg);
+
+    tokio::spawn(async move {
+        let _permit = permit;
+
+        if let Err(error) = handle_connection(stream, &addr, &router, &cfg).await {
+            eprintln!("connection {addr} failed with {error}");
+        }
+    });
+}
+
+/// Reject the connection (see [`shed`]) in a new task, so that a slow client does not hold up
+/// accepting others
+fn spawn_shed<S>(stream: S, addr: PeerAddr, write_timeout: Duration)
+where
+    S: AsyncWrite + Send + Unpin + 'static,
+{
+    eprintln!("connection {addr} rejected: too many connections");
+
+    tokio::spawn(async move {
+        match timeout(write_timeout, shed(stream)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => eprintln!("connection {addr} failed with {error}"),
+            Err(_) => eprintln!("connection {addr} failed with write timeout"),
+        }
+    });
+}
+
+/// Reject the connection with `503 Service Unavailable` without reading the request
+async fn shed<S>(stream: S) -> Result<()>
+where
+    S: AsyncWrite + Send + Unpin,
+{
+    let mut writer = ResponseWriter::new(stream);
+
+    let mut resp = Response::closing(StatusCode::SERVICE_UNAVAILABLE);
+    resp.headers = resp.headers.assoc(RETRY_AFTER, Bytes::from_static(b"1"));
+
+    writer.write_response(resp).await
+}
+
+/// Detect available encodings again whenever the process receives `SIGHUP`, so that installed
+/// or removed compression programs are picked up without restarting the server
+async fn redetect_encodings(cfg: Arc<Config>) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            eprintln!("cannot listen for SIGHUP: {error}");
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        let detected = tokio::task::spawn_blocking(encoding::get_supported)
+            .await
+            .context("detection task")
+            .and_then(|detected| detected);
+
+        let detected = match detected {
+            Ok(detected) => detected,
+            Err(error) => {
+                eprintln!("failed to detect encodings: {error:#}");
+                continue;
+            }
+        };
+
+        let encodings = cfg.select_encodings(&detected);
+        println!("supported encodings: {}", itertools::join(&encodings, ", "));
+
+        config::set_encodings(encodings);
+        encoding::reset_unavailable();
+        encoding::respawn_prespawned(&Config::encodings());
+    }
+}
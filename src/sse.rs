@@ -0,0 +1,150 @@
+//! Server-Sent Events (see the HTML Living Standard, section 9.2).
+//!
+//! A handler creates an event stream with [`Sse::channel`], hands the [`EventSender`] over to a
+//! task that produces events and returns the [`Sse`] as the response. Events are sent to the
+//! client as soon as they're produced and the stream ends once all the senders are dropped.
+use std::time::Duration;
+
+use bytes::{BufMut as _, Bytes, BytesMut};
+use tokio::sync::mpsc;
+
+use crate::body::{Body, BodySender};
+use crate::header::{MimeType, CACHE_CONTROL, NO_CACHE};
+use crate::{IntoResponse, Response, ResponseBuilder};
+
+/// Number of events buffered before the producer has to wait for the client
+const CAPACITY: usize = 16;
+
+/// Single event of an event stream
+#[derive(Clone, Debug, Default)]
+pub struct Event {
+    buf: BytesMut,
+}
+
+impl Event {
+    /// Set the event type (i.e., the `event` field)
+    #[inline]
+    pub fn event(mut self, event: &str) -> Self {
+        self.field(b"event", event);
+        self
+    }
+
+    /// Set the event ID (i.e., the `id` field)
+    #[inline]
+    pub fn id(mut self, id: &str) -> Self {
+        self.field(b"id", id);
+        self
+    }
+
+    /// Set the reconnection time of the client
+    #[inline]
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.field(b"retry", &retry.as_millis().to_string());
+        self
+    }
+
+    /// Append event data, multi-line data is split into multiple `data` fields
+    pub fn data(mut self, data: &str) -> Self {
+        for line in data.split('\n') {
+            self.field(b"data", line.strip_suffix('\r').unwrap_or(line));
+        }
+        self
+    }
+
+    /// Append a comment line which is ignored by the client
+    #[inline]
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.field(b"", comment);
+        self
+    }
+
+    fn field(&mut self, name: &[u8], value: &str) {
+        // NOTE: line breaks would start a new field (or even event), so these are dropped
+        let value = value.replace(['\r', '\n'], "");
+
+        self.buf.put_slice(name);
+        self.buf.put_slice(b": ");
+        self.buf.put_slice(value.as_bytes());
+        self.buf.put_u8(b'\n');
+    }
+
+    fn finish(mut self) -> Bytes {
+        self.buf.put_u8(b'\n');
+        self.buf.freeze()
+    }
+}
+
+/// Producing half of an event stream
+#[derive(Clone, Debug)]
+#[repr(transparent)]
+pub struct EventSender(mpsc::Sender<Event>);
+
+impl EventSender {
+    /// Send an event to the client.
+    ///
+    /// Fails if the client disconnected.
+    #[inline]
+    pub async fn send(&self, event: Event) -> Result<(), Event> {
+        self.0.send(event).await.map_err(|e| e.0)
+    }
+}
+
+/// Streaming `text/event-stream` response
+#[derive(Debug)]
+pub struct Sse {
+    events: mpsc::Receiver<Event>,
+    heartbeat: Option<Duration>,
+}
+
+impl Sse {
+    /// Create an event stream along with a sender of its events
+    pub fn channel() -> (EventSender, Self) {
+        let (tx, rx) = mpsc::channel(CAPACITY);
+        let sse = Self {
+            events: rx,
+            heartbeat: None,
+        };
+        (EventSender(tx), sse)
+    }
+
+    /// Send a comment to the client whenever no event has been sent for given `interval`.
+    ///
+    /// This prevents intermediaries from closing the connection and detects disconnected clients.
+    #[inline]
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
+        self
+    }
+
+    async fn forward(mut self, body: BodySender) {
+        loop {
+            let event = match self.heartbeat {
+                Some(interval) => match tokio::time::timeout(interval, self.events.recv()).await {
+                    Ok(event) => event,
+                    Err(_) => Some(Event::default().comment("heartbeat")),
+                },
+                None => self.events.recv().await,
+            };
+
+            let Some(event) = event else {
+                break;
+            };
+
+            if body.send(event.finish()).await.is_err() {
+                break; // client disconnected
+            }
+        }
+    }
+}
+
+/// Streamed `200 OK` response with `Content-Type: text/event-stream`
+impl IntoResponse for Sse {
+    fn into_response(self, resp: ResponseBuilder) -> Response {
+        let (tx, body) = Body::channel(CAPACITY);
+
+        tokio::spawn(self.forward(tx));
+
+        resp.header(CACHE_CONTROL, NO_CACHE)
+            .with_content_type(MimeType::EventStream, body)
+    }
+}
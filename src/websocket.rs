@@ -0,0 +1,465 @@
+//! WebSocket connections (RFC 6455).
+//!
+//! A handler accepts the opening handshake with the [`WebSocketUpgrade`] extractor and responds
+//! with [`WebSocketUpgrade::on_upgrade`], which takes over the connection once the `101 Switching
+//! Protocols` response is sent (see the `/ws/echo` endpoint for an example).
+//!
+//! Extensions (e.g., `permessage-deflate`) and sub-protocols are not supported.
+use std::future::Future;
+
+use anyhow::{bail, Context as _, Result};
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+use crate::extract::{FromRequest, Rejection};
+use crate::header::{Connection, HeaderMap, UPGRADE, WEBSOCKET};
+use crate::upgrade::{OnUpgrade, Upgraded};
+use crate::{Method, Request, Response, StatusCode};
+
+const SEC_WEBSOCKET_KEY: Bytes = Bytes::from_static(b"Sec-WebSocket-Key");
+const SEC_WEBSOCKET_ACCEPT: Bytes = Bytes::from_static(b"Sec-WebSocket-Accept");
+const SEC_WEBSOCKET_VERSION: Bytes = Bytes::from_static(b"Sec-WebSocket-Version");
+
+/// The only protocol version defined by RFC 6455
+const VERSION: &[u8] = b"13";
+
+/// Magic value appended to the client's key when computing `Sec-WebSocket-Accept`
+const GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Upper bound on the size of a (reassembled) message
+const MAX_MESSAGE_SIZE: usize = 16 << 20;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Close status codes (see RFC 6455, section 7.4.1)
+pub mod close_code {
+    pub const NORMAL: u16 = 1000;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const INVALID_DATA: u16 = 1007;
+    pub const TOO_BIG: u16 = 1009;
+}
+
+/// Extractor of the WebSocket opening handshake.
+///
+/// Rejects requests which are not a valid `GET` upgrade to `websocket` over HTTP/1.1.
+#[derive(Debug)]
+pub struct WebSocketUpgrade {
+    key: Bytes,
+}
+
+impl FromRequest for WebSocketUpgrade {
+    async fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        if req.method != Method::Get {
+            return Err(Rejection::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "WebSocket handshake must be a GET request",
+            ));
+        }
+
+        if req.version != Request::HTTP_1_1
+            || !req
+                .headers
+                .extract::<Connection>()
+                .is_some_and(|connection| connection.contains(UPGRADE))
+            || !req.headers.contains_token(UPGRADE, WEBSOCKET)
+        {
+            return Err(Rejection::bad_request("not a WebSocket upgrade request"));
+        }
+
+        if req.headers.get(SEC_WEBSOCKET_VERSION).as_deref() != Some(VERSION) {
+            return Err(Rejection::new(
+                StatusCode::UPGRADE_REQUIRED,
+                "unsupported WebSocket version",
+            ));
+        }
+
+        match req.headers.get(SEC_WEBSOCKET_KEY) {
+            Some(key) if !key.is_empty() => Ok(Self { key }),
+            _ => Err(Rejection::bad_request("missing header 'Sec-WebSocket-Key'")),
+        }
+    }
+}
+
+impl WebSocketUpgrade {
+    /// Accept the handshake and run `callback` with the established connection.
+    ///
+    /// The connection is closed when the callback returns.
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut headers = HeaderMap::builder();
+        headers.assoc(UPGRADE, WEBSOCKET);
+        headers.insert(Connection::upgrade());
+        headers.assoc(SEC_WEBSOCKET_ACCEPT, accept_key(&self.key));
+
+        let on_upgrade = OnUpgrade::new(move |io| callback(WebSocket::new(io)));
+
+        Response::switching_protocols(headers.build(), on_upgrade)
+    }
+}
+
+/// Status code and reason of a close frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong(Bytes),
+    Close(Option<CloseFrame>),
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: BytesMut,
+}
+
+/// Established WebSocket connection
+pub struct WebSocket {
+    io: Upgraded,
+    closed: bool,
+}
+
+impl WebSocket {
+    #[inline]
+    fn new(io: Upgraded) -> Self {
+        Self { io, closed: false }
+    }
+
+    /// Receive next data message from the client.
+    ///
+    /// Pings are answered automatically and are not returned. When the client starts the closing
+    /// handshake, it's answered and a [`Message::Close`] is returned. Returns `None` once the
+    /// connection is closed.
+    pub async fn recv(&mut self) -> Result<Option<Message>> {
+        let mut fragmented: Option<(u8, BytesMut)> = None;
+
+        loop {
+            if self.closed {
+                return Ok(None);
+            }
+
+            let Some(frame) = self.read_frame().await? else {
+                self.closed = true;
+                return Ok(None);
+            };
+
+            match frame.opcode {
+                OP_PING => {
+                    self.write_frame(OP_PONG, &frame.payload).await?;
+                }
+
+                OP_PONG => {}
+
+                OP_CLOSE => {
+                    let close = match parse_close(frame.payload.freeze()) {
+                        Ok(close) => close,
+                        Err(error) => return self.fail(close_code::PROTOCOL_ERROR, error).await,
+                    };
+
+                    let code = close.as_ref().map_or(close_code::NORMAL, |c| c.code);
+                    self.close(code, "").await?;
+
+                    return Ok(Some(Message::Close(close)));
+                }
+
+                OP_TEXT | OP_BINARY if fragmented.is_some() => {
+                    return self
+                        .fail(close_code::PROTOCOL_ERROR, "expected continuation frame")
+                        .await;
+                }
+
+                OP_TEXT | OP_BINARY if !frame.fin => {
+                    fragmented = Some((frame.opcode, frame.payload));
+                }
+
+                OP_TEXT | OP_BINARY => {
+                    return self.message(frame.opcode, frame.payload).await.map(Some);
+                }
+
+                OP_CONTINUATION => {
+                    let Some((opcode, mut payload)) = fragmented.take() else {
+                        return self
+                            .fail(close_code::PROTOCOL_ERROR, "unexpected continuation frame")
+                            .await;
+                    };
+
+                    if payload.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+                        return self.fail(close_code::TOO_BIG, "message too big").await;
+                    }
+
+                    payload.extend_from_slice(&frame.payload);
+
+                    if frame.fin {
+                        return self.message(opcode, payload).await.map(Some);
+                    }
+
+                    fragmented = Some((opcode, payload));
+                }
+
+                opcode => {
+                    return self
+                        .fail(
+                            close_code::PROTOCOL_ERROR,
+                            format!("unknown opcode {opcode:#x}"),
+                        )
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Send a message to the client.
+    ///
+    /// Sending [`Message::Close`] starts (or completes) the closing handshake after which no
+    /// more messages can be sent.
+    pub async fn send(&mut self, msg: Message) -> Result<()> {
+        match msg {
+            // the closing handshake has been already done in recv
+            Message::Close(_) if self.closed => Ok(()),
+            _ if self.closed => bail!("WebSocket connection is closed"),
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()).await,
+            Message::Binary(data) => self.write_frame(OP_BINARY, &data).await,
+            Message::Ping(data) => self.write_frame(OP_PING, &data).await,
+            Message::Pong(data) => self.write_frame(OP_PONG, &data).await,
+            Message::Close(None) => self.close(close_code::NORMAL, "").await,
+            Message::Close(Some(frame)) => self.close(frame.code, &frame.reason).await,
+        }
+    }
+
+    /// Send a close frame with given code and reason
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+
+        let mut payload = Vec::with_capacity(2 + reason.len());
+        payload.extend_from_slice(&code.to_be_bytes());
+        payload.extend_from_slice(reason.as_bytes());
+
+        self.closed = true;
+        self.write_frame(OP_CLOSE, &payload).await
+    }
+
+    async fn message(&mut self, opcode: u8, payload: BytesMut) -> Result<Message> {
+        if opcode == OP_BINARY {
+            return Ok(Message::Binary(payload.freeze()));
+        }
+
+        match String::from_utf8(payload.into()) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => {
+                self.fail(close_code::INVALID_DATA, "text message is not valid UTF-8")
+                    .await
+            }
+        }
+    }
+
+    /// Close the connection with given code due to a client's error and return the error
+    async fn fail<T>(&mut self, code: u16, reason: impl Into<String>) -> Result<T> {
+        let reason = reason.into();
+        self.close(code, &reason).await?;
+        bail!("WebSocket connection failed: {reason}")
+    }
+
+    /// Read next frame, returns `None` if the client disconnected
+    async fn read_frame(&mut self) -> Result<Option<Frame>> {
+        let mut head = [0; 2];
+
+        match self.io.read_exact(&mut head).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e).context("frame header"),
+        }
+
+        let fin = head[0] & 0x80 != 0;
+        let rsv = head[0] & 0x70;
+        let opcode = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+
+        let len = match head[1] & 0x7F {
+            126 => self.io.read_u16().await.context("frame length")? as u64,
+            127 => self.io.read_u64().await.context("frame length")?,
+            len => len as u64,
+        };
+
+        if rsv != 0 {
+            return self
+                .fail(close_code::PROTOCOL_ERROR, "unsupported extension")
+                .await;
+        }
+
+        if !masked {
+            return self
+                .fail(close_code::PROTOCOL_ERROR, "client frames must be masked")
+                .await;
+        }
+
+        if opcode & 0x8 != 0 && (!fin || len > 125) {
+            return self
+                .fail(close_code::PROTOCOL_ERROR, "invalid control frame")
+                .await;
+        }
+
+        if len > MAX_MESSAGE_SIZE as u64 {
+            return self.fail(close_code::TOO_BIG, "message too big").await;
+        }
+
+        let mut mask = [0; 4];
+        self.io.read_exact(&mut mask).await.context("frame mask")?;
+
+        let mut payload = BytesMut::zeroed(len as usize);
+        self.io
+            .read_exact(&mut payload)
+            .await
+            .context("frame payload")?;
+
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+
+    /// Write a single (unmasked) final frame
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut head = Vec::with_capacity(10);
+        head.push(0x80 | opcode);
+
+        match payload.len() {
+            len if len < 126 => head.push(len as u8),
+            len if len <= u16::MAX as usize => {
+                head.push(126);
+                head.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                head.push(127);
+                head.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        self.io.write_all(&head).await.context("frame header")?;
+        self.io.write_all(payload).await.context("frame payload")?;
+        self.io.flush().await.context("flush")
+    }
+}
+
+fn parse_close(payload: Bytes) -> Result<Option<CloseFrame>, &'static str> {
+    match payload.len() {
+        0 => Ok(None),
+        1 => Err("invalid close frame"),
+        _ => {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            let Ok(reason) = String::from_utf8(payload[2..].to_vec()) else {
+                return Err("close reason is not valid UTF-8");
+            };
+            Ok(Some(CloseFrame { code, reason }))
+        }
+    }
+}
+
+/// Compute `Sec-WebSocket-Accept` for given `Sec-WebSocket-Key`
+fn accept_key(key: &[u8]) -> Bytes {
+    let mut data = Vec::with_capacity(key.len() + GUID.len());
+    data.extend_from_slice(key);
+    data.extend_from_slice(GUID);
+    base64(&sha1(&data)).into()
+}
+
+// NOTE: SHA-1 is used just for the handshake (see RFC 6455, section 4.2.2), not for security
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in msg.chunks_exact(64) {
+        let mut w = [0u32; 80];
+
+        for (w, word) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *w = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (out, h) in digest.chunks_exact_mut(4).zip(h) {
+        out.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+pub(crate) fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}